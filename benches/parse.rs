@@ -0,0 +1,80 @@
+//! Parser throughput by thread count.
+//!
+//! `cargo bench --bench parse -- /path/to/nginx_logs`; without a path the sample dataset is
+//! downloaded.
+
+use std::time::Instant;
+
+use grammar::{
+    ingest::{ParallelOptions, ParallelParser, ParsedChunk},
+    input::{self, HttpOptions},
+    nginx::parse_nginx_line,
+};
+
+const SAMPLE: &str = "https://raw.githubusercontent.com/elastic/examples/master/Common%20Data%20Formats/nginx_logs/nginx_logs";
+const ROUNDS: usize = 3;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // cargo passes `--bench`
+    let specs: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with("--"))
+        .collect();
+    let specs = if specs.is_empty() {
+        vec![SAMPLE.to_string()]
+    } else {
+        specs
+    };
+
+    let options = ParallelOptions::default();
+    let mut chunks = Vec::new();
+    for source in input::expand(&specs)? {
+        let mut lines = source.open(&HttpOptions::default()).await?;
+        while let Some(chunk) = lines.next_chunk(options.chunk_bytes).await? {
+            chunks.push(chunk);
+        }
+    }
+    let bytes: usize = chunks.iter().map(|c| c.len() + 1).sum();
+
+    let max = options.threads();
+    let mut threads: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2))
+        .take_while(|&n| n < max)
+        .collect();
+    threads.push(max);
+
+    println!(
+        "{:>7} {:>12} {:>10} {:>8}",
+        "threads", "lines/s", "MiB/s", "speedup"
+    );
+    let mut baseline = None;
+    for n in threads {
+        let mut best = f64::MAX;
+        let mut lines = 0;
+        for _ in 0..ROUNDS {
+            let start = Instant::now();
+            lines = 0;
+            let mut parser = ParallelParser::new(n, parse_nginx_line);
+            let mut emit = |chunk: ParsedChunk<_>| {
+                chunk.for_each(|_, _, _, result| {
+                    result?;
+                    lines += 1;
+                    Ok(())
+                })
+            };
+            for chunk in &chunks {
+                parser.submit(chunk.clone(), &mut emit)?;
+            }
+            parser.finish(&mut emit)?;
+            best = best.min(start.elapsed().as_secs_f64());
+        }
+        let baseline = *baseline.get_or_insert(best);
+        println!(
+            "{n:>7} {:>12.0} {:>10.1} {:>7.2}x",
+            lines as f64 / best,
+            bytes as f64 / best / (1024.0 * 1024.0),
+            baseline / best
+        );
+    }
+    Ok(())
+}
@@ -1,328 +1,346 @@
 use std::{
-    fmt::Debug,
     fs::File,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    str::{self},
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
-use chrono::{DateTime, Utc};
-
-use winnow::{
-    ascii::{digit1, hex_digit1, space1},
-    combinator::{alt, delimited, separated},
-    token::take_until,
-    PResult, Parser,
-};
-//parse
-
-use arrow::{
-    array::{RecordBatch, StringArray, UInt16Array, UInt64Array},
-    datatypes::{DataType, Field, Schema},
+use clap::Parser;
+use grammar::{
+    export::{
+        rolling::resume_checkpoint, PartitionKey, PartitionOptions, PartitionedWriter,
+        RollingWriter, METADATA_PARSE_ERRORS, METADATA_SOURCE,
+    },
+    ingest::{ErrorOptions, LineError, ParallelOptions, ParallelParser, ParsedChunk},
+    input::{self, Checkpoint, FollowOptions, HttpOptions, Source, Tailer},
+    log_format::{LogFormat, LogRecord, Value},
+    nginx::{parse_nginx_line, NginxLog, StatusClass},
+    AppendRecord, ExportOptions, ParquetSink, RecordSink,
 };
 
-use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
-#[derive(Debug, Copy, Clone)]
-enum HttpMethod {
-    Get,
-    Post,
-    Put,
-    Delete,
-    Head,
-    Options,
-    Connect,
-    Trace,
-    Patch,
-}
-#[derive(Debug, Copy, Clone)]
-enum HttpProtocol {
-    HTTP1_0,
-    HTTP1_1,
-    HTTP2_0,
-    HTTP3_0,
+#[derive(Debug, Parser)]
+struct Cli {
+    /// Access logs to import: paths, globs such as `/var/log/nginx/access.log*`, `-` for
+    /// stdin, or URLs. Compressed files are read transparently.
+    #[arg(
+        default_value = "https://raw.githubusercontent.com/elastic/examples/master/Common%20Data%20Formats/nginx_logs/nginx_logs"
+    )]
+    inputs: Vec<String>,
+    /// Parquet file to write, or the dataset directory when partitioning or following
+    /// [default: assets/nginx_log_2.parquet, or assets/nginx_follow/ with --follow].
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Write a Hive-style partitioned dataset keyed on these columns, outermost first.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    partition_by: Vec<PartitionBy>,
+    /// Parse lines with this nginx `log_format` string, or a whole `log_format` directive,
+    /// instead of the built-in combined parser.
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+    /// Parse Apache httpd logs with this `LogFormat` string, `LogFormat` line, or the
+    /// `common`/`combined` nickname.
+    #[arg(long, conflicts_with = "log_format", value_parser = LogFormat::from_apache_config)]
+    apache_format: Option<LogFormat>,
+    #[command(flatten)]
+    http: HttpOptions,
+    #[command(flatten)]
+    follow: FollowOptions,
+    #[command(flatten)]
+    parallel: ParallelOptions,
+    #[command(flatten)]
+    errors: ErrorOptions,
+    #[command(flatten)]
+    partition: PartitionOptions,
+    #[command(flatten)]
+    export: ExportOptions,
 }
-
-#[derive(Debug)]
-#[allow(unused)]
-struct NginxLog {
-    addr: IpAddr,
-    datetime: DateTime<Utc>,
-    method: HttpMethod,
-    url: String,
-    protocol: HttpProtocol,
-    status: u16,
-    body_bytes: u64,
-    referrer: String,
-    ua: String,
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum PartitionBy {
+    /// Day of the request, e.g. `date=2015-05-17`.
+    Date,
+    /// Hour of the request, e.g. `hour=08`.
+    Hour,
+    /// Status class, e.g. `class=4xx`.
+    Class,
+    /// Requested `$host`; only available with `--log-format` or `--apache-format`.
+    Host,
 }
-impl From<HttpProtocol> for String {
-    fn from(p: HttpProtocol) -> String {
-        match p {
-            HttpProtocol::HTTP1_0 => "HTTP/1.0".to_string(),
-            HttpProtocol::HTTP1_1 => "HTTP/1.1".to_string(),
-            HttpProtocol::HTTP2_0 => "HTTP/2.0".to_string(),
-            HttpProtocol::HTTP3_0 => "HTTP/3.0".to_string(),
+impl PartitionBy {
+    fn key(self) -> PartitionKey<NginxLog> {
+        match self {
+            PartitionBy::Date => PartitionKey::new("date", |log: &NginxLog| {
+                log.datetime.format("%Y-%m-%d").to_string()
+            }),
+            PartitionBy::Hour => PartitionKey::new("hour", |log: &NginxLog| {
+                log.datetime.format("%H").to_string()
+            }),
+            PartitionBy::Class => PartitionKey::new("class", |log: &NginxLog| {
+                log.status_class.as_ref().to_string()
+            }),
+            PartitionBy::Host => unreachable!("rejected before building partition keys"),
         }
     }
-}
-impl From<HttpMethod> for String {
-    fn from(p: HttpMethod) -> String {
-        match p {
-            HttpMethod::Get => "GET".to_string(),
-            HttpMethod::Post => "POST".to_string(),
-            HttpMethod::Put => "PUT".to_string(),
-            HttpMethod::Delete => "DELETE".to_string(),
-            HttpMethod::Head => "HEAD".to_string(),
-            HttpMethod::Options => "OPTIONS".to_string(),
-            HttpMethod::Connect => "CONNECT".to_string(),
-            HttpMethod::Trace => "TRACE".to_string(),
-            HttpMethod::Patch => "PATCH".to_string(),
+
+    fn record_key(self, format: &LogFormat) -> anyhow::Result<PartitionKey<LogRecord>> {
+        let column = match self {
+            PartitionBy::Date | PartitionBy::Hour => "datetime",
+            PartitionBy::Class => "status",
+            PartitionBy::Host => "host",
+        };
+        if !format.columns().iter().any(|c| c.name == column) {
+            anyhow::bail!("--partition-by {self:?} needs ${column} in the log format");
         }
+        let key = match self {
+            PartitionBy::Date => {
+                PartitionKey::new("date", |log: &LogRecord| match log.get("datetime") {
+                    Some(Value::Time(t)) => t.format("%Y-%m-%d").to_string(),
+                    _ => String::new(),
+                })
+            }
+            PartitionBy::Hour => {
+                PartitionKey::new("hour", |log: &LogRecord| match log.get("datetime") {
+                    Some(Value::Time(t)) => t.format("%H").to_string(),
+                    _ => String::new(),
+                })
+            }
+            PartitionBy::Class => {
+                PartitionKey::new("class", |log: &LogRecord| match log.get("status") {
+                    Some(Value::UInt(s)) => StatusClass::from(*s as u16).as_ref().to_string(),
+                    _ => String::new(),
+                })
+            }
+            PartitionBy::Host => PartitionKey::new("host", |log: &LogRecord| {
+                log.get("host")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            }),
+        };
+        Ok(key)
     }
 }
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // let s = r#"2a01:7e00::f03c:91ff:fe70:a4cc - - [18/May/2015:16:05:29 +0000] "GET /downloads/product_1 HTTP/1.1" 200 85619205 "-" "Chef Client/12.0.3 (ruby-2.1.4-p265; ohai-8.0.1; x86_64-linux; +http://opscode.com)""#;
-    // let logs=parse_nginx_log(s);
-    // let mut s = r#"2a01:7e00::f03c:91ff:fe70:a4cc"#;
-    // let mut s = r#"2001:4801:7824:102:8bee:6e66:ff10:6aa2"#;
-    // let ip = parse_ip(&mut s);
-    // println!("{:?}", ip);
-    read_nginx_log("https://raw.githubusercontent.com/elastic/examples/master/Common%20Data%20Formats/nginx_logs/nginx_logs").await?;
-    Ok(())
-}
-
-//parse this log
-//93.180.71.3 - - [17/May/2015:08:05:32 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
-#[allow(clippy::useless_asref)]
-fn parse_nginx_log(s: &str) -> PResult<NginxLog> {
-    // println!("++++++++++++++s {}", s);
-    let input = &mut s.as_ref();
-    let ip = parse_ip(input)?;
-    space1(input)?;
-    parse_ignore(input)?;
-    parse_ignore(input)?;
-    let datetime = parse_datetime(input)?;
-    let (method, url, protocol) = parse_http(input)?;
-    let status = parse_status(input)?;
-    let body_bytes = parse_body_bytes(input)?;
-    let referrer = parse_referrer(input)?;
-    let ua = parse_ua(input)?;
-    Ok(NginxLog {
-        addr: ip,
-        datetime,
-        method,
-        url,
-        protocol,
-        status,
-        body_bytes,
-        referrer,
-        ua,
-    })
-}
-
-fn parse_ignore(s: &mut &str) -> PResult<()> {
-    let _ = "-".parse_next(s)?;
-    space1(s)?;
-    Ok(())
-}
-
-fn parse_ip(s: &mut &str) -> PResult<IpAddr> {
-    // parse_ip_v4.map(IpAddr).or(parse_ip_v6.map(IpAddr::V6)).parse_next(s);
-    alt((parse_ip_v4, parse_ip_v6_len)).parse_next(s)
-}
-//parse 54.194.175.38
-//parse 2001:4801:7824:102:8bee:6e66:ff10:6aa2
-fn parse_ip_v4(s: &mut &str) -> PResult<IpAddr> {
-    // println!("v4 {}", s);
-    let ret: Vec<u8> = separated(4, digit1.parse_to::<u8>(), '.').parse_next(s)?;
-    // space1(s)?;
-    Ok(IpAddr::V4(Ipv4Addr::new(ret[0], ret[1], ret[2], ret[3])))
-}
+    let cli = Cli::parse();
+    let sources = input::expand(&cli.inputs)?;
+    if cli.follow.follow {
+        let [Source::File(path)] = sources.as_slice() else {
+            anyhow::bail!("--follow needs exactly one log file");
+        };
+        let output = cli
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("assets/nginx_follow"));
+        if output.is_file() {
+            anyhow::bail!(
+                "--follow writes a directory of parts, {} is a file",
+                output.display()
+            );
+        }
+        if !cli.partition_by.is_empty() {
+            anyhow::bail!("--follow cannot be combined with --partition-by");
+        }
+        if let Some(format) = cli.log_format.or(cli.apache_format) {
+            let format = Arc::new(format);
+            let new_builder = {
+                let format = format.clone();
+                move |capacity| format.new_batch_builder(capacity)
+            };
+            let writer = RollingWriter::try_with_builder(&output, new_builder, cli.export)?;
+            return follow_log(&output, path, writer, &cli.follow, &cli.errors, |line| {
+                format.parse_line(line)
+            })
+            .await;
+        }
+        let writer = RollingWriter::try_new(&output, cli.export)?;
+        return follow_log(
+            &output,
+            path,
+            writer,
+            &cli.follow,
+            &cli.errors,
+            parse_nginx_line,
+        )
+        .await;
+    }
+    let output = cli
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("assets/nginx_log_2.parquet"));
+    if let Some(format) = cli.log_format.or(cli.apache_format) {
+        let format = Arc::new(format);
+        let sink: Box<dyn RecordSink<LogRecord>> = if cli.partition_by.is_empty() {
+            let file = File::create(&output)?;
+            let builder = format.new_batch_builder(cli.export.batch_size);
+            Box::new(ParquetSink::try_with_builder(file, builder, cli.export)?)
+        } else {
+            let keys = cli
+                .partition_by
+                .iter()
+                .map(|p| p.record_key(&format))
+                .collect::<anyhow::Result<_>>()?;
+            let new_builder = {
+                let format = format.clone();
+                move |capacity| format.new_batch_builder(capacity)
+            };
+            Box::new(PartitionedWriter::try_with_builder(
+                &output,
+                keys,
+                new_builder,
+                cli.export,
+                cli.partition,
+            )?)
+        };
+        return read_nginx_log(
+            &sources,
+            sink,
+            &cli.http,
+            &cli.parallel,
+            &cli.errors,
+            move |line| format.parse_line(line),
+        )
+        .await;
+    }
 
-fn parse_ip_v6_len(s: &mut &str) -> PResult<IpAddr> {
-    let ret: Vec<u16> = separated(
-        1..=8,
-        |input: &mut &str| {
-            hex_digit1
-                .try_map(|s| u16::from_str_radix(s, 16))
-                .parse_next(input)
-        },
-        ':',
+    if cli
+        .partition_by
+        .iter()
+        .any(|p| matches!(p, PartitionBy::Host))
+    {
+        anyhow::bail!(
+            "--partition-by host needs a --log-format or --apache-format logging the host"
+        );
+    }
+    let sink: Box<dyn RecordSink<NginxLog>> = if cli.partition_by.is_empty() {
+        let file = File::create(&output)?;
+        Box::new(ParquetSink::try_new(file, cli.export)?)
+    } else {
+        let keys = cli.partition_by.iter().map(|p| p.key()).collect();
+        Box::new(PartitionedWriter::try_new(
+            &output,
+            keys,
+            cli.export,
+            cli.partition,
+        )?)
+    };
+    read_nginx_log(
+        &sources,
+        sink,
+        &cli.http,
+        &cli.parallel,
+        &cli.errors,
+        parse_nginx_line,
     )
-    .parse_next(s)?;
-    // 处理压缩格式，补全缺失的零段
-
-    // println!("{:?}", ret);
-    // space1(s)?;
-    Ok(IpAddr::V6(Ipv6Addr::new(
-        ret[0], ret[1], ret[2], ret[3], ret[4], ret[5], ret[6], ret[7],
-    )))
-}
-
-fn parse_datetime(s: &mut &str) -> PResult<DateTime<Utc>> {
-    let ret = delimited('[', take_until(1.., ']'), ']').parse_next(s)?;
-    space1(s)?;
-    Ok(DateTime::parse_from_str(ret, "%d/%b/%Y:%H:%M:%S %z")
-        .map(|dt| dt.with_timezone(&Utc))
-        .unwrap())
-}
-
-fn parse_http(s: &mut &str) -> PResult<(HttpMethod, String, HttpProtocol)> {
-    let parser = (parse_method, parse_url, parse_protocol);
-    let ret = delimited('"', parser, '"').parse_next(s)?;
-    space1(s)?;
-    Ok(ret)
-}
-
-fn parse_method(s: &mut &str) -> PResult<HttpMethod> {
-    let ret = alt((
-        "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "CONNECT", "TRACE", "PATCH",
-    ))
-    .parse_next(s)?;
-    space1(s)?;
-    Ok(match ret {
-        "GET" => HttpMethod::Get,
-        "POST" => HttpMethod::Post,
-        "PUT" => HttpMethod::Put,
-        "DELETE" => HttpMethod::Delete,
-        "HEAD" => HttpMethod::Head,
-        "OPTIONS" => HttpMethod::Options,
-        "CONNECT" => HttpMethod::Connect,
-        "TRACE" => HttpMethod::Trace,
-        "PATCH" => HttpMethod::Patch,
-        _ => unreachable!(),
-    })
-}
-
-fn parse_url(s: &mut &str) -> PResult<String> {
-    let ret = take_until(1.., ' ').parse_next(s)?;
-    space1(s)?;
-    Ok(ret.to_string())
-}
-
-fn parse_protocol(s: &mut &str) -> PResult<HttpProtocol> {
-    let ret = alt(("HTTP/1.0", "HTTP/1.1", "HTTP/2.0", "HTTP/3.0")).parse_next(s)?;
-    Ok(match ret {
-        "HTTP/1.0" => HttpProtocol::HTTP1_0,
-        "HTTP/1.1" => HttpProtocol::HTTP1_1,
-        "HTTP/2.0" => HttpProtocol::HTTP2_0,
-        "HTTP/3.0" => HttpProtocol::HTTP3_0,
-        _ => unreachable!(),
-    })
-}
-
-fn parse_status(s: &mut &str) -> PResult<u16> {
-    let ret = digit1.parse_to().parse_next(s)?;
-    space1(s)?;
-    Ok(ret)
-}
-
-fn parse_body_bytes(s: &mut &str) -> PResult<u64> {
-    let ret = digit1.parse_to().parse_next(s)?;
-    space1(s)?;
-    Ok(ret)
+    .await
 }
 
-fn parse_referrer(s: &mut &str) -> PResult<String> {
-    let ret = delimited('"', take_until(1.., '"'), '"').parse_next(s)?;
-    space1(s)?;
-    Ok(ret.to_string())
+/// Parses chunks of every source on `parallel` threads, writing records in input order.
+async fn read_nginx_log<T: Send + 'static>(
+    sources: &[Source],
+    mut sink: Box<dyn RecordSink<T>>,
+    http: &HttpOptions,
+    parallel: &ParallelOptions,
+    errors: &ErrorOptions,
+    parse: impl Fn(&str) -> Result<T, LineError> + Send + Sync + 'static,
+) -> anyhow::Result<()> {
+    let mut errors = errors.error_sink()?;
+    let names: Vec<_> = sources.iter().map(Source::to_string).collect();
+    sink.append_metadata(METADATA_SOURCE, &names.join(","));
+    let mut parser = ParallelParser::new(parallel.threads(), parse);
+    let mut emit = |chunk: ParsedChunk<T>| {
+        chunk.for_each(|source, line_no, line, result| {
+            if let Some(log) = errors.handle(source, line_no, line, result)? {
+                sink.write(&log)?;
+            }
+            Ok(())
+        })
+    };
+    for source in sources {
+        let mut lines = source.open(http).await?;
+        lines.on_progress(input::report_progress(source));
+        parser.start_source(&source.to_string());
+        while let Some(chunk) = lines.next_chunk(parallel.chunk_bytes).await? {
+            parser.submit(chunk, &mut emit)?;
+        }
+    }
+    parser.finish(&mut emit)?;
+    let stats = errors.finish()?;
+    sink.append_metadata(METADATA_PARSE_ERRORS, &stats.skipped.to_string());
+    sink.close()?;
+    eprintln!("{stats}");
+    Ok(())
 }
 
-fn parse_ua(s: &mut &str) -> PResult<String> {
-    let ret = delimited('"', take_until(1.., '"'), '"').parse_next(s)?;
-    Ok(ret.to_string())
-}
+/// Parses lines appended to `path` until interrupted, committing a Parquet part whenever a
+/// flush threshold is reached. Each part records the checkpoint it was committed at, so a
+/// crash before the checkpoint file is saved resumes from the part instead.
+async fn follow_log<T: AppendRecord>(
+    dir: &Path,
+    path: &Path,
+    mut writer: RollingWriter<T>,
+    options: &FollowOptions,
+    errors: &ErrorOptions,
+    parse: impl Fn(&str) -> Result<T, LineError>,
+) -> anyhow::Result<()> {
+    let mut errors = errors.error_sink()?;
+    let saved = match &options.checkpoint {
+        Some(file) => Checkpoint::load(file)?,
+        None => None,
+    };
+    let checkpoint = resume_checkpoint(dir, saved)?;
+    let mut tailer = Tailer::resume(path, checkpoint.as_ref())?;
+    let source = path.display().to_string();
+    writer.append_metadata(METADATA_SOURCE, &source);
 
-async fn read_nginx_log(s: &str) -> anyhow::Result<()> {
-    let url = s;
-    let response = reqwest::get(url).await?;
-    let mut data: Vec<NginxLog> = Vec::new();
-    for line in response.text().await?.lines() {
-        // let line = r#"2a01:7e00::f03c:91ff:fe70:a4cc - - [18/May/2015:16:05:29 +0000] "GET /downloads/product_1 HTTP/1.1" 200 85619205 "-" "Chef Client/12.0.3 (ruby-2.1.4-p265; ohai-8.0.1; x86_64-linux; +http://opscode.com)""#;
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                stop.store(true, Ordering::Relaxed);
+            }
+        });
+    }
 
-        println!("{}", line);
-        //println!("+++++++++++++s {}", line);
-        let mut zero = String::new();
-        if line.contains("::") {
-            if let Some((str1, _str2)) = line.split_once(" ") {
-                let ct = 8 - str1.chars().filter(|&c| c == ':').count();
-                println!("ct {}", ct);
-                zero = ":0:".repeat(ct).replace("::", ":");
+    let mut oldest_pending: Option<Instant> = None;
+    while !stop.load(Ordering::Relaxed) {
+        let line = tailer.poll()?;
+        if let Some(line) = &line {
+            // counted in the checkpoint, so numbers carry on across restarts
+            let line_no = tailer.checkpoint().map_or(0, |c| c.line);
+            if let Some(log) = errors.handle(&source, line_no, line, parse(line))? {
+                writer.write(&log)?;
+                oldest_pending.get_or_insert_with(Instant::now);
             }
         }
-        // println!("zero {}", zero);
-        let line = line.replace("::", zero.as_str());
-        // println!("+++++++++++++s {}", line);
-        let log = parse_nginx_log(line.as_str()).map_err(|e| anyhow::anyhow!(e))?;
-        // println!("{:?}", log);
-        data.push(log);
+        let due = oldest_pending.is_some_and(|t| t.elapsed() >= options.flush_interval);
+        if due || writer.pending_rows() >= options.flush_rows {
+            commit(&mut writer, &tailer, options)?;
+            oldest_pending = None;
+        }
+        if line.is_none() {
+            tokio::time::sleep(options.poll_interval).await;
+        }
     }
-    let schema = Arc::new(Schema::new(vec![
-        Field::new("ip", DataType::Utf8, false),
-        Field::new("datetime", DataType::Utf8, false),
-        Field::new("method", DataType::Utf8, false),
-        Field::new("url", DataType::Utf8, false),
-        Field::new("protocol", DataType::Utf8, false),
-        Field::new("status", DataType::UInt16, false),
-        Field::new("body_bytes", DataType::UInt64, false),
-        Field::new("referrer", DataType::Utf8, false),
-        Field::new("ua", DataType::Utf8, false),
-    ]));
-
-    let id: StringArray = data
-        .iter()
-        .map(|x| x.addr.to_string())
-        .collect::<Vec<_>>()
-        .into();
-    let datetime: StringArray = data
-        .iter()
-        .map(|x| x.datetime.to_rfc3339())
-        .collect::<Vec<_>>()
-        .into();
-    let method: StringArray = data
-        .iter()
-        .map(|x| x.method.into())
-        .collect::<Vec<String>>()
-        .into();
-    let url: StringArray = data
-        .iter()
-        .map(|x| x.url.clone())
-        .collect::<Vec<_>>()
-        .into();
-    let protocol: StringArray = data
-        .iter()
-        .map(|x| x.protocol.into())
-        .collect::<Vec<String>>()
-        .into();
-    let status: UInt16Array = data.iter().map(|x| x.status).collect::<Vec<_>>().into();
-    let body_bytes: UInt64Array = data.iter().map(|x| x.body_bytes).collect::<Vec<_>>().into();
-    let referrer: StringArray = data
-        .iter()
-        .map(|x| x.referrer.clone())
-        .collect::<Vec<_>>()
-        .into();
-    let ua: StringArray = data.iter().map(|x| x.ua.clone()).collect::<Vec<_>>().into();
+    commit(&mut writer, &tailer, options)?;
+    eprintln!("{}", errors.finish()?);
+    Ok(())
+}
 
-    let batch = RecordBatch::try_new(
-        schema.clone(),
-        vec![
-            Arc::new(id),
-            Arc::new(datetime),
-            Arc::new(method),
-            Arc::new(url),
-            Arc::new(protocol),
-            Arc::new(status),
-            Arc::new(body_bytes),
-            Arc::new(referrer),
-            Arc::new(ua),
-        ],
-    );
-    let file = File::create("assets/nginx_log_2.parquet")?;
-    let props = WriterProperties::builder().build();
-    let mut writer = ArrowWriter::try_new(file, schema, Some(props))?;
-    writer.write(&batch?)?;
-    writer.close()?;
+fn commit<T: AppendRecord>(
+    writer: &mut RollingWriter<T>,
+    tailer: &Tailer,
+    options: &FollowOptions,
+) -> anyhow::Result<()> {
+    let checkpoint = tailer.checkpoint();
+    if let Some(part) = writer.commit_at(checkpoint.as_ref())? {
+        eprintln!("committed {}", part.display());
+    }
+    if let (Some(file), Some(checkpoint)) = (&options.checkpoint, checkpoint) {
+        checkpoint.save(file)?;
+    }
     Ok(())
 }
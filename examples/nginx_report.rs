@@ -0,0 +1,39 @@
+use std::{fs, path::PathBuf, time::Instant};
+
+use clap::Parser;
+use datafusion::prelude::SessionContext;
+use grammar::{
+    query::{self, NGINX_TABLE},
+    report::{ReportFormat, ReportOptions, TrafficReport},
+};
+
+#[derive(Debug, Parser)]
+struct Cli {
+    /// Parquet file or dataset directory written by `nginx_log2`, or raw access logs.
+    #[arg(default_value = "assets/nginx_log_2.parquet")]
+    input: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    format: ReportFormat,
+    /// Write the report here instead of stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    report: ReportOptions,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let start = Instant::now();
+    let ctx = SessionContext::new();
+    query::register_nginx_format(&ctx)?;
+    query::register_access_logs(&ctx, NGINX_TABLE, &cli.input).await?;
+    let report = TrafficReport::compute(&ctx, NGINX_TABLE, &cli.report).await?;
+    let rendered = report.render(cli.format);
+    match &cli.output {
+        Some(path) => fs::write(path, rendered)?,
+        None => println!("{rendered}"),
+    }
+    eprintln!("{:?}", start.elapsed());
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::{path::PathBuf, time::Instant};
+
+use clap::Parser;
+use datafusion::prelude::SessionContext;
+use grammar::{
+    ingest::{ErrorOptions, LineError},
+    input::{self, HttpOptions},
+    nginx::{parse_nginx_line, NginxLog},
+    query::{self, NGINX_TABLE},
+};
+
+#[derive(Debug, Parser)]
+struct Cli {
+    /// SQL to run against the `nginx` table; each statement is printed as a table.
+    #[arg(
+        default_value = "SELECT status, count(*) AS requests FROM nginx GROUP BY status ORDER BY requests DESC"
+    )]
+    sql: Vec<String>,
+    /// Parquet file or partitioned dataset directory written by `nginx_log2`.
+    #[arg(
+        long,
+        default_value = "assets/nginx_log_2.parquet",
+        conflicts_with = "log"
+    )]
+    parquet: PathBuf,
+    /// Parse these access logs into memory instead of reading Parquet.
+    #[arg(long)]
+    log: Vec<String>,
+    /// Rows per in-memory batch when parsing logs.
+    #[arg(long, default_value_t = 8192)]
+    batch_size: usize,
+    #[command(flatten)]
+    http: HttpOptions,
+    #[command(flatten)]
+    errors: ErrorOptions,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let ctx = SessionContext::new();
+    // lets statements create `STORED AS NGINX` tables over raw logs
+    query::register_nginx_format(&ctx)?;
+    query::register_udfs(&ctx);
+    if cli.log.is_empty() {
+        query::register_parquet(&ctx, NGINX_TABLE, &cli.parquet).await?;
+    } else {
+        let logs = parse_logs(&cli.log, &cli.http, &cli.errors).await?;
+        query::register_records(&ctx, NGINX_TABLE, &logs, cli.batch_size)?;
+    }
+    for sql in &cli.sql {
+        let start = Instant::now();
+        let batches = query::run_sql(&ctx, sql).await?;
+        println!("{}", query::pretty(&batches)?);
+        eprintln!("{:?}", start.elapsed());
+    }
+    Ok(())
+}
+
+async fn parse_logs(
+    specs: &[String],
+    http: &HttpOptions,
+    errors: &ErrorOptions,
+) -> anyhow::Result<Vec<NginxLog>> {
+    let mut errors = errors.error_sink()?;
+    let mut logs = Vec::new();
+    for source in input::expand(specs)? {
+        let name = source.to_string();
+        let mut lines = source.open(http).await?;
+        let mut line_no = 0;
+        while let Some(line) = lines.next_line().await? {
+            line_no += 1;
+            let result: Result<_, LineError> = parse_nginx_line(&line);
+            logs.extend(errors.handle(&name, line_no, &line, result)?);
+        }
+    }
+    eprintln!("{}", errors.finish()?);
+    Ok(logs)
+}
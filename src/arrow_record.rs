@@ -0,0 +1,599 @@
+use std::{
+    marker::PhantomData,
+    net::{IpAddr, Ipv6Addr},
+    sync::Arc,
+};
+
+use arrow::{
+    array::{
+        ArrayBuilder, BooleanBuilder, FixedSizeBinaryBuilder, Float32Builder, Float64Builder,
+        Int16Builder, Int32Builder, Int64Builder, Int8Builder, MapBuilder, RecordBatch,
+        RecordBatchOptions, StringBuilder, StringDictionaryBuilder, TimestampMicrosecondBuilder,
+        UInt16Builder, UInt32Builder, UInt64Builder, UInt8Builder,
+    },
+    datatypes::{DataType, Field, Int32Type, Schema, SchemaRef, TimeUnit},
+    error::ArrowError,
+};
+use chrono::{DateTime, Utc};
+
+use crate::{net::ClientAddr, url::QueryParams};
+
+/// A value that can be stored in a single Arrow column.
+pub trait ArrowField {
+    type Builder: ArrayBuilder;
+
+    fn data_type() -> DataType;
+
+    fn nullable() -> bool {
+        false
+    }
+
+    fn field(name: &str) -> Field {
+        Field::new(name, Self::data_type(), Self::nullable())
+    }
+
+    fn new_builder(capacity: usize) -> Self::Builder;
+
+    fn append(&self, builder: &mut Self::Builder);
+
+    fn append_null(builder: &mut Self::Builder);
+
+    fn append_dyn(&self, builder: &mut dyn ArrayBuilder) {
+        let builder = builder
+            .as_any_mut()
+            .downcast_mut::<Self::Builder>()
+            .expect("column builder does not match the field type");
+        self.append(builder);
+    }
+}
+
+/// A row that can be appended to a set of Arrow builders, one builder per column.
+pub trait AppendRecord {
+    fn append_to(&self, builders: &mut [Box<dyn ArrayBuilder>]);
+}
+
+/// A row type whose schema is known at compile time.
+///
+/// Usually implemented with the [`arrow_record!`](crate::arrow_record!) macro.
+pub trait ToArrowRecord: AppendRecord {
+    fn fields() -> Vec<Field>;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(Self::fields()))
+    }
+
+    fn new_builders(capacity: usize) -> Vec<Box<dyn ArrayBuilder>>;
+
+    fn to_record_batch(records: &[Self]) -> Result<RecordBatch, ArrowError>
+    where
+        Self: Sized,
+    {
+        let mut builder = RecordBatchBuilder::with_capacity(records.len());
+        builder.extend(records);
+        builder.finish()
+    }
+}
+
+/// Collects records row by row and turns them into a [`RecordBatch`].
+pub struct RecordBatchBuilder<T> {
+    schema: SchemaRef,
+    builders: Vec<Box<dyn ArrayBuilder>>,
+    len: usize,
+    _record: PhantomData<fn(&T)>,
+}
+
+impl<T: ToArrowRecord> RecordBatchBuilder<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::new(T::schema(), T::new_builders(capacity))
+    }
+}
+
+impl<T: AppendRecord> RecordBatchBuilder<T> {
+    /// Creates a builder for a schema only known at runtime; `builders` must match its fields.
+    pub fn new(schema: SchemaRef, builders: Vec<Box<dyn ArrayBuilder>>) -> Self {
+        Self {
+            schema,
+            builders,
+            len: 0,
+            _record: PhantomData,
+        }
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&mut self, record: &T) {
+        record.append_to(&mut self.builders);
+        self.len += 1;
+    }
+
+    pub fn extend<'a>(&mut self, records: impl IntoIterator<Item = &'a T>)
+    where
+        T: 'a,
+    {
+        for record in records {
+            self.append(record);
+        }
+    }
+
+    /// Builds a batch from the rows appended so far and resets the builder.
+    pub fn finish(&mut self) -> Result<RecordBatch, ArrowError> {
+        let columns = self.builders.iter_mut().map(|b| b.finish()).collect();
+        // the row count is needed for schemas without columns, e.g. an empty projection
+        let options = RecordBatchOptions::new().with_row_count(Some(self.len));
+        self.len = 0;
+        RecordBatch::try_new_with_options(self.schema.clone(), columns, &options)
+    }
+}
+
+impl<T: ToArrowRecord> Default for RecordBatchBuilder<T> {
+    fn default() -> Self {
+        Self::with_capacity(1024)
+    }
+}
+
+macro_rules! impl_primitive_field {
+    ($($ty:ty => $builder:ty, $data_type:expr;)*) => {
+        $(
+            impl ArrowField for $ty {
+                type Builder = $builder;
+
+                fn data_type() -> DataType {
+                    $data_type
+                }
+
+                fn new_builder(capacity: usize) -> Self::Builder {
+                    <$builder>::with_capacity(capacity)
+                }
+
+                fn append(&self, builder: &mut Self::Builder) {
+                    builder.append_value(*self);
+                }
+
+                fn append_null(builder: &mut Self::Builder) {
+                    builder.append_null();
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive_field! {
+    bool => BooleanBuilder, DataType::Boolean;
+    u8 => UInt8Builder, DataType::UInt8;
+    u16 => UInt16Builder, DataType::UInt16;
+    u32 => UInt32Builder, DataType::UInt32;
+    u64 => UInt64Builder, DataType::UInt64;
+    i8 => Int8Builder, DataType::Int8;
+    i16 => Int16Builder, DataType::Int16;
+    i32 => Int32Builder, DataType::Int32;
+    i64 => Int64Builder, DataType::Int64;
+    f32 => Float32Builder, DataType::Float32;
+    f64 => Float64Builder, DataType::Float64;
+}
+
+impl ArrowField for String {
+    type Builder = StringBuilder;
+
+    fn data_type() -> DataType {
+        DataType::Utf8
+    }
+
+    fn new_builder(capacity: usize) -> Self::Builder {
+        StringBuilder::with_capacity(capacity, capacity * 16)
+    }
+
+    fn append(&self, builder: &mut Self::Builder) {
+        builder.append_value(self);
+    }
+
+    fn append_null(builder: &mut Self::Builder) {
+        builder.append_null();
+    }
+}
+
+/// Stored as the 16 bytes of the IPv6 address, IPv4 addresses being mapped to `::ffff:a.b.c.d`,
+/// so that both families sort and compare by network prefix.
+impl ArrowField for IpAddr {
+    type Builder = FixedSizeBinaryBuilder;
+
+    fn data_type() -> DataType {
+        DataType::FixedSizeBinary(16)
+    }
+
+    fn new_builder(capacity: usize) -> Self::Builder {
+        FixedSizeBinaryBuilder::with_capacity(capacity, 16)
+    }
+
+    fn append(&self, builder: &mut Self::Builder) {
+        builder
+            .append_value(ip_to_bytes(self))
+            .expect("an IPv6 address is 16 bytes");
+    }
+
+    fn append_null(builder: &mut Self::Builder) {
+        builder.append_null();
+    }
+}
+
+impl ArrowField for DateTime<Utc> {
+    type Builder = TimestampMicrosecondBuilder;
+
+    fn data_type() -> DataType {
+        DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+    }
+
+    fn new_builder(capacity: usize) -> Self::Builder {
+        TimestampMicrosecondBuilder::with_capacity(capacity).with_timezone("UTC")
+    }
+
+    fn append(&self, builder: &mut Self::Builder) {
+        builder.append_value(self.timestamp_micros());
+    }
+
+    fn append_null(builder: &mut Self::Builder) {
+        builder.append_null();
+    }
+}
+
+/// Hosts and addresses share one column, so client addresses are kept as text.
+impl ArrowField for ClientAddr {
+    type Builder = StringBuilder;
+
+    fn data_type() -> DataType {
+        DataType::Utf8
+    }
+
+    fn new_builder(capacity: usize) -> Self::Builder {
+        StringBuilder::with_capacity(capacity, capacity * 16)
+    }
+
+    fn append(&self, builder: &mut Self::Builder) {
+        builder.append_value(self.to_string());
+    }
+
+    fn append_null(builder: &mut Self::Builder) {
+        builder.append_null();
+    }
+}
+
+pub fn ip_to_bytes(ip: &IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+        IpAddr::V6(v6) => v6.octets(),
+    }
+}
+
+pub fn ip_from_bytes(bytes: [u8; 16]) -> IpAddr {
+    let v6 = Ipv6Addr::from(bytes);
+    match v6.to_ipv4_mapped() {
+        Some(v4) => IpAddr::V4(v4),
+        None => IpAddr::V6(v6),
+    }
+}
+
+/// Dictionary-encoded string column, for fields with few distinct values.
+pub type DictionaryBuilder = StringDictionaryBuilder<Int32Type>;
+
+/// Stored as an Arrow map from key to value; repeated keys are kept as separate entries.
+impl ArrowField for QueryParams {
+    type Builder = MapBuilder<StringBuilder, StringBuilder>;
+
+    fn data_type() -> DataType {
+        let entries = DataType::Struct(
+            vec![
+                Field::new("keys", DataType::Utf8, false),
+                Field::new("values", DataType::Utf8, true),
+            ]
+            .into(),
+        );
+        DataType::Map(Arc::new(Field::new("entries", entries, false)), false)
+    }
+
+    fn new_builder(capacity: usize) -> Self::Builder {
+        MapBuilder::with_capacity(None, StringBuilder::new(), StringBuilder::new(), capacity)
+    }
+
+    fn append(&self, builder: &mut Self::Builder) {
+        for (k, v) in self.iter() {
+            builder.keys().append_value(k);
+            builder.values().append_value(v);
+        }
+        builder
+            .append(true)
+            .expect("keys and values are appended in pairs");
+    }
+
+    fn append_null(builder: &mut Self::Builder) {
+        builder
+            .append(false)
+            .expect("keys and values are appended in pairs");
+    }
+}
+
+impl<T: ArrowField> ArrowField for Option<T> {
+    type Builder = T::Builder;
+
+    fn data_type() -> DataType {
+        T::data_type()
+    }
+
+    fn nullable() -> bool {
+        true
+    }
+
+    fn new_builder(capacity: usize) -> Self::Builder {
+        T::new_builder(capacity)
+    }
+
+    fn append(&self, builder: &mut Self::Builder) {
+        match self {
+            Some(v) => v.append(builder),
+            None => T::append_null(builder),
+        }
+    }
+
+    fn append_null(builder: &mut Self::Builder) {
+        T::append_null(builder);
+    }
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use arrow::{
+        array::ArrayBuilder,
+        datatypes::{DataType, Field},
+    };
+}
+
+/// Implements [`ArrowField`] as a dictionary-encoded string column for types that are `AsRef<str>`,
+/// typically enums with a handful of variants.
+#[macro_export]
+macro_rules! arrow_dictionary {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl $crate::arrow_record::ArrowField for $ty {
+                type Builder = $crate::arrow_record::DictionaryBuilder;
+
+                fn data_type() -> $crate::arrow_record::__private::DataType {
+                    $crate::arrow_record::__private::DataType::Dictionary(
+                        Box::new($crate::arrow_record::__private::DataType::Int32),
+                        Box::new($crate::arrow_record::__private::DataType::Utf8),
+                    )
+                }
+
+                fn new_builder(capacity: usize) -> Self::Builder {
+                    $crate::arrow_record::DictionaryBuilder::with_capacity(capacity, 16, 256)
+                }
+
+                fn append(&self, builder: &mut Self::Builder) {
+                    builder.append_value(AsRef::<str>::as_ref(self));
+                }
+
+                fn append_null(builder: &mut Self::Builder) {
+                    builder.append_null();
+                }
+            }
+        )*
+    };
+}
+
+/// Defines a struct and implements [`ToArrowRecord`] and [`AppendRecord`] for it, one column per
+/// field.
+///
+/// A field can be exported under another column name with `#[arrow(name = "...")]`.
+///
+/// ```
+/// grammar::arrow_record! {
+///     #[derive(Debug)]
+///     pub struct Hit {
+///         #[arrow(name = "path")]
+///         pub url: String,
+///         pub status: u16,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! arrow_record {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[arrow(name = $column:literal)])?
+                $field_vis:vis $field:ident : $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($field_vis $field: $ty,)*
+        }
+
+        impl $crate::arrow_record::ToArrowRecord for $name {
+            fn fields() -> Vec<$crate::arrow_record::__private::Field> {
+                vec![$(
+                    <$ty as $crate::arrow_record::ArrowField>::field(
+                        $crate::arrow_record!(@column $field $(, $column)?),
+                    ),
+                )*]
+            }
+
+            fn new_builders(
+                capacity: usize,
+            ) -> Vec<Box<dyn $crate::arrow_record::__private::ArrayBuilder>> {
+                vec![$(
+                    Box::new(<$ty as $crate::arrow_record::ArrowField>::new_builder(capacity)),
+                )*]
+            }
+        }
+
+        impl $crate::arrow_record::AppendRecord for $name {
+            fn append_to(
+                &self,
+                builders: &mut [Box<dyn $crate::arrow_record::__private::ArrayBuilder>],
+            ) {
+                let mut builders = builders.iter_mut();
+                $(
+                    $crate::arrow_record::ArrowField::append_dyn(
+                        &self.$field,
+                        builders
+                            .next()
+                            .expect("one builder per record field")
+                            .as_mut(),
+                    );
+                )*
+            }
+        }
+    };
+    (@column $field:ident) => {
+        stringify!($field)
+    };
+    (@column $field:ident, $column:literal) => {
+        $column
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::{TimestampMicrosecondType, UInt16Type};
+
+    use super::*;
+
+    crate::arrow_record! {
+        struct Hit {
+            #[arrow(name = "path")]
+            url: String,
+            status: u16,
+            referrer: Option<String>,
+        }
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = Hit::schema();
+        assert_eq!(
+            schema
+                .fields()
+                .iter()
+                .map(|f| f.name().as_str())
+                .collect::<Vec<_>>(),
+            vec!["path", "status", "referrer"]
+        );
+        assert_eq!(schema.field(1).data_type(), &DataType::UInt16);
+        assert!(!schema.field(0).is_nullable());
+        assert!(schema.field(2).is_nullable());
+    }
+
+    #[test]
+    fn test_record_batch() {
+        let hits = vec![
+            Hit {
+                url: "/a".to_string(),
+                status: 200,
+                referrer: None,
+            },
+            Hit {
+                url: "/b".to_string(),
+                status: 404,
+                referrer: Some("/a".to_string()),
+            },
+        ];
+        let batch = Hit::to_record_batch(&hits).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.column(0).as_string::<i32>().value(1), "/b");
+        assert_eq!(batch.column(1).as_primitive::<UInt16Type>().value(1), 404);
+        assert!(batch.column(2).is_null(0));
+        assert_eq!(batch.column(2).as_string::<i32>().value(1), "/a");
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum Level {
+        Low,
+        High,
+    }
+
+    impl AsRef<str> for Level {
+        fn as_ref(&self) -> &str {
+            match self {
+                Level::Low => "low",
+                Level::High => "high",
+            }
+        }
+    }
+
+    crate::arrow_dictionary!(Level);
+
+    crate::arrow_record! {
+        struct Event {
+            at: DateTime<Utc>,
+            addr: IpAddr,
+            level: Level,
+        }
+    }
+
+    #[test]
+    fn test_typed_columns() {
+        let at = DateTime::parse_from_rfc3339("2015-05-17T08:05:32Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let events = vec![
+            Event {
+                at,
+                addr: "93.180.71.3".parse().unwrap(),
+                level: Level::Low,
+            },
+            Event {
+                at,
+                addr: "2001:db8::1".parse().unwrap(),
+                level: Level::High,
+            },
+            Event {
+                at,
+                addr: "10.0.0.1".parse().unwrap(),
+                level: Level::Low,
+            },
+        ];
+        let batch = Event::to_record_batch(&events).unwrap();
+        assert_eq!(
+            batch.schema().field(0).data_type(),
+            &DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+        );
+        let at_col = batch.column(0).as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(at_col.value(0), at.timestamp_micros());
+
+        let addr = batch.column(1).as_fixed_size_binary();
+        assert_eq!(addr.value_length(), 16);
+        let back: Vec<IpAddr> = (0..3)
+            .map(|i| ip_from_bytes(addr.value(i).try_into().unwrap()))
+            .collect();
+        assert_eq!(back, events.iter().map(|e| e.addr).collect::<Vec<_>>());
+
+        let level = batch.column(2).as_dictionary::<Int32Type>();
+        assert_eq!(level.values().len(), 2);
+        assert_eq!(level.keys().values(), &[0, 1, 0]);
+    }
+
+    #[test]
+    fn test_builder_resets_after_finish() {
+        let mut builder = RecordBatchBuilder::<Hit>::with_capacity(4);
+        builder.append(&Hit {
+            url: "/".to_string(),
+            status: 304,
+            referrer: None,
+        });
+        assert_eq!(builder.len(), 1);
+        assert_eq!(builder.finish().unwrap().num_rows(), 1);
+        assert!(builder.is_empty());
+        assert_eq!(builder.finish().unwrap().num_rows(), 0);
+    }
+}
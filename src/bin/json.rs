@@ -0,0 +1,18 @@
+use anyhow::Result;
+use grammar::json::parse_json;
+
+fn main() -> Result<()> {
+    let s = r#"{
+        "name": "John Doe",
+        "age": 43,
+        "is_adult": true,
+        "mark:": [90.1, 80.2, 85.0],
+        "address": {
+            "city": "New York",
+            "state": "NY"
+        }
+    }"#;
+    let v = parse_json(s)?;
+    println!("{:#?}", v);
+    Ok(())
+}
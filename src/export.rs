@@ -0,0 +1,557 @@
+use std::{fmt, io::Write, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use arrow::datatypes::Schema;
+use clap::{Args, ValueEnum};
+use parquet::{
+    arrow::{arrow_to_parquet_schema, ArrowWriter},
+    basic::{Compression, GzipLevel, ZstdLevel},
+    file::properties::{EnabledStatistics, WriterProperties},
+    format::{FileMetaData, KeyValue, SortingColumn},
+    schema::types::{ColumnPath, SchemaDescriptor},
+};
+
+use crate::{AppendRecord, RecordBatchBuilder, ToArrowRecord};
+
+pub mod partition;
+pub mod rolling;
+
+pub use partition::{Manifest, PartitionKey, PartitionOptions, PartitionedWriter};
+pub use rolling::RollingWriter;
+
+pub const PARSER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const METADATA_SOURCE: &str = "grammar.source";
+pub const METADATA_PARSER_VERSION: &str = "grammar.parser_version";
+pub const METADATA_PARSE_ERRORS: &str = "grammar.parse_errors";
+/// The [`Checkpoint`](crate::input::Checkpoint) a followed log had reached when a part was
+/// committed.
+pub const METADATA_CHECKPOINT: &str = "grammar.checkpoint";
+
+#[derive(Debug, Clone, Args)]
+pub struct ExportOptions {
+    /// Rows buffered in the Arrow builders before they are handed to the Parquet writer.
+    #[arg(long, default_value_t = 8192)]
+    pub batch_size: usize,
+    /// Maximum number of rows in a Parquet row group.
+    #[arg(long = "row-group-size", default_value_t = 128 * 1024)]
+    pub max_row_group_size: usize,
+    /// Flush the current row group once its encoded size exceeds this many bytes; 0 turns
+    /// the limit off.
+    #[arg(long = "row-group-bytes", default_value_t = 64 * 1024 * 1024)]
+    pub max_row_group_bytes: usize,
+    /// Compression codec: none, snappy, lz4, gzip[:level] or zstd[:level].
+    #[arg(long, default_value_t = Codec::None)]
+    pub compression: Codec,
+    /// Disable dictionary encoding for every column not listed with `--dictionary`.
+    #[arg(long)]
+    pub no_dictionary: bool,
+    /// Columns to dictionary-encode.
+    #[arg(long = "dictionary", value_name = "COLUMN")]
+    pub dictionary_columns: Vec<String>,
+    /// Columns to write without dictionary encoding.
+    #[arg(long = "plain", value_name = "COLUMN")]
+    pub plain_columns: Vec<String>,
+    /// Granularity of min/max statistics.
+    #[arg(long, value_enum, default_value_t = Statistics::Page)]
+    pub statistics: Statistics,
+    /// Columns to write bloom filters for.
+    #[arg(long = "bloom-filter", value_name = "COLUMN")]
+    pub bloom_filter_columns: Vec<String>,
+    /// False positive probability of the bloom filters.
+    #[arg(long, default_value_t = 0.05)]
+    pub bloom_filter_fpp: f64,
+    /// Columns the rows are known to be sorted by, as `column` or `column:desc`.
+    #[arg(long = "sorted-by", value_name = "COLUMN")]
+    pub sorted_by: Vec<String>,
+    /// Extra key-value file metadata, as `key=value`.
+    #[arg(long = "metadata", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 8192,
+            max_row_group_size: 128 * 1024,
+            max_row_group_bytes: 64 * 1024 * 1024,
+            compression: Codec::None,
+            no_dictionary: false,
+            dictionary_columns: Vec::new(),
+            plain_columns: Vec::new(),
+            statistics: Statistics::Page,
+            bloom_filter_columns: Vec::new(),
+            bloom_filter_fpp: 0.05,
+            sorted_by: Vec::new(),
+            metadata: Vec::new(),
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn writer_properties(&self, schema: &Schema) -> Result<WriterProperties> {
+        let mut builder = WriterProperties::builder()
+            .set_created_by(format!("grammar version {PARSER_VERSION}"))
+            .set_max_row_group_size(self.max_row_group_size)
+            .set_compression(self.compression.into())
+            .set_dictionary_enabled(!self.no_dictionary)
+            .set_statistics_enabled(self.statistics.into());
+        let descr = arrow_to_parquet_schema(schema)?;
+        for name in &self.dictionary_columns {
+            for (_, path) in leaf_columns(&descr, schema, name)? {
+                builder = builder.set_column_dictionary_enabled(path, true);
+            }
+        }
+        for name in &self.plain_columns {
+            for (_, path) in leaf_columns(&descr, schema, name)? {
+                builder = builder.set_column_dictionary_enabled(path, false);
+            }
+        }
+        for name in &self.bloom_filter_columns {
+            for (_, path) in leaf_columns(&descr, schema, name)? {
+                builder = builder
+                    .set_column_bloom_filter_enabled(path.clone(), true)
+                    .set_column_bloom_filter_fpp(path, self.bloom_filter_fpp);
+            }
+        }
+        if !self.sorted_by.is_empty() {
+            let columns = self
+                .sorted_by
+                .iter()
+                .map(|spec| {
+                    let (name, descending) = match spec.split_once(':') {
+                        Some((name, "desc")) => (name, true),
+                        Some((name, "asc")) => (name, false),
+                        Some(_) => return Err(anyhow!("invalid sort order in {spec:?}")),
+                        None => (spec.as_str(), false),
+                    };
+                    match leaf_columns(&descr, schema, name)?[..] {
+                        [(idx, ref path)] if path.parts().len() == 1 => {
+                            Ok(SortingColumn::new(idx as i32, descending, false))
+                        }
+                        _ => bail!("cannot sort by {name}, it is a nested column"),
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?;
+            builder = builder.set_sorting_columns(Some(columns));
+        }
+        let kv = std::iter::once(KeyValue::new(
+            METADATA_PARSER_VERSION.to_string(),
+            PARSER_VERSION.to_string(),
+        ))
+        .chain(
+            self.metadata
+                .iter()
+                .map(|(k, v)| KeyValue::new(k.clone(), v.clone())),
+        )
+        .collect();
+        builder = builder.set_key_value_metadata(Some(kv));
+        Ok(builder.build())
+    }
+}
+
+/// The Parquet leaf columns, with their indices, that the Arrow field `name` is written to.
+/// Maps and lists become leaves with longer paths, e.g. `query.key_value.key`.
+fn leaf_columns(
+    descr: &SchemaDescriptor,
+    schema: &Schema,
+    name: &str,
+) -> Result<Vec<(usize, ColumnPath)>> {
+    schema.index_of(name)?;
+    Ok(descr
+        .columns()
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| column.path().parts()[0] == name)
+        .map(|(i, column)| (i, column.path().clone()))
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    None,
+    Snappy,
+    Lz4,
+    Gzip(u32),
+    Zstd(i32),
+}
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+        let codec = match (name.to_ascii_lowercase().as_str(), level) {
+            ("none" | "uncompressed", None) => Codec::None,
+            ("snappy", None) => Codec::Snappy,
+            ("lz4", None) => Codec::Lz4,
+            ("gzip", level) => {
+                let level = level.map(str::parse).transpose()?.unwrap_or(6);
+                GzipLevel::try_new(level)?;
+                Codec::Gzip(level)
+            }
+            ("zstd", level) => {
+                let level = level.map(str::parse).transpose()?.unwrap_or(3);
+                ZstdLevel::try_new(level)?;
+                Codec::Zstd(level)
+            }
+            _ => return Err(anyhow!("unsupported compression codec {s:?}")),
+        };
+        Ok(codec)
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::None => write!(f, "none"),
+            Codec::Snappy => write!(f, "snappy"),
+            Codec::Lz4 => write!(f, "lz4"),
+            Codec::Gzip(level) => write!(f, "gzip:{level}"),
+            Codec::Zstd(level) => write!(f, "zstd:{level}"),
+        }
+    }
+}
+
+impl From<Codec> for Compression {
+    fn from(codec: Codec) -> Self {
+        // levels are validated in `Codec::from_str`
+        match codec {
+            Codec::None => Compression::UNCOMPRESSED,
+            Codec::Snappy => Compression::SNAPPY,
+            Codec::Lz4 => Compression::LZ4_RAW,
+            Codec::Gzip(level) => Compression::GZIP(GzipLevel::try_new(level).unwrap_or_default()),
+            Codec::Zstd(level) => Compression::ZSTD(ZstdLevel::try_new(level).unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Statistics {
+    None,
+    Chunk,
+    Page,
+}
+
+impl From<Statistics> for EnabledStatistics {
+    fn from(s: Statistics) -> Self {
+        match s {
+            Statistics::None => EnabledStatistics::None,
+            Statistics::Chunk => EnabledStatistics::Chunk,
+            Statistics::Page => EnabledStatistics::Page,
+        }
+    }
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String)> {
+    let (k, v) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected KEY=VALUE, got {s:?}"))?;
+    Ok((k.to_string(), v.to_string()))
+}
+
+/// Destination for parsed records, so a pipeline does not care how its output is laid out.
+pub trait RecordSink<T> {
+    fn write(&mut self, record: &T) -> Result<()>;
+
+    fn append_metadata(&mut self, key: &str, value: &str);
+
+    fn close(self: Box<Self>) -> Result<()>;
+}
+
+/// Streams records into a Parquet file, keeping at most one batch and one row group in memory.
+pub struct ParquetSink<W: Write + Send, T> {
+    writer: ArrowWriter<W>,
+    builder: RecordBatchBuilder<T>,
+    options: ExportOptions,
+    rows: usize,
+}
+
+impl<W: Write + Send, T: ToArrowRecord> ParquetSink<W, T> {
+    pub fn try_new(out: W, options: ExportOptions) -> Result<Self> {
+        let builder = RecordBatchBuilder::with_capacity(options.batch_size);
+        Self::try_with_builder(out, builder, options)
+    }
+}
+
+impl<W: Write + Send, T: AppendRecord> ParquetSink<W, T> {
+    /// Creates a sink for records whose schema is only known at runtime.
+    pub fn try_with_builder(
+        out: W,
+        builder: RecordBatchBuilder<T>,
+        options: ExportOptions,
+    ) -> Result<Self> {
+        let props = options.writer_properties(&builder.schema())?;
+        let writer = ArrowWriter::try_new(out, builder.schema(), Some(props))?;
+        Ok(Self {
+            writer,
+            builder,
+            options,
+            rows: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &T) -> Result<()> {
+        self.builder.append(record);
+        self.rows += 1;
+        if self.builder.len() >= self.options.batch_size {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Adds a key-value pair to the file footer, e.g. counters only known once input is exhausted.
+    pub fn append_metadata(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.writer
+            .append_key_value_metadata(KeyValue::new(key.into(), value.into()));
+    }
+
+    /// Bytes written to the output so far, plus the encoded size of the open row group.
+    pub fn bytes_written(&self) -> usize {
+        self.writer.bytes_written() + self.writer.in_progress_size()
+    }
+
+    /// Total number of records written so far.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn flush_batch(&mut self) -> Result<()> {
+        if self.builder.is_empty() {
+            return Ok(());
+        }
+        let batch = self.builder.finish()?;
+        self.writer.write(&batch)?;
+        let limit = self.options.max_row_group_bytes;
+        if limit > 0 && self.writer.in_progress_size() >= limit {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes buffered rows and closes the current row group.
+    pub fn flush(&mut self) -> Result<()> {
+        self.flush_batch()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn close(mut self) -> Result<FileMetaData> {
+        self.flush_batch()?;
+        Ok(self.writer.close()?)
+    }
+}
+
+impl<W: Write + Send, T: AppendRecord> RecordSink<T> for ParquetSink<W, T> {
+    fn write(&mut self, record: &T) -> Result<()> {
+        ParquetSink::write(self, record)
+    }
+
+    fn append_metadata(&mut self, key: &str, value: &str) {
+        ParquetSink::append_metadata(self, key, value);
+    }
+
+    fn close(self: Box<Self>) -> Result<()> {
+        ParquetSink::close(*self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use parquet::{arrow::arrow_reader::ParquetRecordBatchReaderBuilder, basic::Encoding};
+
+    use super::*;
+
+    crate::arrow_record! {
+        struct Row {
+            id: u64,
+            name: String,
+        }
+    }
+
+    fn write_rows(n: u64, options: ExportOptions) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut sink = ParquetSink::<_, Row>::try_new(&mut buf, options).unwrap();
+        for id in 0..n {
+            sink.write(&Row {
+                id,
+                name: format!("row-{id}"),
+            })
+            .unwrap();
+        }
+        assert_eq!(sink.rows(), n as usize);
+        let meta = sink.close().unwrap();
+        assert_eq!(meta.num_rows, n as i64);
+        buf
+    }
+
+    #[test]
+    fn test_sink_writes_all_rows() {
+        let options = ExportOptions {
+            batch_size: 3,
+            ..Default::default()
+        };
+        let buf = write_rows(10, options);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(buf))
+            .unwrap()
+            .build()
+            .unwrap();
+        let rows: usize = reader.map(|b| b.unwrap().num_rows()).sum();
+        assert_eq!(rows, 10);
+    }
+
+    #[test]
+    fn test_sink_row_group_size() {
+        let options = ExportOptions {
+            batch_size: 3,
+            max_row_group_size: 4,
+            max_row_group_bytes: 0,
+            ..Default::default()
+        };
+        let buf = write_rows(10, options);
+        let builder = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(buf)).unwrap();
+        let sizes: Vec<i64> = builder
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|rg| rg.num_rows())
+            .collect();
+        assert_eq!(sizes, vec![4, 4, 2]);
+
+        // any byte limit is reached by every batch, so each one closes its row group
+        let options = ExportOptions {
+            batch_size: 3,
+            max_row_group_size: 4,
+            max_row_group_bytes: 1,
+            ..Default::default()
+        };
+        let buf = write_rows(10, options);
+        let builder = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(buf)).unwrap();
+        let sizes: Vec<i64> = builder
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|rg| rg.num_rows())
+            .collect();
+        assert_eq!(sizes, vec![3, 3, 3, 1]);
+    }
+
+    #[test]
+    fn test_codec_from_str() {
+        assert_eq!("zstd".parse::<Codec>().unwrap(), Codec::Zstd(3));
+        assert_eq!("ZSTD:9".parse::<Codec>().unwrap(), Codec::Zstd(9));
+        assert_eq!("gzip".parse::<Codec>().unwrap(), Codec::Gzip(6));
+        assert_eq!("lz4".parse::<Codec>().unwrap(), Codec::Lz4);
+        assert!("gzip:42".parse::<Codec>().is_err());
+        assert!("brotli".parse::<Codec>().is_err());
+        assert!("snappy:1".parse::<Codec>().is_err());
+    }
+
+    #[test]
+    fn test_writer_properties_reject_unknown_column() {
+        let options = ExportOptions {
+            bloom_filter_columns: vec!["missing".to_string()],
+            ..Default::default()
+        };
+        assert!(options.writer_properties(&Row::schema()).is_err());
+    }
+
+    #[test]
+    fn test_writer_properties_applied() {
+        let options = ExportOptions {
+            compression: Codec::Zstd(1),
+            plain_columns: vec!["name".to_string()],
+            bloom_filter_columns: vec!["name".to_string()],
+            sorted_by: vec!["id:desc".to_string()],
+            metadata: vec![("source".to_string(), "test".to_string())],
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        let mut sink = ParquetSink::<_, Row>::try_new(&mut buf, options).unwrap();
+        sink.write(&Row {
+            id: 1,
+            name: "a".to_string(),
+        })
+        .unwrap();
+        sink.append_metadata("parse_errors", "0");
+        sink.close().unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(buf)).unwrap();
+        let meta = builder.metadata();
+        let kv = meta.file_metadata().key_value_metadata().unwrap();
+        let value = |key: &str| {
+            kv.iter()
+                .find(|kv| kv.key == key)
+                .and_then(|kv| kv.value.clone())
+        };
+        assert_eq!(value("source").as_deref(), Some("test"));
+        assert_eq!(
+            value(METADATA_PARSER_VERSION).as_deref(),
+            Some(PARSER_VERSION)
+        );
+        assert_eq!(value("parse_errors").as_deref(), Some("0"));
+
+        let rg = meta.row_group(0);
+        assert_eq!(
+            rg.sorting_columns().unwrap(),
+            &vec![SortingColumn::new(0, true, false)]
+        );
+        assert!(matches!(rg.column(0).compression(), Compression::ZSTD(_)));
+        assert!(rg.column(1).bloom_filter_offset().is_some());
+        assert!(!rg.column(1).encodings().contains(&Encoding::RLE_DICTIONARY));
+        assert!(rg.column(0).encodings().contains(&Encoding::RLE_DICTIONARY));
+    }
+
+    #[test]
+    fn test_writer_properties_nested_columns() {
+        use crate::nginx::{parse_nginx_log, NginxLog};
+
+        let schema = NginxLog::schema();
+        let options = ExportOptions {
+            sorted_by: vec!["status".to_string()],
+            plain_columns: vec!["query".to_string()],
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        let mut sink = ParquetSink::<_, NginxLog>::try_new(&mut buf, options).unwrap();
+        let line =
+            r#"93.180.71.3 - - [17/May/2015:08:05:32 +0000] "GET /a?x=1 HTTP/1.1" 304 0 "-" "-""#;
+        sink.write(&parse_nginx_log(line).unwrap()).unwrap();
+        sink.close().unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(buf)).unwrap();
+        let rg = builder.metadata().row_group(0);
+        // the query map takes two leaves, so leaf and field indices differ past it
+        let status = rg
+            .columns()
+            .iter()
+            .position(|c| c.column_path().string() == "status")
+            .unwrap();
+        assert_ne!(status, schema.index_of("status").unwrap());
+        assert_eq!(
+            rg.sorting_columns().unwrap(),
+            &vec![SortingColumn::new(status as i32, false, false)]
+        );
+        let query: Vec<_> = rg
+            .columns()
+            .iter()
+            .filter(|c| c.column_path().parts()[0] == "query")
+            .collect();
+        assert_eq!(query.len(), 2);
+        for column in query {
+            assert!(!column.encodings().contains(&Encoding::RLE_DICTIONARY));
+        }
+
+        let options = ExportOptions {
+            sorted_by: vec!["query".to_string()],
+            ..Default::default()
+        };
+        let err = options.writer_properties(&schema).unwrap_err();
+        assert!(err.to_string().contains("nested"), "{err}");
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use super::{ExportOptions, ParquetSink, RecordSink};
+use crate::{AppendRecord, RecordBatchBuilder, ToArrowRecord};
+
+pub const MANIFEST_FILE: &str = "_manifest.json";
+
+/// A named partition column computed from each record.
+pub struct PartitionKey<T> {
+    name: String,
+    expr: Box<dyn Fn(&T) -> String + Send + Sync>,
+}
+
+impl<T> PartitionKey<T> {
+    pub fn new(
+        name: impl Into<String>,
+        expr: impl Fn(&T) -> String + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            expr: Box::new(expr),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct PartitionOptions {
+    /// Start a new file in a partition once the current one reaches this many bytes.
+    #[arg(long, default_value_t = 128 * 1024 * 1024)]
+    pub max_file_bytes: usize,
+    /// Close the least recently written partition file when more than this many are open.
+    #[arg(long, default_value_t = 64)]
+    pub max_open_files: usize,
+}
+
+impl Default for PartitionOptions {
+    fn default() -> Self {
+        Self {
+            max_file_bytes: 128 * 1024 * 1024,
+            max_open_files: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Partition columns, in directory nesting order.
+    pub partition_columns: Vec<String>,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Path relative to the dataset root.
+    pub path: String,
+    /// Partition values, in the order of `Manifest::partition_columns`.
+    pub partition: Vec<String>,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+impl Manifest {
+    pub fn read(root: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(root.as_ref().join(MANIFEST_FILE))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+}
+
+struct OpenFile<T> {
+    sink: ParquetSink<File, T>,
+    path: String,
+    partition: Vec<String>,
+    last_write: u64,
+}
+
+/// Writes records into a Hive-style directory tree, e.g. `date=2015-05-17/part-0001.parquet`,
+/// and a `_manifest.json` describing every file once closed.
+pub struct PartitionedWriter<T> {
+    root: PathBuf,
+    keys: Vec<PartitionKey<T>>,
+    new_builder: Box<dyn Fn(usize) -> RecordBatchBuilder<T> + Send + Sync>,
+    export: ExportOptions,
+    options: PartitionOptions,
+    open: HashMap<String, OpenFile<T>>,
+    next_part: HashMap<String, usize>,
+    metadata: Vec<(String, String)>,
+    files: Vec<ManifestFile>,
+    writes: u64,
+}
+
+impl<T: ToArrowRecord + 'static> PartitionedWriter<T> {
+    pub fn try_new(
+        root: impl Into<PathBuf>,
+        keys: Vec<PartitionKey<T>>,
+        export: ExportOptions,
+        options: PartitionOptions,
+    ) -> Result<Self> {
+        Self::try_with_builder(
+            root,
+            keys,
+            RecordBatchBuilder::with_capacity,
+            export,
+            options,
+        )
+    }
+}
+
+impl<T: AppendRecord> PartitionedWriter<T> {
+    /// Creates a writer for records whose schema is only known at runtime; `new_builder` is
+    /// called with the batch size for every file opened.
+    pub fn try_with_builder(
+        root: impl Into<PathBuf>,
+        keys: Vec<PartitionKey<T>>,
+        new_builder: impl Fn(usize) -> RecordBatchBuilder<T> + Send + Sync + 'static,
+        export: ExportOptions,
+        options: PartitionOptions,
+    ) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self {
+            root,
+            keys,
+            new_builder: Box::new(new_builder),
+            export,
+            options,
+            open: HashMap::new(),
+            next_part: HashMap::new(),
+            metadata: Vec::new(),
+            files: Vec::new(),
+            writes: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &T) -> Result<()> {
+        let partition: Vec<String> = self.keys.iter().map(|k| (k.expr)(record)).collect();
+        let dir = self.partition_dir(&partition);
+        if !self.open.contains_key(&dir) {
+            if self.open.len() >= self.options.max_open_files {
+                self.close_least_recent()?;
+            }
+            let file = self.open_file(&dir, partition)?;
+            self.open.insert(dir.clone(), file);
+        }
+
+        self.writes += 1;
+        let file = self.open.get_mut(&dir).expect("partition file is open");
+        file.sink.write(record)?;
+        file.last_write = self.writes;
+        if file.sink.bytes_written() >= self.options.max_file_bytes {
+            let file = self.open.remove(&dir).expect("partition file is open");
+            self.close_file(file)?;
+        }
+        Ok(())
+    }
+
+    pub fn append_metadata(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.metadata.push((key.into(), value.into()));
+    }
+
+    /// Closes every open file and writes the manifest.
+    pub fn close(mut self) -> Result<Manifest> {
+        let mut open: Vec<_> = self.open.drain().map(|(_, f)| f).collect();
+        open.sort_by_key(|f| f.last_write);
+        for file in open {
+            self.close_file(file)?;
+        }
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+        let manifest = Manifest {
+            partition_columns: self.keys.iter().map(|k| k.name.clone()).collect(),
+            files: self.files,
+        };
+        let out = File::create(self.root.join(MANIFEST_FILE))?;
+        serde_json::to_writer_pretty(out, &manifest)?;
+        Ok(manifest)
+    }
+
+    fn partition_dir(&self, partition: &[String]) -> String {
+        self.keys
+            .iter()
+            .zip(partition)
+            .map(|(k, v)| format!("{}={}", k.name, escape_partition_value(v)))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn open_file(&mut self, dir: &str, partition: Vec<String>) -> Result<OpenFile<T>> {
+        let seq = self.next_part.entry(dir.to_string()).or_insert(0);
+        *seq += 1;
+        let name = format!("part-{seq:04}.parquet");
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{dir}/{name}")
+        };
+        let full = self.root.join(&path);
+        if let Some(parent) = full.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let builder = (self.new_builder)(self.export.batch_size);
+        let sink =
+            ParquetSink::try_with_builder(File::create(full)?, builder, self.export.clone())?;
+        Ok(OpenFile {
+            sink,
+            path,
+            partition,
+            last_write: 0,
+        })
+    }
+
+    fn close_least_recent(&mut self) -> Result<()> {
+        let dir = self
+            .open
+            .iter()
+            .min_by_key(|(_, f)| f.last_write)
+            .map(|(dir, _)| dir.clone());
+        if let Some(dir) = dir {
+            let file = self.open.remove(&dir).expect("partition file is open");
+            self.close_file(file)?;
+        }
+        Ok(())
+    }
+
+    fn close_file(&mut self, mut file: OpenFile<T>) -> Result<()> {
+        for (k, v) in &self.metadata {
+            file.sink.append_metadata(k.clone(), v.clone());
+        }
+        let meta = file.sink.close()?;
+        let bytes = fs::metadata(self.root.join(&file.path))?.len();
+        self.files.push(ManifestFile {
+            path: file.path,
+            partition: file.partition,
+            rows: meta.num_rows as u64,
+            bytes,
+        });
+        Ok(())
+    }
+}
+
+impl<T: AppendRecord> RecordSink<T> for PartitionedWriter<T> {
+    fn write(&mut self, record: &T) -> Result<()> {
+        PartitionedWriter::write(self, record)
+    }
+
+    fn append_metadata(&mut self, key: &str, value: &str) {
+        PartitionedWriter::append_metadata(self, key, value);
+    }
+
+    fn close(self: Box<Self>) -> Result<()> {
+        PartitionedWriter::close(*self)?;
+        Ok(())
+    }
+}
+
+/// Percent-encodes characters that are not safe in a Hive partition directory name.
+pub fn escape_partition_value(value: &str) -> String {
+    if value.is_empty() {
+        return "__HIVE_DEFAULT_PARTITION__".to_string();
+    }
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_control() || "\"#%'*/:=?\\\x7f{}[]^".contains(c) {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                let _ = write!(out, "%{b:02X}");
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::arrow_record! {
+        struct Hit {
+            day: String,
+            status: u16,
+        }
+    }
+
+    fn hit(day: &str, status: u16) -> Hit {
+        Hit {
+            day: day.to_string(),
+            status,
+        }
+    }
+
+    fn keys() -> Vec<PartitionKey<Hit>> {
+        vec![
+            PartitionKey::new("date", |h: &Hit| h.day.clone()),
+            PartitionKey::new("class", |h: &Hit| format!("{}xx", h.status / 100)),
+        ]
+    }
+
+    #[test]
+    fn test_escape_partition_value() {
+        assert_eq!(escape_partition_value("2015-05-17"), "2015-05-17");
+        assert_eq!(escape_partition_value("a/b=c"), "a%2Fb%3Dc");
+        assert_eq!(escape_partition_value(""), "__HIVE_DEFAULT_PARTITION__");
+    }
+
+    #[test]
+    fn test_partitioned_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = PartitionedWriter::try_new(
+            dir.path(),
+            keys(),
+            ExportOptions::default(),
+            PartitionOptions::default(),
+        )
+        .unwrap();
+        for h in [
+            hit("2015-05-17", 200),
+            hit("2015-05-17", 404),
+            hit("2015-05-18", 200),
+            hit("2015-05-17", 304),
+        ] {
+            writer.write(&h).unwrap();
+        }
+        let manifest = writer.close().unwrap();
+        assert_eq!(manifest.partition_columns, vec!["date", "class"]);
+        let files: Vec<_> = manifest
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), f.rows))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("date=2015-05-17/class=2xx/part-0001.parquet", 1),
+                ("date=2015-05-17/class=3xx/part-0001.parquet", 1),
+                ("date=2015-05-17/class=4xx/part-0001.parquet", 1),
+                ("date=2015-05-18/class=2xx/part-0001.parquet", 1),
+            ]
+        );
+        for f in &manifest.files {
+            assert!(dir.path().join(&f.path).exists());
+        }
+        assert_eq!(Manifest::read(dir.path()).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_partitioned_writer_rolls_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = PartitionOptions {
+            max_file_bytes: 1,
+            max_open_files: 1,
+        };
+        let mut writer =
+            PartitionedWriter::try_new(dir.path(), keys(), ExportOptions::default(), options)
+                .unwrap();
+        for h in [hit("2015-05-17", 200), hit("2015-05-17", 200)] {
+            writer.write(&h).unwrap();
+        }
+        let manifest = writer.close().unwrap();
+        let paths: Vec<_> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "date=2015-05-17/class=2xx/part-0001.parquet",
+                "date=2015-05-17/class=2xx/part-0002.parquet",
+            ]
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+
+use super::{ExportOptions, ParquetSink, RecordSink, METADATA_CHECKPOINT};
+use crate::{input::Checkpoint, AppendRecord, RecordBatchBuilder, ToArrowRecord};
+
+/// Writes an unbounded stream of records as a sequence of `part-000001.parquet` files.
+///
+/// A part is written under a hidden `.tmp` name and renamed when [`commit`](Self::commit)ed,
+/// so readers of the directory only ever see complete files.
+pub struct RollingWriter<T> {
+    dir: PathBuf,
+    new_builder: Box<dyn Fn(usize) -> RecordBatchBuilder<T> + Send + Sync>,
+    export: ExportOptions,
+    sink: Option<ParquetSink<File, T>>,
+    next_part: usize,
+    metadata: Vec<(String, String)>,
+}
+
+impl<T: ToArrowRecord + 'static> RollingWriter<T> {
+    pub fn try_new(dir: impl Into<PathBuf>, export: ExportOptions) -> Result<Self> {
+        Self::try_with_builder(dir, RecordBatchBuilder::with_capacity, export)
+    }
+}
+
+impl<T: AppendRecord> RollingWriter<T> {
+    /// Continues the numbering of parts already in `dir`, and drops uncommitted leftovers.
+    pub fn try_with_builder(
+        dir: impl Into<PathBuf>,
+        new_builder: impl Fn(usize) -> RecordBatchBuilder<T> + Send + Sync + 'static,
+        export: ExportOptions,
+    ) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut last = 0;
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.starts_with(".part-") && name.ends_with(".tmp") {
+                fs::remove_file(dir.join(&name))?;
+            } else if let Some(n) = part_number(&name) {
+                last = last.max(n);
+            }
+        }
+        Ok(Self {
+            dir,
+            new_builder: Box::new(new_builder),
+            export,
+            sink: None,
+            next_part: last + 1,
+            metadata: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, record: &T) -> Result<()> {
+        if self.sink.is_none() {
+            let file = File::create(self.tmp_path())?;
+            let builder = (self.new_builder)(self.export.batch_size);
+            let sink = ParquetSink::try_with_builder(file, builder, self.export.clone())?;
+            self.sink = Some(sink);
+        }
+        self.sink.as_mut().expect("opened above").write(record)
+    }
+
+    /// Metadata added to every part committed from now on.
+    pub fn append_metadata(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.metadata.push((key.into(), value.into()));
+    }
+
+    /// Rows written since the last commit.
+    pub fn pending_rows(&self) -> usize {
+        self.sink.as_ref().map_or(0, ParquetSink::rows)
+    }
+
+    /// Closes the current part and makes it visible, returning its path; `None` when
+    /// nothing was written since the last commit.
+    pub fn commit(&mut self) -> Result<Option<PathBuf>> {
+        self.commit_at(None)
+    }
+
+    /// Like [`commit`](Self::commit), recording in the part how far the followed log was read,
+    /// so the part and its checkpoint become visible together.
+    pub fn commit_at(&mut self, checkpoint: Option<&Checkpoint>) -> Result<Option<PathBuf>> {
+        let Some(mut sink) = self.sink.take() else {
+            return Ok(None);
+        };
+        for (k, v) in &self.metadata {
+            sink.append_metadata(k.clone(), v.clone());
+        }
+        if let Some(checkpoint) = checkpoint {
+            sink.append_metadata(METADATA_CHECKPOINT, serde_json::to_string(checkpoint)?);
+        }
+        sink.close()?;
+        let path = self.dir.join(format!("part-{:06}.parquet", self.next_part));
+        fs::rename(self.tmp_path(), &path)?;
+        self.next_part += 1;
+        Ok(Some(path))
+    }
+
+    pub fn close(mut self) -> Result<()> {
+        self.commit()?;
+        Ok(())
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        self.dir
+            .join(format!(".part-{:06}.parquet.tmp", self.next_part))
+    }
+}
+
+fn part_number(name: &str) -> Option<usize> {
+    name.strip_prefix("part-")?
+        .strip_suffix(".parquet")?
+        .parse()
+        .ok()
+}
+
+impl<T: AppendRecord> RecordSink<T> for RollingWriter<T> {
+    fn write(&mut self, record: &T) -> Result<()> {
+        RollingWriter::write(self, record)
+    }
+
+    fn append_metadata(&mut self, key: &str, value: &str) {
+        RollingWriter::append_metadata(self, key, value);
+    }
+
+    fn close(self: Box<Self>) -> Result<()> {
+        RollingWriter::close(*self)
+    }
+}
+
+/// The committed parts in `dir`, oldest first.
+pub fn committed_parts(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let mut parts = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some(n) = part_number(&name) {
+            parts.push((n, path));
+        }
+    }
+    parts.sort();
+    Ok(parts.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Where to resume following a log written to `dir`: the checkpoint recorded in the newest
+/// committed part when it is ahead of `saved`, as after a crash between committing a part
+/// and saving the checkpoint file.
+pub fn resume_checkpoint(
+    dir: impl AsRef<Path>,
+    saved: Option<Checkpoint>,
+) -> Result<Option<Checkpoint>> {
+    let Some(part) = committed_parts(dir)?.pop() else {
+        return Ok(saved);
+    };
+    let reader = SerializedFileReader::new(File::open(&part)?)?;
+    let committed = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .into_iter()
+        .flatten()
+        .find(|kv| kv.key == METADATA_CHECKPOINT)
+        .and_then(|kv| kv.value.as_deref())
+        .map(serde_json::from_str::<Checkpoint>)
+        .transpose()
+        .with_context(|| format!("invalid checkpoint in {}", part.display()))?;
+    Ok(match (saved, committed) {
+        (Some(saved), Some(committed)) if !committed.is_ahead_of(&saved) => Some(saved),
+        (saved, committed) => committed.or(saved),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::input::Tailer;
+
+    crate::arrow_record! {
+        struct Row {
+            id: u64,
+        }
+    }
+
+    fn rows(path: &Path) -> i64 {
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        reader.metadata().file_metadata().num_rows()
+    }
+
+    #[test]
+    fn test_commit_and_resume_numbering() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer =
+            RollingWriter::<Row>::try_new(dir.path(), ExportOptions::default()).unwrap();
+        assert_eq!(writer.commit().unwrap(), None);
+        for id in 0..3 {
+            writer.write(&Row { id }).unwrap();
+        }
+        assert_eq!(writer.pending_rows(), 3);
+        // not visible until committed
+        assert!(committed_parts(dir.path()).unwrap().is_empty());
+        let first = writer.commit().unwrap().unwrap();
+        assert_eq!(rows(&first), 3);
+        writer.write(&Row { id: 3 }).unwrap();
+        // dropped without a commit, as if the process died
+        drop(writer);
+
+        let mut writer =
+            RollingWriter::<Row>::try_new(dir.path(), ExportOptions::default()).unwrap();
+        writer.write(&Row { id: 4 }).unwrap();
+        writer.close().unwrap();
+        let parts = committed_parts(dir.path()).unwrap();
+        let names: Vec<_> = parts
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["part-000001.parquet", "part-000002.parquet"]);
+        assert_eq!(rows(&parts[1]), 1);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_resume_after_crash_before_checkpoint_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let log = dir.path().join("access.log");
+        let saved_file = dir.path().join("checkpoint.json");
+        let append = |text: &str| {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&log)
+                .unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        };
+        let mut writer = RollingWriter::<Row>::try_new(&out, ExportOptions::default()).unwrap();
+        let mut tailer = Tailer::new(&log);
+
+        append("0\n1\n");
+        while let Some(line) = tailer.poll().unwrap() {
+            writer
+                .write(&Row {
+                    id: line.parse().unwrap(),
+                })
+                .unwrap();
+        }
+        writer.commit_at(tailer.checkpoint().as_ref()).unwrap();
+        tailer.checkpoint().unwrap().save(&saved_file).unwrap();
+
+        append("2\n3\n");
+        while let Some(line) = tailer.poll().unwrap() {
+            writer
+                .write(&Row {
+                    id: line.parse().unwrap(),
+                })
+                .unwrap();
+        }
+        writer.commit_at(tailer.checkpoint().as_ref()).unwrap();
+        // the process dies here, before the checkpoint file is saved
+        drop(writer);
+        append("4\n");
+
+        let saved = Checkpoint::load(&saved_file).unwrap();
+        let resumed = resume_checkpoint(&out, saved.clone()).unwrap().unwrap();
+        assert!(resumed.is_ahead_of(saved.as_ref().unwrap()));
+        let mut tailer = Tailer::resume(&log, Some(&resumed)).unwrap();
+        assert_eq!(tailer.poll().unwrap().as_deref(), Some("4"));
+        assert_eq!(tailer.poll().unwrap(), None);
+
+        // a saved checkpoint ahead of the parts wins, as when the last lines were all rejected
+        let ahead = tailer.checkpoint().unwrap();
+        assert_eq!(
+            resume_checkpoint(&out, Some(ahead.clone())).unwrap(),
+            Some(ahead)
+        );
+    }
+
+    #[test]
+    fn test_resume_after_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let log = dir.path().join("access.log");
+        let saved_file = dir.path().join("checkpoint.json");
+        let mut writer = RollingWriter::<Row>::try_new(&out, ExportOptions::default()).unwrap();
+        let mut tailer = Tailer::new(&log);
+
+        fs::write(&log, "0\n1").unwrap();
+        assert_eq!(tailer.poll().unwrap().as_deref(), Some("0"));
+        assert_eq!(tailer.poll().unwrap(), None);
+        fs::rename(&log, dir.path().join("access.log.1")).unwrap();
+        fs::write(&log, "2\n").unwrap();
+        // the rotated file's unterminated last line, with the new file not opened yet
+        assert_eq!(tailer.poll().unwrap().as_deref(), Some("1"));
+        writer.write(&Row { id: 0 }).unwrap();
+        writer.write(&Row { id: 1 }).unwrap();
+        let checkpoint = tailer.checkpoint().expect("the rotated file's end");
+        assert_eq!((checkpoint.offset, checkpoint.line), (3, 2));
+        writer.commit_at(Some(&checkpoint)).unwrap();
+        checkpoint.save(&saved_file).unwrap();
+        drop(writer);
+
+        let saved = Checkpoint::load(&saved_file).unwrap();
+        let resumed = resume_checkpoint(&out, saved).unwrap();
+        let mut tailer = Tailer::resume(&log, resumed.as_ref()).unwrap();
+        assert_eq!(tailer.poll().unwrap().as_deref(), Some("2"));
+        assert_eq!(tailer.poll().unwrap(), None);
+        assert_eq!(tailer.checkpoint().unwrap().line, 1);
+    }
+}
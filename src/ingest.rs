@@ -0,0 +1,283 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Result};
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use winnow::error::{ContextError, ErrMode, StrContext};
+
+pub mod parallel;
+
+pub use parallel::{ParallelOptions, ParallelParser, ParsedChunk};
+
+/// Why a line could not be parsed; `kind` names the field that failed, e.g. `datetime`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineError {
+    pub kind: String,
+    pub message: String,
+}
+
+impl LineError {
+    pub fn new(kind: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for LineError {}
+
+/// Takes the kind from the outermost `StrContext::Label`, or `syntax` when there is none.
+impl From<ErrMode<ContextError>> for LineError {
+    fn from(err: ErrMode<ContextError>) -> Self {
+        let err = match err {
+            ErrMode::Backtrack(e) | ErrMode::Cut(e) => e,
+            ErrMode::Incomplete(_) => return LineError::new("incomplete", "line is truncated"),
+        };
+        let kind = err
+            .context()
+            .filter_map(|c| match c {
+                StrContext::Label(label) => Some(*label),
+                _ => None,
+            })
+            .last()
+            .unwrap_or("syntax");
+        let message = err.to_string();
+        let message = if message.is_empty() {
+            format!("invalid {kind}")
+        } else {
+            message
+        };
+        LineError::new(kind, message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ErrorPolicy {
+    /// Abort the import on the first malformed line.
+    #[default]
+    FailFast,
+    /// Count malformed lines and carry on.
+    Skip,
+    /// Like `skip`, and also write each malformed line to the quarantine file.
+    Quarantine,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct ErrorOptions {
+    /// What to do with lines that do not parse.
+    #[arg(long, value_enum, default_value_t = ErrorPolicy::FailFast)]
+    pub on_error: ErrorPolicy,
+    /// JSON lines file receiving malformed lines; implies `--on-error quarantine`.
+    #[arg(long)]
+    pub quarantine: Option<PathBuf>,
+}
+
+impl ErrorOptions {
+    pub fn error_sink(&self) -> Result<ErrorSink> {
+        match (&self.quarantine, self.on_error) {
+            (Some(path), ErrorPolicy::FailFast | ErrorPolicy::Quarantine) => {
+                let out = BufWriter::new(File::create(path)?);
+                Ok(ErrorSink::quarantine(out))
+            }
+            (Some(_), ErrorPolicy::Skip) => {
+                bail!("--quarantine cannot be used with --on-error skip")
+            }
+            (None, ErrorPolicy::Quarantine) => {
+                bail!("--on-error quarantine needs --quarantine <PATH>")
+            }
+            (None, policy) => Ok(ErrorSink::new(policy)),
+        }
+    }
+}
+
+/// Counts of parsed and skipped lines, the latter per error kind.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ParseStats {
+    pub parsed: u64,
+    pub skipped: u64,
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl fmt::Display for ParseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "parsed {} lines, skipped {}", self.parsed, self.skipped)?;
+        for (kind, count) in &self.errors {
+            write!(f, "\n  {kind}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct QuarantinedLine<'a> {
+    source: &'a str,
+    line: u64,
+    kind: &'a str,
+    reason: &'a str,
+    raw: &'a str,
+}
+
+/// Applies an [`ErrorPolicy`] to parse results and keeps the [`ParseStats`].
+pub struct ErrorSink {
+    policy: ErrorPolicy,
+    quarantine: Option<Box<dyn Write + Send>>,
+    stats: ParseStats,
+}
+
+impl ErrorSink {
+    pub fn new(policy: ErrorPolicy) -> Self {
+        Self {
+            policy,
+            quarantine: None,
+            stats: ParseStats::default(),
+        }
+    }
+
+    pub fn quarantine(out: impl Write + Send + 'static) -> Self {
+        Self {
+            policy: ErrorPolicy::Quarantine,
+            quarantine: Some(Box::new(out)),
+            stats: ParseStats::default(),
+        }
+    }
+
+    /// Returns the record to write, `None` for a skipped line, or the error under fail-fast.
+    /// `line` is 1-based within `source`.
+    pub fn handle<T>(
+        &mut self,
+        source: &str,
+        line: u64,
+        raw: &str,
+        result: Result<T, LineError>,
+    ) -> Result<Option<T>> {
+        let err = match result {
+            Ok(record) => {
+                self.stats.parsed += 1;
+                return Ok(Some(record));
+            }
+            Err(err) => err,
+        };
+        if self.policy == ErrorPolicy::FailFast {
+            bail!("{source}:{line}: {err}");
+        }
+        self.stats.skipped += 1;
+        *self.stats.errors.entry(err.kind.clone()).or_default() += 1;
+        if let Some(out) = self.quarantine.as_mut() {
+            let entry = QuarantinedLine {
+                source,
+                line,
+                kind: &err.kind,
+                reason: &err.message,
+                raw,
+            };
+            serde_json::to_writer(&mut *out, &entry)?;
+            out.write_all(b"\n")?;
+        }
+        Ok(None)
+    }
+
+    pub fn stats(&self) -> &ParseStats {
+        &self.stats
+    }
+
+    pub fn finish(mut self) -> Result<ParseStats> {
+        if let Some(out) = self.quarantine.as_mut() {
+            out.flush()?;
+        }
+        Ok(self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn lines() -> Vec<(&'static str, Result<u32, LineError>)> {
+        vec![
+            ("1", Ok(1)),
+            ("\x16\x03\x01", Err(LineError::new("ip", "invalid ip"))),
+            ("2", Ok(2)),
+            ("GET /", Err(LineError::new("ip", "invalid ip"))),
+            (
+                "x - - [",
+                Err(LineError::new("datetime", "invalid datetime")),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let mut sink = ErrorSink::new(ErrorPolicy::FailFast);
+        let mut lines = lines().into_iter();
+        let (raw, result) = lines.next().unwrap();
+        assert_eq!(sink.handle("access.log", 1, raw, result).unwrap(), Some(1));
+        let (raw, result) = lines.next().unwrap();
+        let err = sink.handle("access.log", 2, raw, result).unwrap_err();
+        assert_eq!(err.to_string(), "access.log:2: ip: invalid ip");
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let out = Shared::default();
+        let mut sink = ErrorSink::quarantine(out.clone());
+        let mut records = Vec::new();
+        for (i, (raw, result)) in lines().into_iter().enumerate() {
+            let line = i as u64 + 1;
+            records.extend(sink.handle("access.log.1", line, raw, result).unwrap());
+        }
+        assert_eq!(records, vec![1, 2]);
+
+        let stats = sink.finish().unwrap();
+        assert_eq!(stats.parsed, 2);
+        assert_eq!(stats.skipped, 3);
+        assert_eq!(
+            stats.errors.into_iter().collect::<Vec<_>>(),
+            vec![("datetime".to_string(), 1), ("ip".to_string(), 2)]
+        );
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let first: serde_json::Value = serde_json::from_str(out.lines().next().unwrap()).unwrap();
+        assert_eq!(first["source"], "access.log.1");
+        assert_eq!(first["line"], 2);
+        assert_eq!(first["kind"], "ip");
+        assert_eq!(first["raw"], "\x16\x03\x01");
+        assert_eq!(out.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_error_options() {
+        let options = ErrorOptions {
+            on_error: ErrorPolicy::Quarantine,
+            quarantine: None,
+        };
+        assert!(options.error_sink().is_err());
+    }
+}
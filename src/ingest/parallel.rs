@@ -0,0 +1,333 @@
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Result};
+use clap::Args;
+
+use super::LineError;
+
+#[derive(Debug, Clone, Args)]
+pub struct ParallelOptions {
+    /// Parser threads; 0 uses every core.
+    #[arg(long, default_value_t = 0)]
+    pub threads: usize,
+    /// Approximate size of the newline-aligned chunks handed to each thread.
+    #[arg(long, default_value_t = 256 * 1024)]
+    pub chunk_bytes: usize,
+}
+
+impl Default for ParallelOptions {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            chunk_bytes: 256 * 1024,
+        }
+    }
+}
+
+impl ParallelOptions {
+    pub fn threads(&self) -> usize {
+        match self.threads {
+            0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            n => n,
+        }
+    }
+}
+
+/// The lines of one chunk with their parse results, in input order.
+pub struct ParsedChunk<T> {
+    /// The name given to [`ParallelParser::start_source`].
+    pub source: Arc<str>,
+    /// 1-based number of the chunk's first line within its source.
+    pub first_line: u64,
+    text: String,
+    results: Vec<Result<T, LineError>>,
+}
+
+impl<T> ParsedChunk<T> {
+    /// Calls `f` with the source, the line number, the raw line and its parse result, line by
+    /// line.
+    pub fn for_each(
+        self,
+        mut f: impl FnMut(&str, u64, &str, Result<T, LineError>) -> Result<()>,
+    ) -> Result<()> {
+        let lines = self.text.split('\n').zip(self.results);
+        for (line, (raw, result)) in (self.first_line..).zip(lines) {
+            f(&self.source, line, raw, result)?;
+        }
+        Ok(())
+    }
+}
+
+type Job = (u64, String);
+/// A chunk's parse results, or the payload of a panic while parsing it.
+type Results<T> = thread::Result<Vec<Result<T, LineError>>>;
+type Parsed<T> = (u64, String, Results<T>);
+
+/// Parses newline-separated chunks on a pool of threads and hands them back in input order.
+///
+/// At most twice as many chunks as threads are in flight, so memory stays bounded however
+/// large the input is.
+pub struct ParallelParser<T> {
+    jobs: Option<SyncSender<Job>>,
+    results: Receiver<Parsed<T>>,
+    workers: Vec<JoinHandle<()>>,
+    /// Source and first line of every chunk not emitted yet, by sequence number.
+    submitted: BTreeMap<u64, (Arc<str>, u64)>,
+    finished: BTreeMap<u64, (String, Results<T>)>,
+    next_seq: u64,
+    next_emit: u64,
+    source: Arc<str>,
+    next_line: u64,
+    window: usize,
+}
+
+impl<T: Send + 'static> ParallelParser<T> {
+    pub fn new(
+        threads: usize,
+        parse: impl Fn(&str) -> Result<T, LineError> + Send + Sync + 'static,
+    ) -> Self {
+        let threads = threads.max(1);
+        let (jobs, job_rx) = mpsc::sync_channel::<Job>(threads);
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let (result_tx, results) = mpsc::channel();
+        let parse = Arc::new(parse);
+        let workers = (0..threads)
+            .map(|_| {
+                let job_rx = job_rx.clone();
+                let result_tx = result_tx.clone();
+                let parse = parse.clone();
+                thread::spawn(move || loop {
+                    let job = job_rx.lock().expect("job queue poisoned").recv();
+                    let Ok((seq, text)) = job else {
+                        return;
+                    };
+                    // a panic is passed on rather than ending the thread, which would leave the
+                    // chunk's result missing and the caller waiting for it
+                    let parsed = panic::catch_unwind(AssertUnwindSafe(|| {
+                        text.split('\n').map(|line| parse(line)).collect()
+                    }));
+                    if result_tx.send((seq, text, parsed)).is_err() {
+                        return;
+                    }
+                })
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+            submitted: BTreeMap::new(),
+            finished: BTreeMap::new(),
+            next_seq: 0,
+            next_emit: 0,
+            source: Arc::from(""),
+            next_line: 1,
+            window: threads * 2,
+        }
+    }
+
+    /// Numbers the lines submitted from now on from 1 again, as coming from `name`.
+    pub fn start_source(&mut self, name: &str) {
+        self.source = Arc::from(name);
+        self.next_line = 1;
+    }
+
+    /// Queues `text`, lines separated by `\n` without a trailing one, and passes every chunk
+    /// that is complete and next in order to `emit`.
+    pub fn submit(
+        &mut self,
+        text: String,
+        emit: &mut impl FnMut(ParsedChunk<T>) -> Result<()>,
+    ) -> Result<()> {
+        while self.submitted.len() >= self.window {
+            self.emit_next(emit)?;
+        }
+        let lines = text.split('\n').count() as u64;
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.submitted
+            .insert(seq, (self.source.clone(), self.next_line));
+        self.next_line += lines;
+        self.jobs
+            .as_ref()
+            .expect("parser is open")
+            .send((seq, text))
+            .map_err(|_| anyhow!("parser threads exited"))?;
+        // pass on whatever is already done without waiting
+        while let Ok((seq, text, parsed)) = self.results.try_recv() {
+            self.finished.insert(seq, (text, parsed));
+        }
+        while self.finished.contains_key(&self.next_emit) {
+            self.emit_next(emit)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the remaining chunks and stops the threads.
+    pub fn finish(mut self, emit: &mut impl FnMut(ParsedChunk<T>) -> Result<()>) -> Result<()> {
+        self.jobs = None;
+        while !self.submitted.is_empty() {
+            self.emit_next(emit)?;
+        }
+        for worker in self.workers.drain(..) {
+            worker
+                .join()
+                .map_err(|_| anyhow!("parser thread panicked"))?;
+        }
+        Ok(())
+    }
+
+    /// Blocks until the oldest submitted chunk is parsed, then emits it.
+    fn emit_next(&mut self, emit: &mut impl FnMut(ParsedChunk<T>) -> Result<()>) -> Result<()> {
+        let (text, results) = loop {
+            if let Some(parsed) = self.finished.remove(&self.next_emit) {
+                break parsed;
+            }
+            let (seq, text, parsed) = self
+                .results
+                .recv()
+                .map_err(|_| anyhow!("parser thread panicked"))?;
+            self.finished.insert(seq, (text, parsed));
+        };
+        let (source, first_line) = self
+            .submitted
+            .remove(&self.next_emit)
+            .expect("emitted chunks were submitted");
+        self.next_emit += 1;
+        let results = results.map_err(|payload| {
+            anyhow!(
+                "{source}:{first_line}: parser panicked: {}",
+                panic_message(payload.as_ref())
+            )
+        })?;
+        emit(ParsedChunk {
+            source,
+            first_line,
+            text,
+            results,
+        })
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload.downcast_ref::<String>().map_or("", String::as_str),
+    }
+}
+
+impl<T> Drop for ParallelParser<T> {
+    fn drop(&mut self) {
+        // closing the queue lets idle workers exit
+        self.jobs = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<u64, LineError> {
+        line.parse().map_err(|_| LineError::new("number", line))
+    }
+
+    #[test]
+    fn test_results_in_order() {
+        let input: Vec<String> = (1..=1000).map(|i| i.to_string()).collect();
+        let mut parser = ParallelParser::new(4, parse);
+        let mut seen = Vec::new();
+        let mut emit = |chunk: ParsedChunk<u64>| {
+            chunk.for_each(|_, _, raw, result| {
+                assert_eq!(raw.parse::<u64>().ok(), result.as_ref().ok().copied());
+                seen.push(result?);
+                Ok(())
+            })
+        };
+        // uneven chunks, so threads finish out of order
+        for (i, lines) in input.chunks(7).enumerate() {
+            let lines = if i % 3 == 0 { &lines[..1] } else { lines };
+            let lines = lines.to_vec();
+            parser.submit(lines.join("\n"), &mut emit).unwrap();
+        }
+        parser.finish(&mut emit).unwrap();
+        let expected: Vec<u64> = input
+            .chunks(7)
+            .enumerate()
+            .flat_map(|(i, lines)| if i % 3 == 0 { &lines[..1] } else { lines })
+            .map(|l| l.parse().unwrap())
+            .collect();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_errors_keep_line_numbers() {
+        let mut parser = ParallelParser::new(2, parse);
+        let mut errors = Vec::new();
+        let mut emit = |chunk: ParsedChunk<u64>| {
+            chunk.for_each(|source, line, raw, result| {
+                if let Err(err) = result {
+                    errors.push((source.to_string(), line, raw.to_string(), err.kind));
+                }
+                Ok(())
+            })
+        };
+        parser.start_source("access.log.1");
+        parser.submit("1\n2\nx".to_string(), &mut emit).unwrap();
+        parser.submit("4\n\n6".to_string(), &mut emit).unwrap();
+        parser.start_source("access.log");
+        parser.submit("y\n8".to_string(), &mut emit).unwrap();
+        parser.finish(&mut emit).unwrap();
+        let error = |source: &str, line, raw: &str| {
+            (
+                source.to_string(),
+                line,
+                raw.to_string(),
+                "number".to_string(),
+            )
+        };
+        assert_eq!(
+            errors,
+            vec![
+                error("access.log.1", 3, "x"),
+                error("access.log.1", 5, ""),
+                // numbering restarts with each source
+                error("access.log", 1, "y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_panicking_parser() {
+        let mut parser = ParallelParser::new(2, |line: &str| {
+            if line == "boom" {
+                panic!("cannot parse {line}");
+            }
+            parse(line)
+        });
+        let mut emit = |chunk: ParsedChunk<u64>| chunk.for_each(|_, _, _, _| Ok(()));
+        parser.start_source("access.log");
+        // more chunks than the window, so a lost result would block `submit` for good
+        let submitted = (0..20).try_for_each(|i| {
+            let text = if i == 3 { "1\nboom" } else { "1\n2" };
+            parser.submit(text.to_string(), &mut emit)
+        });
+        let err = submitted
+            .and_then(|()| parser.finish(&mut emit))
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("access.log:7: parser panicked: cannot parse boom"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+
+pub mod chunked;
+pub mod http;
+pub mod tail;
+
+pub use chunked::{ChunkedLines, StreamLines};
+pub use http::{report_progress, HttpLines, HttpOptions, Progress};
+pub use tail::{Checkpoint, FollowOptions, Tailer};
+
+/// Where log lines come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// `-`
+    Stdin,
+    File(PathBuf),
+    Http(String),
+}
+
+impl Source {
+    /// Reads `-` as stdin and `http://`/`https://` as a URL, anything else is a path.
+    pub fn parse(spec: &str) -> Self {
+        if spec == "-" {
+            Source::Stdin
+        } else if spec.starts_with("http://") || spec.starts_with("https://") {
+            Source::Http(spec.to_string())
+        } else {
+            Source::File(spec.into())
+        }
+    }
+
+    /// Opens the source, decompressing gzip, zstd and bzip2 content whatever the name says.
+    pub async fn open(&self, http: &HttpOptions) -> Result<LineReader> {
+        let lines = match self {
+            Source::Stdin => {
+                BlockingLines::open(|| Ok(Box::new(BufReader::new(io::stdin())))).await?
+            }
+            Source::File(path) => {
+                let path = path.clone();
+                BlockingLines::open(move || {
+                    let file = File::open(&path)
+                        .with_context(|| format!("cannot open {}", path.display()))?;
+                    Ok(Box::new(BufReader::new(file)))
+                })
+                .await?
+            }
+            Source::Http(url) => {
+                let lines = HttpLines::connect(url, http.clone()).await?;
+                return Ok(LineReader::Http(Box::new(lines)));
+            }
+        };
+        Ok(LineReader::Read(Box::new(lines)))
+    }
+}
+
+/// Lines of an opened [`Source`]; remote logs are streamed rather than read up front.
+pub enum LineReader {
+    Read(Box<BlockingLines>),
+    Stream(Box<StreamLines>),
+    Http(Box<HttpLines>),
+}
+
+impl LineReader {
+    pub async fn next_line(&mut self) -> Result<Option<String>> {
+        match self {
+            LineReader::Read(lines) => lines.next_line().await,
+            LineReader::Stream(lines) => lines.next_line().await,
+            LineReader::Http(lines) => lines.next_line().await,
+        }
+    }
+
+    /// Joins lines with `\n` until there are about `target_bytes`, for
+    /// [`ParallelParser`](crate::ingest::ParallelParser); `None` at the end of input.
+    pub async fn next_chunk(&mut self, target_bytes: usize) -> Result<Option<String>> {
+        let Some(mut chunk) = self.next_line().await? else {
+            return Ok(None);
+        };
+        while chunk.len() < target_bytes {
+            let Some(line) = self.next_line().await? else {
+                break;
+            };
+            chunk.push('\n');
+            chunk.push_str(&line);
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Calls `f` as a download advances; files and stdin report nothing.
+    pub fn on_progress(&mut self, f: impl FnMut(&Progress) + Send + 'static) {
+        if let LineReader::Http(lines) = self {
+            lines.stream_mut().on_progress(f);
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Stdin => write!(f, "-"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Http(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Bytes [`sniff`](Self::sniff) needs to tell every format apart.
+    pub const MAGIC_LEN: usize = 4;
+
+    pub fn sniff(magic: &[u8]) -> Self {
+        match magic {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// How much [`BlockingLines`] reads per trip to the blocking pool.
+const BLOCKING_READ_BYTES: usize = 64 * 1024;
+
+/// Lines of a file or stdin, read a batch at a time on tokio's blocking pool so the reads
+/// do not stall the async workers.
+pub struct BlockingLines {
+    reader: Option<Box<dyn BufRead + Send>>,
+    lines: VecDeque<String>,
+}
+
+impl BlockingLines {
+    /// Calls `open` and sniffs the compression on the blocking pool.
+    pub async fn open(
+        open: impl FnOnce() -> Result<Box<dyn BufRead + Send>> + Send + 'static,
+    ) -> Result<Self> {
+        let reader = tokio::task::spawn_blocking(move || decompress(open()?)).await??;
+        Ok(Self {
+            reader: Some(reader),
+            lines: VecDeque::new(),
+        })
+    }
+
+    pub async fn next_line(&mut self) -> Result<Option<String>> {
+        if self.lines.is_empty() {
+            // taken while reading, so an error leaves the input finished
+            let Some(mut reader) = self.reader.take() else {
+                return Ok(None);
+            };
+            let (lines, reader) = tokio::task::spawn_blocking(move || {
+                let (lines, eof) = read_lines(&mut reader, BLOCKING_READ_BYTES)?;
+                anyhow::Ok((lines, (!eof).then_some(reader)))
+            })
+            .await??;
+            self.lines = lines;
+            self.reader = reader;
+        }
+        Ok(self.lines.pop_front())
+    }
+}
+
+/// Reads lines until about `target_bytes` or the end of input, which the flag reports.
+fn read_lines(reader: &mut dyn BufRead, target_bytes: usize) -> Result<(VecDeque<String>, bool)> {
+    let mut lines = VecDeque::new();
+    let mut bytes = 0;
+    while bytes < target_bytes {
+        let mut line = Vec::new();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok((lines, true));
+        }
+        bytes += line.len();
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        lines.push_back(decode_line(line));
+    }
+    Ok((lines, false))
+}
+
+/// Wraps `reader` in the decoder its first bytes call for.
+pub fn decompress(mut reader: Box<dyn BufRead + Send>) -> Result<Box<dyn BufRead + Send>> {
+    // a pipe can hand out fewer bytes per read than the magic takes
+    let mut magic = Vec::with_capacity(Compression::MAGIC_LEN);
+    while magic.len() < Compression::MAGIC_LEN {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let n = buf.len().min(Compression::MAGIC_LEN - magic.len());
+        magic.extend_from_slice(&buf[..n]);
+        reader.consume(n);
+    }
+    let compression = Compression::sniff(&magic);
+    let reader: Box<dyn BufRead + Send> = Box::new(io::Cursor::new(magic).chain(reader));
+    let reader: Box<dyn BufRead + Send> = match compression {
+        Compression::None => reader,
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+        Compression::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+    };
+    Ok(reader)
+}
+
+/// Expands globs and turns every spec into a [`Source`].
+///
+/// The files a glob matches are put in chronological order, so `access.log*` reads
+/// `access.log.3.gz`, `access.log.2.gz`, `access.log.1` and then `access.log`.
+pub fn expand(specs: &[String]) -> Result<Vec<Source>> {
+    let mut sources = Vec::new();
+    for spec in specs {
+        let source = Source::parse(spec);
+        if !matches!(source, Source::File(_)) || !spec.contains(['*', '?', '[']) {
+            sources.push(source);
+            continue;
+        }
+        let mut paths = glob::glob(spec)?
+            .filter(|entry| entry.as_ref().map_or(true, |path| path.is_file()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if paths.is_empty() {
+            bail!("no files match {spec}");
+        }
+        paths.sort_by_cached_key(|path| rotation_key(path));
+        sources.extend(paths.into_iter().map(Source::File));
+    }
+    Ok(sources)
+}
+
+/// Where a file sits in a logrotate sequence, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Rotation {
+    /// `access.log.2.gz`, higher numbers are older.
+    Numbered(Reverse<u32>),
+    /// `access.log-20150517.gz` with `dateext`.
+    Dated(String),
+    Current,
+}
+
+fn rotation_key(path: &Path) -> (String, Rotation) {
+    let name = path.to_string_lossy();
+    let name = ["gz", "zst", "bz2"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext)?.strip_suffix('.'))
+        .unwrap_or(&name);
+    if let Some((base, n)) = name.rsplit_once('.') {
+        if let Ok(n) = n.parse() {
+            return (base.to_string(), Rotation::Numbered(Reverse(n)));
+        }
+    }
+    if let Some((base, date)) = name.rsplit_once('-') {
+        if date.len() >= 8 && date.bytes().all(|b| b.is_ascii_digit()) {
+            return (base.to_string(), Rotation::Dated(date.to_string()));
+        }
+    }
+    (name.to_string(), Rotation::Current)
+}
+
+/// Drops a trailing `\r` and replaces invalid UTF-8.
+fn decode_line(mut line: Vec<u8>) -> String {
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const LINES: &str = "first\r\nsecond\n";
+
+    fn write(dir: &Path, name: &str, compression: Compression) -> PathBuf {
+        let path = dir.join(name);
+        let file = File::create(&path).unwrap();
+        let mut out: Box<dyn Write> = match compression {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(flate2::write::GzEncoder::new(
+                file,
+                flate2::Compression::default(),
+            )),
+            Compression::Zstd => Box::new(zstd::Encoder::new(file, 0).unwrap().auto_finish()),
+            Compression::Bzip2 => Box::new(bzip2::write::BzEncoder::new(
+                file,
+                bzip2::Compression::default(),
+            )),
+        };
+        out.write_all(format!("{name}\n{LINES}").as_bytes())
+            .unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_decompress() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, compression) in [
+            ("plain.log", Compression::None),
+            ("a.log.gz", Compression::Gzip),
+            ("a.log.zst", Compression::Zstd),
+            ("a.log.bz2", Compression::Bzip2),
+            // named for neither, sniffed anyway
+            ("a.log.1", Compression::Gzip),
+        ] {
+            let path = write(dir.path(), name, compression);
+            let mut reader = Source::File(path)
+                .open(&HttpOptions::default())
+                .await
+                .unwrap();
+            let mut lines = Vec::new();
+            while let Some(line) = reader.next_line().await.unwrap() {
+                lines.push(line);
+            }
+            assert_eq!(lines, vec![name, "first", "second"]);
+        }
+    }
+
+    #[test]
+    fn test_decompress_sniffs_short_reads() {
+        /// Hands out one byte per read, like a slow pipe.
+        struct Trickle(io::Cursor<Vec<u8>>);
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = buf.len().min(1);
+                self.0.read(&mut buf[..n])
+            }
+        }
+
+        let body = zstd::encode_all(LINES.as_bytes(), 0).unwrap();
+        let reader = BufReader::with_capacity(1, Trickle(io::Cursor::new(body)));
+        let mut text = String::new();
+        decompress(Box::new(reader))
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, LINES);
+    }
+
+    #[test]
+    fn test_expand_rotated() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "access.log",
+            "access.log.1",
+            "access.log.2.gz",
+            "access.log.10.gz",
+            "error.log",
+        ] {
+            write(dir.path(), name, Compression::None);
+        }
+        let pattern = format!("{}/access.log*", dir.path().display());
+        let names: Vec<_> = expand(&[pattern, "-".to_string()])
+            .unwrap()
+            .into_iter()
+            .map(|s| match s {
+                Source::File(path) => path.file_name().unwrap().to_string_lossy().into_owned(),
+                s => s.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "access.log.10.gz",
+                "access.log.2.gz",
+                "access.log.1",
+                "access.log",
+                "-"
+            ]
+        );
+
+        let missing = format!("{}/nothing*", dir.path().display());
+        assert!(expand(&[missing]).is_err());
+    }
+
+    #[test]
+    fn test_rotation_key() {
+        let mut paths = [
+            "access.log",
+            "access.log-20150518.gz",
+            "access.log-20150517",
+        ]
+        .map(PathBuf::from);
+        paths.sort_by_key(|p| rotation_key(p));
+        assert_eq!(
+            paths,
+            [
+                "access.log-20150517",
+                "access.log-20150518.gz",
+                "access.log"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(Source::parse("-"), Source::Stdin);
+        assert_eq!(
+            Source::parse("https://example.com/access.log"),
+            Source::Http("https://example.com/access.log".to_string())
+        );
+        assert_eq!(
+            Source::parse("/var/log/nginx/access.log"),
+            Source::File("/var/log/nginx/access.log".into())
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+
+use super::Compression;
+
+/// Cuts a byte stream into lines, holding on to a partial line until the rest arrives.
+#[derive(Debug, Default)]
+pub struct LineFramer {
+    partial: Vec<u8>,
+}
+
+impl LineFramer {
+    pub fn push(&mut self, mut chunk: &[u8], lines: &mut VecDeque<String>) {
+        while let Some(end) = chunk.iter().position(|&b| b == b'\n') {
+            self.partial.extend_from_slice(&chunk[..end]);
+            lines.push_back(super::decode_line(std::mem::take(&mut self.partial)));
+            chunk = &chunk[end + 1..];
+        }
+        self.partial.extend_from_slice(chunk);
+    }
+
+    /// The last line, when the stream does not end with a newline.
+    pub fn finish(&mut self) -> Option<String> {
+        (!self.partial.is_empty()).then(|| super::decode_line(std::mem::take(&mut self.partial)))
+    }
+}
+
+/// Push-style decompression, since the body arrives one chunk at a time.
+enum Decoder {
+    None,
+    Gzip(flate2::write::MultiGzDecoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+    Bzip2(bzip2::write::BzDecoder<Vec<u8>>),
+}
+
+impl Decoder {
+    fn new(compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Decoder::None,
+            Compression::Gzip => Decoder::Gzip(flate2::write::MultiGzDecoder::new(Vec::new())),
+            Compression::Zstd => Decoder::Zstd(zstd::stream::write::Decoder::new(Vec::new())?),
+            Compression::Bzip2 => Decoder::Bzip2(bzip2::write::BzDecoder::new(Vec::new())),
+        })
+    }
+
+    fn decode(
+        &mut self,
+        chunk: &[u8],
+        framer: &mut LineFramer,
+        lines: &mut VecDeque<String>,
+    ) -> io::Result<()> {
+        let out = match self {
+            Decoder::None => {
+                framer.push(chunk, lines);
+                return Ok(());
+            }
+            Decoder::Gzip(d) => {
+                d.write_all(chunk)?;
+                d.get_mut()
+            }
+            Decoder::Zstd(d) => {
+                d.write_all(chunk)?;
+                d.flush()?;
+                d.get_mut()
+            }
+            Decoder::Bzip2(d) => {
+                d.write_all(chunk)?;
+                d.get_mut()
+            }
+        };
+        framer.push(out, lines);
+        out.clear();
+        Ok(())
+    }
+
+    fn finish(&mut self, framer: &mut LineFramer, lines: &mut VecDeque<String>) -> io::Result<()> {
+        let out = match self {
+            Decoder::None => return Ok(()),
+            Decoder::Gzip(d) => {
+                d.try_finish()?;
+                d.get_mut()
+            }
+            Decoder::Zstd(d) => {
+                d.flush()?;
+                d.get_mut()
+            }
+            Decoder::Bzip2(d) => {
+                d.try_finish()?;
+                d.get_mut()
+            }
+        };
+        framer.push(out, lines);
+        out.clear();
+        Ok(())
+    }
+}
+
+/// Lines of a body that arrives in chunks, decompressed as they come.
+#[derive(Default)]
+pub struct ChunkedLines {
+    decoder: Option<Decoder>,
+    /// The first bytes of the body, held until there are enough to sniff.
+    magic: Vec<u8>,
+    framer: LineFramer,
+    lines: VecDeque<String>,
+}
+
+impl ChunkedLines {
+    pub fn push(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if let Some(decoder) = &mut self.decoder {
+            return decoder.decode(chunk, &mut self.framer, &mut self.lines);
+        }
+        // sniffed once, from the first bytes of the body, however thinly it is chunked
+        self.magic.extend_from_slice(chunk);
+        if self.magic.len() >= Compression::MAGIC_LEN {
+            self.start_decoder()?;
+        }
+        Ok(())
+    }
+
+    fn start_decoder(&mut self) -> io::Result<()> {
+        let magic = std::mem::take(&mut self.magic);
+        let decoder = self
+            .decoder
+            .insert(Decoder::new(Compression::sniff(&magic))?);
+        decoder.decode(&magic, &mut self.framer, &mut self.lines)
+    }
+
+    /// Flushes the decoder and a last line without a newline, at the end of the body.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.decoder.is_none() {
+            // a body shorter than the magic bytes
+            self.start_decoder()?;
+        }
+        let decoder = self.decoder.as_mut().expect("started above");
+        decoder.finish(&mut self.framer, &mut self.lines)?;
+        self.lines.extend(self.framer.finish());
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<String> {
+        self.lines.pop_front()
+    }
+}
+
+/// Lines of a stream of byte chunks, such as an object store download.
+pub struct StreamLines {
+    chunks: BoxStream<'static, Result<Bytes>>,
+    lines: ChunkedLines,
+    done: bool,
+}
+
+impl StreamLines {
+    pub fn new(chunks: BoxStream<'static, Result<Bytes>>) -> Self {
+        Self {
+            chunks,
+            lines: ChunkedLines::default(),
+            done: false,
+        }
+    }
+
+    pub async fn next_line(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(line) = self.lines.pop() {
+                return Ok(Some(line));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            match self.chunks.next().await {
+                Some(chunk) => self.lines.push(&chunk?)?,
+                None => {
+                    self.lines.finish()?;
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_framer() {
+        let mut framer = LineFramer::default();
+        let mut lines = VecDeque::new();
+        for chunk in ["GET /a", " 200\r\nGET /b 404\n", "", "GET", " /c 500"] {
+            framer.push(chunk.as_bytes(), &mut lines);
+        }
+        lines.extend(framer.finish());
+        assert_eq!(lines, ["GET /a 200", "GET /b 404", "GET /c 500"]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_lines() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(b"GET /a 200\nGET /b 404\nGET /c 500")
+            .unwrap();
+        let body = gzip.finish().unwrap();
+        // chunks that split the gzip header and the lines
+        let chunks: Vec<Result<Bytes>> = body
+            .chunks(7)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        let mut lines = StreamLines::new(futures::stream::iter(chunks).boxed());
+        let mut got = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            got.push(line);
+        }
+        assert_eq!(got, ["GET /a 200", "GET /b 404", "GET /c 500"]);
+    }
+
+    #[test]
+    fn test_chunked_lines_sniffs_split_magic() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(b"GET /a 200\nGET /b 404\n").unwrap();
+        let body = gzip.finish().unwrap();
+        let mut lines = ChunkedLines::default();
+        for byte in body.chunks(1) {
+            lines.push(byte).unwrap();
+        }
+        lines.finish().unwrap();
+        assert_eq!(lines.pop().as_deref(), Some("GET /a 200"));
+        assert_eq!(lines.pop().as_deref(), Some("GET /b 404"));
+        assert_eq!(lines.pop(), None);
+
+        // a plain body shorter than any magic
+        let mut lines = ChunkedLines::default();
+        lines.push(b"ok").unwrap();
+        lines.finish().unwrap();
+        assert_eq!(lines.pop().as_deref(), Some("ok"));
+    }
+}
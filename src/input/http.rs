@@ -0,0 +1,440 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use clap::Args;
+use reqwest::{
+    header::{CONTENT_RANGE, RANGE},
+    Client, Response, StatusCode,
+};
+
+use super::{ChunkedLines, Source};
+
+#[derive(Debug, Clone, Args)]
+pub struct HttpOptions {
+    /// Failed attempts in a row before a download is given up; the count starts over once
+    /// data arrives again.
+    #[arg(long = "http-retries", default_value_t = 5)]
+    pub retries: u32,
+    /// Delay before the first retry in milliseconds, doubled for every further retry in a row
+    /// up to a minute.
+    #[arg(long = "http-backoff", value_name = "MS", default_value = "500", value_parser = parse_millis)]
+    pub backoff: Duration,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// The longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+impl HttpOptions {
+    /// The wait before the next attempt after `failures` failed ones in a row.
+    fn delay(&self, failures: u32) -> Duration {
+        self.backoff
+            .checked_mul(2u32.saturating_pow(failures))
+            .map_or(MAX_BACKOFF, |d| d.min(MAX_BACKOFF))
+    }
+}
+
+fn parse_millis(s: &str) -> Result<Duration, std::num::ParseIntError> {
+    Ok(Duration::from_millis(s.parse()?))
+}
+
+/// How far a download got; `total` is unknown when the server sends no length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub bytes: u64,
+    pub total: Option<u64>,
+    /// Retries over the whole download.
+    pub retries: u32,
+}
+
+pub type ProgressFn = Box<dyn FnMut(&Progress) + Send>;
+
+/// A [`LineReader::on_progress`](super::LineReader::on_progress) callback logging every whole
+/// percent of a download to stderr.
+pub fn report_progress(source: &Source) -> impl FnMut(&Progress) + Send + 'static {
+    let source = source.to_string();
+    let mut reported = None;
+    move |p| {
+        let Some(total) = p.total.filter(|&t| t > 0) else {
+            return;
+        };
+        let percent = p.bytes * 100 / total;
+        if reported != Some(percent) {
+            reported = Some(percent);
+            eprintln!("{source}: {percent}% of {total} bytes");
+        }
+    }
+}
+
+/// The body of a GET as a sequence of chunks, resuming with a `Range` request whenever the
+/// connection drops part way.
+pub struct HttpStream {
+    client: Client,
+    url: String,
+    options: HttpOptions,
+    response: Option<Response>,
+    /// Bytes of a resumed response to throw away, for servers ignoring `Range`.
+    skip: u64,
+    /// Failed attempts since data last arrived.
+    failures: u32,
+    progress: Progress,
+    on_progress: Option<ProgressFn>,
+}
+
+impl HttpStream {
+    /// Sends the first request, so a bad URL or status fails here rather than mid-import.
+    pub async fn connect(url: &str, options: HttpOptions) -> Result<Self> {
+        let mut stream = Self {
+            client: Client::new(),
+            url: url.to_string(),
+            options,
+            response: None,
+            skip: 0,
+            failures: 0,
+            progress: Progress {
+                bytes: 0,
+                total: None,
+                retries: 0,
+            },
+            on_progress: None,
+        };
+        stream.reconnect().await?;
+        Ok(stream)
+    }
+
+    pub fn on_progress(&mut self, f: impl FnMut(&Progress) + Send + 'static) {
+        self.on_progress = Some(Box::new(f));
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        loop {
+            if self.response.is_none() {
+                if self.progress.total == Some(self.progress.bytes) {
+                    return Ok(None);
+                }
+                self.reconnect().await?;
+            }
+            let response = self.response.as_mut().expect("connected above");
+            match response.chunk().await {
+                Ok(Some(mut chunk)) => {
+                    if self.skip > 0 {
+                        let n = self.skip.min(chunk.len() as u64);
+                        self.skip -= n;
+                        chunk = chunk.slice(n as usize..);
+                        if chunk.is_empty() {
+                            continue;
+                        }
+                    }
+                    self.failures = 0;
+                    self.progress.bytes += chunk.len() as u64;
+                    if let Some(f) = self.on_progress.as_mut() {
+                        f(&self.progress);
+                    }
+                    return Ok(Some(chunk));
+                }
+                // without a length, a clean end of body is the end of the file
+                Ok(None) if self.progress.total.is_none_or(|t| self.progress.bytes >= t) => {
+                    self.response = None;
+                    return Ok(None);
+                }
+                Ok(None) | Err(_) => {
+                    self.response = None;
+                    self.retry().await?;
+                }
+            }
+        }
+    }
+
+    /// Waits out the backoff for the next attempt, or fails once the retries in a row are
+    /// used up.
+    async fn retry(&mut self) -> Result<()> {
+        if self.failures >= self.options.retries {
+            bail!(
+                "{}: gave up after {} retries in a row at byte {}",
+                self.url,
+                self.failures,
+                self.progress.bytes
+            );
+        }
+        let delay = self.options.delay(self.failures);
+        self.failures += 1;
+        self.progress.retries += 1;
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        loop {
+            let mut request = self.client.get(&self.url);
+            if self.progress.bytes > 0 {
+                request = request.header(RANGE, format!("bytes={}-", self.progress.bytes));
+            }
+            let response = match request.send().await {
+                Ok(response) => response,
+                Err(err) if err.is_connect() || err.is_timeout() || err.is_request() => {
+                    self.retry().await?;
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let status = response.status();
+            if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                self.retry().await?;
+                continue;
+            }
+            let response = response.error_for_status()?;
+            if status == StatusCode::PARTIAL_CONTENT {
+                self.progress.total = self.progress.total.or_else(|| range_total(&response));
+                self.skip = 0;
+            } else {
+                if self.progress.total.is_none() {
+                    self.progress.total = response.content_length();
+                }
+                self.skip = self.progress.bytes;
+            }
+            self.response = Some(response);
+            return Ok(());
+        }
+    }
+}
+
+/// The complete length from `Content-Range: bytes 100-199/200`.
+fn range_total(response: &Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    range.rsplit_once('/')?.1.parse().ok()
+}
+
+/// Lines of a remote log, decompressed on the fly.
+pub struct HttpLines {
+    stream: HttpStream,
+    lines: ChunkedLines,
+    done: bool,
+}
+
+impl HttpLines {
+    pub async fn connect(url: &str, options: HttpOptions) -> Result<Self> {
+        Ok(Self::new(HttpStream::connect(url, options).await?))
+    }
+
+    pub fn new(stream: HttpStream) -> Self {
+        Self {
+            stream,
+            lines: ChunkedLines::default(),
+            done: false,
+        }
+    }
+
+    pub fn stream_mut(&mut self) -> &mut HttpStream {
+        &mut self.stream
+    }
+
+    pub async fn next_line(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(line) = self.lines.pop() {
+                return Ok(Some(line));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            match self.stream.next_chunk().await? {
+                Some(chunk) => self
+                    .lines
+                    .push(&chunk)
+                    .map_err(|e| anyhow!("{}: {e}", self.stream.url))?,
+                None => {
+                    self.lines.finish()?;
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    type Ranges = Arc<Mutex<Vec<Option<String>>>>;
+
+    /// A stand-in server answering the n-th request, with its `Range` start, with the bytes
+    /// `respond` gives. Returns the URL and the `Range` headers seen.
+    async fn serve(respond: impl Fn(usize, usize) -> Vec<u8> + Send + 'static) -> (String, Ranges) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/access.log", listener.local_addr().unwrap());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let requests = AtomicUsize::new(0);
+        {
+            let ranges = ranges.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (mut socket, _) = listener.accept().await.unwrap();
+                    let mut head = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                        let n = socket.read(&mut buf).await.unwrap();
+                        head.extend_from_slice(&buf[..n]);
+                    }
+                    let head = String::from_utf8(head).unwrap();
+                    let range = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("range: bytes="))
+                        .map(|r| r.trim_end_matches('-').to_string());
+                    ranges.lock().unwrap().push(range.clone());
+
+                    let start = range.map_or(0, |r| r.parse().unwrap());
+                    let response = respond(requests.fetch_add(1, Ordering::SeqCst), start);
+                    socket.write_all(&response).await.unwrap();
+                    socket.shutdown().await.unwrap();
+                }
+            });
+        }
+        (url, ranges)
+    }
+
+    /// `body[start..]` as a 206, cut off after `limit` bytes.
+    fn partial(body: &[u8], start: usize, limit: usize) -> Vec<u8> {
+        let mut r = format!(
+            "HTTP/1.1 206 Partial Content\r\ncontent-length: {}\r\ncontent-range: bytes {start}-{}/{}\r\n\r\n",
+            body.len() - start,
+            body.len() - 1,
+            body.len()
+        )
+        .into_bytes();
+        r.extend_from_slice(&body[start..body.len().min(start + limit)]);
+        r
+    }
+
+    /// The first request gets a 503, the second is cut off half way, and later ones honour
+    /// `Range`.
+    async fn flaky_server(body: Vec<u8>) -> (String, Ranges) {
+        serve(move |request, start| match request {
+            0 => b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n".to_vec(),
+            1 => {
+                let mut r = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", body.len())
+                    .into_bytes();
+                r.extend_from_slice(&body[..body.len() / 2]);
+                r
+            }
+            _ => partial(&body, start, body.len()),
+        })
+        .await
+    }
+
+    fn options() -> HttpOptions {
+        HttpOptions {
+            retries: 3,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_disconnect() {
+        let body: String = (0..2000).map(|i| format!("line {i}\n")).collect();
+        let (url, ranges) = flaky_server(body.clone().into_bytes()).await;
+
+        let mut lines = HttpLines::connect(&url, options()).await.unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        {
+            let seen = seen.clone();
+            lines
+                .stream_mut()
+                .on_progress(move |p| seen.lock().unwrap().push(*p));
+        }
+        let mut got = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            got.push(line);
+        }
+        assert_eq!(got, body.lines().collect::<Vec<_>>());
+
+        let half = (body.len() / 2).to_string();
+        assert_eq!(*ranges.lock().unwrap(), vec![None, None, Some(half)]);
+        let last = *seen.lock().unwrap().last().unwrap();
+        assert_eq!(last.bytes, body.len() as u64);
+        assert_eq!(last.total, Some(body.len() as u64));
+        assert_eq!(last.retries, 2);
+    }
+
+    #[tokio::test]
+    async fn test_gzip_body() {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(b"a\nb\nc").unwrap();
+        let (url, _) = flaky_server(gz.finish().unwrap()).await;
+        let mut lines = HttpLines::connect(&url, options()).await.unwrap();
+        let mut got = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            got.push(line);
+        }
+        assert_eq!(got, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_give_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let err = HttpStream::connect(&url, options()).await.err().unwrap();
+        assert!(err.to_string().contains("gave up after 3 retries in a row"));
+    }
+
+    #[tokio::test]
+    async fn test_retries_reset_on_progress() {
+        let body: Vec<u8> = (0..100)
+            .flat_map(|i| format!("line {i}\n").into_bytes())
+            .collect();
+        // every response drops after 100 bytes, so a download needs many more resumes than
+        // `retries`, but never fails twice in a row
+        let piece = 100;
+        let (url, ranges) = {
+            let body = body.clone();
+            serve(move |_, start| partial(&body, start, piece)).await
+        };
+        let mut stream = HttpStream::connect(&url, options()).await.unwrap();
+        let mut got = Vec::new();
+        while let Some(chunk) = stream.next_chunk().await.unwrap() {
+            got.extend_from_slice(&chunk);
+        }
+        assert_eq!(got, body);
+        let requests = ranges.lock().unwrap().len() as u32;
+        assert_eq!(requests as usize, body.len().div_ceil(piece));
+        assert_eq!(stream.progress().retries, requests - 1);
+        assert!(stream.progress().retries > options().retries);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let options = HttpOptions {
+            retries: 100,
+            backoff: Duration::from_secs(1),
+        };
+        assert_eq!(options.delay(0), Duration::from_secs(1));
+        assert_eq!(options.delay(2), Duration::from_secs(4));
+        assert_eq!(options.delay(6), MAX_BACKOFF);
+        assert_eq!(options.delay(40), MAX_BACKOFF);
+    }
+}
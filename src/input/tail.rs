@@ -0,0 +1,383 @@
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Args)]
+pub struct FollowOptions {
+    /// Keep reading the log as it grows, across logrotate, until interrupted.
+    #[arg(long)]
+    pub follow: bool,
+    /// File recording how far the log has been read, so a restart carries on from there.
+    #[arg(long, requires = "follow")]
+    pub checkpoint: Option<PathBuf>,
+    /// Commit a Parquet part once this many rows are pending.
+    #[arg(long, default_value_t = 100_000)]
+    pub flush_rows: usize,
+    /// Commit a Parquet part once the oldest pending row is this many seconds old.
+    #[arg(long, value_name = "SECS", default_value = "60", value_parser = parse_secs)]
+    pub flush_interval: Duration,
+    /// How often to look for new lines in milliseconds.
+    #[arg(long, value_name = "MS", default_value = "250", value_parser = parse_millis)]
+    pub poll_interval: Duration,
+}
+
+fn parse_secs(s: &str) -> Result<Duration, std::num::ParseIntError> {
+    Ok(Duration::from_secs(s.parse()?))
+}
+
+fn parse_millis(s: &str) -> Result<Duration, std::num::ParseIntError> {
+    Ok(Duration::from_millis(s.parse()?))
+}
+
+/// Identifies a file across renames, so rotation can be told from growth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileId {
+    pub dev: u64,
+    pub inode: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    fn of(meta: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            dev: meta.dev(),
+            inode: meta.ino(),
+        }
+    }
+
+    /// Without inodes only truncation can be detected.
+    #[cfg(not(unix))]
+    fn of(_meta: &Metadata) -> Self {
+        Self { dev: 0, inode: 0 }
+    }
+}
+
+/// The position after the last line handed out, written once its records are committed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub file: FileId,
+    pub offset: u64,
+    /// Lines of `file` handed out up to `offset`, so numbering carries on after a restart.
+    #[serde(default)]
+    pub line: u64,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(bytes) => {
+                Ok(Some(serde_json::from_slice(&bytes).with_context(|| {
+                    format!("invalid checkpoint {}", path.display())
+                })?))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Whether this position was reached after `other`. Positions in different files are
+    /// told apart by which file is still at the log's path, the other one having been rotated
+    /// away; when neither is, `other` is kept.
+    pub fn is_ahead_of(&self, other: &Checkpoint) -> bool {
+        if self.file == other.file {
+            return self.offset > other.offset;
+        }
+        fs::metadata(&self.path).is_ok_and(|meta| FileId::of(&meta) == self.file)
+    }
+
+    /// Replaces the file atomically, so a crash leaves the old checkpoint or the new one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+struct Open {
+    reader: BufReader<File>,
+    file: FileId,
+    /// Offset just past the last complete line.
+    offset: u64,
+    /// Lines handed out from this file.
+    line: u64,
+}
+
+/// Follows a growing log like `tail -F`.
+///
+/// When the path is renamed away and recreated, the old file is read to its end before
+/// moving on. A file shrinking below the read offset, as `copytruncate` does, is re-read
+/// from the start.
+pub struct Tailer {
+    path: PathBuf,
+    open: Option<Open>,
+    partial: Vec<u8>,
+    /// The end of a file rotated away, until the file replacing it is opened.
+    closed: Option<Checkpoint>,
+}
+
+impl Tailer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            open: None,
+            partial: Vec::new(),
+            closed: None,
+        }
+    }
+
+    /// Starts where `checkpoint` left off. If the log was rotated since, the rotated file is
+    /// looked up by inode next to it and finished first.
+    pub fn resume(path: impl Into<PathBuf>, checkpoint: Option<&Checkpoint>) -> Result<Self> {
+        let mut tailer = Self::new(path);
+        let Some(checkpoint) = checkpoint else {
+            return Ok(tailer);
+        };
+        if let Some(file) = tailer.find(checkpoint.file)? {
+            let meta = file.metadata()?;
+            if meta.len() >= checkpoint.offset {
+                let mut reader = BufReader::new(file);
+                reader.seek(SeekFrom::Start(checkpoint.offset))?;
+                tailer.open = Some(Open {
+                    reader,
+                    file: checkpoint.file,
+                    offset: checkpoint.offset,
+                    line: checkpoint.line,
+                });
+                return Ok(tailer);
+            }
+        }
+        // the file is gone, so the position stands until the log is opened again
+        tailer.closed = Some(checkpoint.clone());
+        Ok(tailer)
+    }
+
+    /// The log itself, or a sibling with the same inode.
+    fn find(&self, id: FileId) -> Result<Option<File>> {
+        if let Ok(meta) = fs::metadata(&self.path) {
+            if FileId::of(&meta) == id {
+                return Ok(Some(File::open(&self.path)?));
+            }
+        }
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_file() && FileId::of(&meta) == id {
+                return Ok(Some(File::open(entry.path())?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Where the lines handed out so far end; `None` until a file has been opened.
+    ///
+    /// Between a rotation and opening the new file this is the end of the rotated one, so a
+    /// restart does not read its last lines again.
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        match &self.open {
+            Some(open) => Some(Checkpoint {
+                path: self.path.clone(),
+                file: open.file,
+                offset: open.offset,
+                line: open.line,
+            }),
+            None => self.closed.clone(),
+        }
+    }
+
+    /// The next complete line, or `None` when caught up with the writer.
+    pub fn poll(&mut self) -> Result<Option<String>> {
+        loop {
+            let Some(open) = self.open.as_mut() else {
+                match File::open(&self.path) {
+                    Ok(file) => {
+                        let file_id = FileId::of(&file.metadata()?);
+                        self.open = Some(Open {
+                            reader: BufReader::new(file),
+                            file: file_id,
+                            offset: 0,
+                            line: 0,
+                        });
+                        self.closed = None;
+                        continue;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(err) => return Err(err.into()),
+                }
+            };
+
+            let n = open.reader.read_until(b'\n', &mut self.partial)?;
+            if self.partial.last() == Some(&b'\n') {
+                open.offset += self.partial.len() as u64;
+                open.line += 1;
+                self.partial.pop();
+                return Ok(Some(super::decode_line(std::mem::take(&mut self.partial))));
+            }
+            if n > 0 {
+                // the writer is part way through a line
+                continue;
+            }
+
+            let meta = match fs::metadata(&self.path) {
+                Ok(meta) => meta,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            if FileId::of(&meta) != open.file {
+                // rotated: the old file has been read to its end, a last unterminated line
+                // will not be finished any more
+                let last = std::mem::take(&mut self.partial);
+                let mut closed = Checkpoint {
+                    path: self.path.clone(),
+                    file: open.file,
+                    offset: open.offset + last.len() as u64,
+                    line: open.line,
+                };
+                self.open = None;
+                if !last.is_empty() {
+                    closed.line += 1;
+                    self.closed = Some(closed);
+                    return Ok(Some(super::decode_line(last)));
+                }
+                self.closed = Some(closed);
+            } else if meta.len() < open.offset + self.partial.len() as u64 {
+                open.reader.seek(SeekFrom::Start(0))?;
+                open.offset = 0;
+                open.line = 0;
+                self.partial.clear();
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write};
+
+    use super::*;
+
+    fn append(path: &Path, s: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(s.as_bytes()).unwrap();
+    }
+
+    fn drain(tailer: &mut Tailer) -> Vec<String> {
+        std::iter::from_fn(|| tailer.poll().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_follow_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        let mut tailer = Tailer::new(&log);
+        assert_eq!(tailer.poll().unwrap(), None);
+
+        append(&log, "one\ntw");
+        assert_eq!(drain(&mut tailer), ["one"]);
+        append(&log, "o\r\nthree\n");
+        assert_eq!(drain(&mut tailer), ["two", "three"]);
+        assert_eq!(tailer.checkpoint().unwrap().offset, 15);
+    }
+
+    #[test]
+    fn test_rename_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        append(&log, "one\n");
+        let mut tailer = Tailer::new(&log);
+        assert_eq!(drain(&mut tailer), ["one"]);
+
+        // written after our last read, then logrotate moves the file and nginx reopens
+        append(&log, "two\nunfinished");
+        fs::rename(&log, dir.path().join("access.log.1")).unwrap();
+        // nginx keeps writing to the renamed file until it reopens its logs
+        assert_eq!(drain(&mut tailer), ["two"]);
+        append(&dir.path().join("access.log.1"), " line\n");
+        append(&log, "three\n");
+        assert_eq!(drain(&mut tailer), ["unfinished line", "three"]);
+    }
+
+    #[test]
+    fn test_copytruncate() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        append(&log, "one\ntwo\n");
+        let mut tailer = Tailer::new(&log);
+        assert_eq!(drain(&mut tailer), ["one", "two"]);
+
+        fs::copy(&log, dir.path().join("access.log.1")).unwrap();
+        File::create(&log).unwrap();
+        append(&log, "three\n");
+        assert_eq!(drain(&mut tailer), ["three"]);
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        let saved = dir.path().join("checkpoint.json");
+        append(&log, "one\ntwo\n");
+        let mut tailer = Tailer::new(&log);
+        assert_eq!(tailer.poll().unwrap().as_deref(), Some("one"));
+        tailer.checkpoint().unwrap().save(&saved).unwrap();
+        drop(tailer);
+
+        let checkpoint = Checkpoint::load(&saved).unwrap().unwrap();
+        let mut tailer = Tailer::resume(&log, Some(&checkpoint)).unwrap();
+        assert_eq!(drain(&mut tailer), ["two"]);
+
+        // rotated while we were down: finish the old file, then start the new one
+        let checkpoint = tailer.checkpoint().unwrap();
+        append(&log, "three\n");
+        fs::rename(&log, dir.path().join("access.log.1")).unwrap();
+        append(&log, "four\n");
+        let mut tailer = Tailer::resume(&log, Some(&checkpoint)).unwrap();
+        assert_eq!(drain(&mut tailer), ["three", "four"]);
+
+        assert_eq!(Checkpoint::load(&dir.path().join("missing")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_checkpoint_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        append(&log, "one\ntwo\n");
+        let mut tailer = Tailer::new(&log);
+        tailer.poll().unwrap();
+        let first = tailer.checkpoint().unwrap();
+        tailer.poll().unwrap();
+        let second = tailer.checkpoint().unwrap();
+        assert_eq!((first.line, second.line), (1, 2));
+        assert!(second.is_ahead_of(&first));
+        assert!(!first.is_ahead_of(&second));
+        assert!(!first.is_ahead_of(&first));
+
+        fs::rename(&log, dir.path().join("access.log.1")).unwrap();
+        append(&log, "three\n");
+        drain(&mut tailer);
+        let rotated = tailer.checkpoint().unwrap();
+        assert_ne!(rotated.file, second.file);
+        assert!(rotated.is_ahead_of(&second));
+        assert!(!second.is_ahead_of(&rotated));
+    }
+}
@@ -1,44 +1,152 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use winnow::{
     ascii::{digit1, multispace0, Caseless},
     combinator::{alt, delimited, opt, separated, separated_pair, trace},
-    error::{ContextError, ErrMode, ParserError},
+    error::{ContextError, ErrMode, ErrorKind, ParseError, ParserError},
     stream::{AsBStr, AsChar, Compare, FindSlice, ParseSlice, Stream, StreamIsPartial},
-    token::take_until,
+    token::{any, one_of},
     PResult, Parser,
 };
+
 #[derive(Debug, Clone, PartialEq)]
-enum Num {
+pub enum Num {
     Int(i64),
     Float(f64),
 }
+
 #[derive(Debug, Clone, PartialEq)]
-enum JsonValue {
+pub enum JsonValue {
     Null,
     Bool(bool),
     Number(Num),
     String(String),
     Array(Vec<JsonValue>),
-    Object(std::collections::HashMap<String, JsonValue>),
+    Object(HashMap<String, JsonValue>),
+}
+
+impl JsonValue {
+    /// The value at an RFC 6901 JSON pointer such as `/items/0/id`; `""` is the whole value.
+    pub fn pointer(&self, pointer: &str) -> Option<&JsonValue> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        let mut tokens = pointer.strip_prefix('/')?.split('/');
+        tokens.try_fold(self, |value, token| {
+            let token = token.replace("~1", "/").replace("~0", "~");
+            match value {
+                JsonValue::Object(map) => map.get(&token),
+                JsonValue::Array(items) => items.get(token.parse::<usize>().ok()?),
+                _ => None,
+            }
+        })
+    }
+
+    /// The JSON type name: `null`, `boolean`, `number`, `string`, `array` or `object`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            JsonValue::Null => "null",
+            JsonValue::Bool(_) => "boolean",
+            JsonValue::Number(_) => "number",
+            JsonValue::String(_) => "string",
+            JsonValue::Array(_) => "array",
+            JsonValue::Object(_) => "object",
+        }
+    }
+
+    /// Integers, and floats without a fractional part that fit.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            JsonValue::Number(Num::Int(i)) => Some(*i),
+            JsonValue::Number(Num::Float(f))
+                if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 =>
+            {
+                Some(*f as i64)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
 }
-fn main() -> Result<()> {
-    let s = r#"{
-        "name": "John Doe",
-        "age": 43,
-        "is_adult": true,
-        "mark:": [90.1, 80.2, 85.0],
-        "address": {
-            "city": "New York",
-            "state": "NY"
+
+/// Serializes compactly; object keys are sorted so the output is stable.
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(b) => write!(f, "{b}"),
+            JsonValue::Number(Num::Int(i)) => write!(f, "{i}"),
+            // JSON has no NaN or infinity
+            JsonValue::Number(Num::Float(n)) if !n.is_finite() => write!(f, "null"),
+            JsonValue::Number(Num::Float(n)) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                write!(f, "{n:.1}")
+            }
+            JsonValue::Number(Num::Float(n)) => write!(f, "{n}"),
+            JsonValue::String(s) => write_string(f, s),
+            JsonValue::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
         }
-    }"#;
-    let s = &mut (&*s);
-    let v = parse_value(s).map_err(|e: ErrMode<ContextError>| anyhow::anyhow!(e))?;
-    println!("{:#?}", v);
-    Ok(())
+    }
+}
+
+/// Writes `s` as a quoted JSON string.
+pub fn write_string(f: &mut impl fmt::Write, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Parses a whole document, allowing whitespace around the value.
+///
+/// Accepts what RFC 8259 allows, where the first version of this parser fell short:
+/// - numbers may have a fraction and an exponent; integers beyond `i64` are kept as
+///   floats, and floats beyond `f64` become infinite, which is written back as `null`
+/// - strings decode every escape, including `\uXXXX` surrogate pairs; an unpaired
+///   surrogate becomes U+FFFD and any other escape is an error
+/// - `{}` and `[]` are empty, not errors
+pub fn parse_json(s: &str) -> Result<JsonValue> {
+    delimited(multispace0, parse_value, multispace0)
+        .parse(s)
+        .map_err(|e: ParseError<&str, ContextError>| anyhow!("invalid JSON: {e}"))
 }
 
 fn parse_null<Input, Error>(s: &mut Input) -> PResult<(), Error>
@@ -58,59 +166,114 @@ where
     alt(("true", "false")).parse_to().parse_next(s)
 }
 
-// fn parse_int<Input, Error>(s: &mut Input) -> PResult<i64, Error>
-// where
-//     Input: StreamIsPartial + Stream + Compare<&'static str>,
-//     <Input as Stream>::Token: AsChar,
-//     <Input as Stream>::Slice: ParseSlice<i64>,
-//     Error: ParserError<Input>,
-// {
-//     let sign = opt("-").map(|x| x.is_some()).parse_next(s)?;
-//     let num = digit1.parse_to::<i64>().parse_next(s)?;
-//     Ok(if sign { -num } else { num })
-// }
-
 fn parse_number<Input, Error>(s: &mut Input) -> PResult<Num, Error>
 where
-    Input: StreamIsPartial
-        + Stream
-        + AsBStr
-        + Compare<Caseless<&'static str>>
-        + Clone
-        + Compare<char>
-        + Compare<&'static str>,
-    <Input as Stream>::Slice:
-        ParseSlice<u64> + ParseSlice<bool> + ParseSlice<f64> + Display + Clone,
-
+    Input: StreamIsPartial + Stream + Compare<char>,
+    <Input as Stream>::Slice: ParseSlice<i64> + ParseSlice<f64>,
     <Input as Stream>::Token: AsChar + Clone,
-    <Input as Stream>::IterOffsets: Clone,
-    <Input as Stream>::Slice: ParseSlice<f64>,
     Error: ParserError<Input>,
 {
-    let sign = opt("-").map(|x| x.is_some()).parse_next(s)?;
-    let num = digit1.parse_to::<u64>().parse_next(s)?;
-    let ret: std::result::Result<<Input as Stream>::Slice, ErrMode<ContextError>> =
-        ".".parse_next(s);
-
-    if ret.is_ok() {
-        let frac = digit1.parse_to::<u64>().parse_next(s)?;
-        let total = num as f64 + frac as f64 / 10u64.pow(frac.to_string().len() as u32) as f64;
-        Ok(Num::Float(if sign { -total as _ } else { total as _ }))
-    } else {
-        Ok(Num::Int(if sign { -(num as i64) } else { num as _ }))
+    let start = s.checkpoint();
+    let ((fraction, exponent), text) = (
+        opt('-'),
+        digit1,
+        opt(('.', digit1)),
+        opt((one_of(['e', 'E']), opt(one_of(['+', '-'])), digit1)),
+    )
+        .map(|(_, _, fraction, exponent)| (fraction.is_some(), exponent.is_some()))
+        .with_taken()
+        .parse_next(s)?;
+    if !fraction && !exponent {
+        // integers too large for i64 are kept as floats
+        if let Some(i) = text.parse_slice() {
+            return Ok(Num::Int(i));
+        }
+    }
+    match text.parse_slice() {
+        Some(f) => Ok(Num::Float(f)),
+        None => {
+            s.reset(&start);
+            Err(ErrMode::from_error_kind(s, ErrorKind::Verify))
+        }
     }
 }
 
 fn parse_string<Input, Error>(s: &mut Input) -> PResult<String, Error>
 where
-    Input: StreamIsPartial + Stream + Compare<char> + FindSlice<char> + AsBStr,
-    <Input as Stream>::Token: AsChar,
+    Input: StreamIsPartial + Stream + Compare<char>,
+    <Input as Stream>::Token: AsChar + Clone,
+    Error: ParserError<Input>,
+{
+    trace("string", |s: &mut Input| {
+        '"'.parse_next(s)?;
+        let mut out = String::new();
+        loop {
+            match any.parse_next(s)?.as_char() {
+                '"' => return Ok(out),
+                '\\' => out.push(parse_escape(s)?),
+                c => out.push(c),
+            }
+        }
+    })
+    .parse_next(s)
+}
 
-    <Input as Stream>::Slice: Display,
+/// The character after a backslash; unpaired surrogates become U+FFFD.
+fn parse_escape<Input, Error>(s: &mut Input) -> PResult<char, Error>
+where
+    Input: StreamIsPartial + Stream + Compare<char>,
+    <Input as Stream>::Token: AsChar + Clone,
     Error: ParserError<Input>,
 {
-    let ret = delimited('"', take_until(0.., '"'), '"').parse_next(s)?;
-    Ok(ret.to_string())
+    let start = s.checkpoint();
+    let c = match any.parse_next(s)?.as_char() {
+        '"' => '"',
+        '\\' => '\\',
+        '/' => '/',
+        'b' => '\u{8}',
+        'f' => '\u{c}',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'u' => {
+            let unit = parse_hex4(s)?;
+            if (0xd800..0xdc00).contains(&unit) {
+                let low = opt(('\\', 'u', parse_hex4).map(|(_, _, low)| low)).parse_next(s)?;
+                match low {
+                    Some(low @ 0xdc00..0xe000) => {
+                        let c = 0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00);
+                        char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER)
+                    }
+                    _ => char::REPLACEMENT_CHARACTER,
+                }
+            } else {
+                char::from_u32(unit).unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+        }
+        _ => {
+            s.reset(&start);
+            return Err(ErrMode::from_error_kind(s, ErrorKind::Verify));
+        }
+    };
+    Ok(c)
+}
+
+fn parse_hex4<Input, Error>(s: &mut Input) -> PResult<u32, Error>
+where
+    Input: StreamIsPartial + Stream,
+    <Input as Stream>::Token: AsChar + Clone,
+    Error: ParserError<Input>,
+{
+    let mut unit = 0;
+    for _ in 0..4 {
+        let start = s.checkpoint();
+        let Some(digit) = any.parse_next(s)?.as_char().to_digit(16) else {
+            s.reset(&start);
+            return Err(ErrMode::from_error_kind(s, ErrorKind::Verify));
+        };
+        unit = unit * 16 + digit;
+    }
+    Ok(unit)
 }
 
 fn parse_array<Input, Error>(s: &mut Input) -> PResult<Vec<JsonValue>, Error>
@@ -124,7 +287,7 @@ where
         + FindSlice<char>
         + Clone,
     <Input as Stream>::Slice:
-        ParseSlice<i64> + ParseSlice<bool> + ParseSlice<u64> + ParseSlice<f64> + Display + Clone,
+        ParseSlice<i64> + ParseSlice<bool> + ParseSlice<u64> + ParseSlice<f64> + Clone,
     <Input as Stream>::Token: AsChar + Clone,
     <Input as Stream>::IterOffsets: Clone,
     Error: ParserError<Input>,
@@ -156,9 +319,7 @@ where
     })
 }
 
-fn parse_object<Input, Error>(
-    s: &mut Input,
-) -> PResult<std::collections::HashMap<String, JsonValue>, Error>
+fn parse_object<Input, Error>(s: &mut Input) -> PResult<HashMap<String, JsonValue>, Error>
 where
     Input: StreamIsPartial
         + Stream
@@ -169,7 +330,7 @@ where
         + FindSlice<char>
         + Clone,
     <Input as Stream>::Slice:
-        Display + ParseSlice<i64> + ParseSlice<bool> + ParseSlice<f64> + ParseSlice<u64> + Clone,
+        ParseSlice<i64> + ParseSlice<bool> + ParseSlice<f64> + ParseSlice<u64> + Clone,
     <Input as Stream>::Token: AsChar + Clone,
     <Input as Stream>::IterOffsets: Clone,
     Error: ParserError<Input>,
@@ -179,12 +340,12 @@ where
     let sep_comma = sep_with_ws(',');
     let sep_sep = sep_with_ws(':');
     let parse_kv_pair = separated_pair(parse_string, sep_sep, parse_value);
-    let parse_kv = separated(1.., parse_kv_pair, sep_comma);
+    let parse_kv = separated(0.., parse_kv_pair, sep_comma);
     let ret = delimited(sep1, parse_kv, sep2).parse_next(s)?;
     Ok(ret)
 }
 
-fn parse_value<Input, Error>(s: &mut Input) -> PResult<JsonValue, Error>
+pub fn parse_value<Input, Error>(s: &mut Input) -> PResult<JsonValue, Error>
 where
     Input: StreamIsPartial
         + Stream
@@ -195,20 +356,14 @@ where
         + FindSlice<char>
         + Clone,
     <Input as Stream>::Slice:
-        ParseSlice<i64> + ParseSlice<bool> + ParseSlice<f64> + ParseSlice<u64> + Display + Clone,
+        ParseSlice<i64> + ParseSlice<bool> + ParseSlice<f64> + ParseSlice<u64> + Clone,
     <Input as Stream>::Token: AsChar + Clone,
     <Input as Stream>::IterOffsets: Clone,
     Error: ParserError<Input>,
 {
-    println!("parse_value{:?}", s);
     alt((
         parse_null.map(|_| JsonValue::Null),
         parse_bool.map(JsonValue::Bool),
-        // alt((
-        //     float.map(JsonValue::Number),
-        //     parse_int.map(JsonValue::Int),
-        //     // parse_number.map(JsonValue::Number),
-        // )), //
         parse_number.map(JsonValue::Number),
         parse_string.map(JsonValue::String),
         parse_array.map(JsonValue::Array),
@@ -237,26 +392,31 @@ mod tests {
         let v = parse_bool::<&str, InputError<&str>>(&mut (&*s)).unwrap();
         assert!(!v);
     }
-    // #[test]
-    // fn test_parse_int() {
-    //     let s = "123";
-    //     let v = parse_int::<&str, InputError<&str>>(&mut (&*s)).unwrap();
-    //     assert_eq!(v, 123);
-    //     let s = "-123";
-    //     let v = parse_int::<&str, InputError<&str>>(&mut (&*s)).unwrap();
-    //     assert_eq!(v, -123);
-    // }
     #[test]
     fn test_parse_number() {
         let s = "123.45";
         let v = parse_number::<&str, InputError<&str>>(&mut (&*s)).unwrap();
         assert_eq!(v, Num::Float(123.45));
+        let s = "1.05";
+        let v = parse_number::<&str, InputError<&str>>(&mut (&*s)).unwrap();
+        assert_eq!(v, Num::Float(1.05));
+        let s = "-2.5e3";
+        let v = parse_number::<&str, InputError<&str>>(&mut (&*s)).unwrap();
+        assert_eq!(v, Num::Float(-2500.0));
+        let s = "-42";
+        let v = parse_number::<&str, InputError<&str>>(&mut (&*s)).unwrap();
+        assert_eq!(v, Num::Int(-42));
     }
     #[test]
     fn test_parse_string() {
         let s = r#""hello""#;
         let v = parse_string::<&str, InputError<&str>>(&mut (&*s)).unwrap();
         assert_eq!(v, "hello");
+        let s = r#""say \"hi\"\né😀 \/""#;
+        let v = parse_string::<&str, InputError<&str>>(&mut (&*s)).unwrap();
+        assert_eq!(v, "say \"hi\"\n\u{e9}\u{1f600} /");
+        let s = r#""bad \x""#;
+        assert!(parse_string::<&str, InputError<&str>>(&mut (&*s)).is_err());
     }
 
     #[test]
@@ -282,16 +442,16 @@ mod tests {
                 JsonValue::Number(Num::Int(85))
             ]
         );
-        // let input = r#"["a", "b", "c"]"#;
-        // let v = parse_array::<&str, InputError<&str>>(&mut (&*input)).unwrap();
-        // assert_eq!(
-        //     v,
-        //     vec![
-        //         JsonValue::String("a".to_string()),
-        //         JsonValue::String("b".to_string()),
-        //         JsonValue::String("c".to_string())
-        //     ]
-        // );
+        let input = r#"["a", "b", "c"]"#;
+        let v = parse_array::<&str, InputError<&str>>(&mut (&*input)).unwrap();
+        assert_eq!(
+            v,
+            vec![
+                JsonValue::String("a".to_string()),
+                JsonValue::String("b".to_string()),
+                JsonValue::String("c".to_string())
+            ]
+        );
     }
     #[test]
     fn test_parse_object() {
@@ -303,5 +463,66 @@ mod tests {
         map.insert("c".to_string(), JsonValue::Number(Num::Int(3)));
 
         assert_eq!(v, map);
+        let s = "{ }";
+        let v = parse_object::<&str, InputError<&str>>(&mut (&*s)).unwrap();
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn test_pointer_and_display() {
+        let v =
+            parse_json(r#" {"user": {"id": 7, "tags": ["a", "b/c"]}, "a/b": null, "price": 2.0} "#)
+                .unwrap();
+        assert_eq!(v.pointer("/user/id").and_then(JsonValue::as_i64), Some(7));
+        assert_eq!(
+            v.pointer("/user/tags/1").and_then(JsonValue::as_str),
+            Some("b/c")
+        );
+        assert_eq!(v.pointer("/a~1b"), Some(&JsonValue::Null));
+        assert_eq!(v.pointer("/user/tags/9"), None);
+        assert_eq!(v.pointer("user"), None);
+        assert_eq!(
+            v.pointer("/price").map(JsonValue::type_name),
+            Some("number")
+        );
+        assert_eq!(
+            v.to_string(),
+            r#"{"a/b":null,"price":2.0,"user":{"id":7,"tags":["a","b/c"]}}"#
+        );
+        // what is written parses back to the same value
+        assert_eq!(parse_json(&v.to_string()).unwrap(), v);
+        assert_eq!(
+            JsonValue::String("q\"\u{1}".to_string()).to_string(),
+            r#""q\"\u0001""#
+        );
+        assert!(parse_json("{} x").is_err());
+    }
+
+    #[test]
+    fn test_parse_json_grammar() {
+        assert_eq!(parse_json("{}").unwrap(), JsonValue::Object(HashMap::new()));
+        assert_eq!(parse_json(" [ ] ").unwrap(), JsonValue::Array(Vec::new()));
+        assert_eq!(
+            parse_json("9223372036854775807").unwrap(),
+            JsonValue::Number(Num::Int(i64::MAX))
+        );
+        assert_eq!(
+            parse_json("9223372036854775808").unwrap(),
+            JsonValue::Number(Num::Float(9223372036854775808.0))
+        );
+        let huge = parse_json("1e400").unwrap();
+        assert_eq!(huge, JsonValue::Number(Num::Float(f64::INFINITY)));
+        assert_eq!(huge.to_string(), "null");
+        assert_eq!(
+            parse_json(r#""\u00e9""#).unwrap(),
+            JsonValue::String("\u{e9}".to_string())
+        );
+        assert_eq!(
+            parse_json(r#""\ud83d\ude00 \ud800""#).unwrap(),
+            JsonValue::String("\u{1f600} \u{fffd}".to_string())
+        );
+        for bad in ["1.", ".5", "-", r#""\q""#, "{,}", "[1,]"] {
+            assert!(parse_json(bad).is_err(), "{bad}");
+        }
     }
 }
@@ -0,0 +1,15 @@
+pub mod arrow_record;
+pub mod export;
+pub mod ingest;
+pub mod input;
+pub mod json;
+pub mod log_format;
+pub mod net;
+pub mod nginx;
+pub mod query;
+pub mod report;
+pub mod ua;
+pub mod url;
+
+pub use arrow_record::{AppendRecord, ArrowField, RecordBatchBuilder, ToArrowRecord};
+pub use export::{Codec, ExportOptions, ParquetSink, RecordSink, PARSER_VERSION};
@@ -0,0 +1,1068 @@
+use std::{net::IpAddr, ops::Range, str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use arrow::{
+    array::{ArrayBuilder, ListBuilder, StringBuilder},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use winnow::{
+    ascii::{multispace0, multispace1},
+    combinator::{alt, delimited, opt, preceded, repeat, terminated},
+    token::{any, none_of, take_till, take_while},
+    PResult, Parser,
+};
+
+use crate::{
+    arrow_record::DictionaryBuilder,
+    ingest::LineError,
+    net::{parse_forwarded_for, parse_ip},
+    nginx::{escape::unescape_str, parse_request, parse_time_local},
+    ua::UaParser,
+    url::{QueryParams, RequestTarget},
+    AppendRecord, ArrowField, RecordBatchBuilder,
+};
+
+pub mod apache;
+
+pub use crate::nginx::escape::Escape;
+
+/// One piece of an nginx `log_format` string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Literal(String),
+    Variable(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFormatDirective {
+    pub name: String,
+    pub escape: Escape,
+    pub format: String,
+}
+
+pub fn parse_log_format(s: &mut &str) -> PResult<Vec<Segment>> {
+    let segments: Vec<Segment> = repeat(
+        0..,
+        alt((
+            parse_variable.map(Segment::Variable),
+            take_till(1.., '$').map(|l: &str| Segment::Literal(l.to_string())),
+            "$".map(|l: &str| Segment::Literal(l.to_string())),
+        )),
+    )
+    .parse_next(s)?;
+
+    // a lone '$' splits literals, join them back
+    let mut merged: Vec<Segment> = Vec::with_capacity(segments.len());
+    for segment in segments {
+        match (merged.last_mut(), segment) {
+            (Some(Segment::Literal(prev)), Segment::Literal(l)) => prev.push_str(&l),
+            (_, segment) => merged.push(segment),
+        }
+    }
+    Ok(merged)
+}
+
+fn parse_variable(s: &mut &str) -> PResult<String> {
+    fn name<'s>(s: &mut &'s str) -> PResult<&'s str> {
+        take_while(1.., |c: char| c.is_ascii_alphanumeric() || c == '_').parse_next(s)
+    }
+    preceded('$', alt((delimited('{', name, '}'), name)))
+        .map(str::to_string)
+        .parse_next(s)
+}
+
+/// Parses a directive such as `log_format main escape=json '$remote_addr - ...' '...';`.
+pub fn parse_log_format_directive(s: &mut &str) -> PResult<LogFormatDirective> {
+    let _ = (multispace0, "log_format", multispace1).parse_next(s)?;
+    let name = terminated(take_till(1.., char::is_whitespace), multispace1).parse_next(s)?;
+    let escape = opt(terminated(
+        preceded(
+            "escape=",
+            alt((
+                "default".value(Escape::Default),
+                "json".value(Escape::Json),
+                "none".value(Escape::None),
+            )),
+        ),
+        multispace1,
+    ))
+    .parse_next(s)?;
+    let parts: Vec<String> = repeat(1.., terminated(parse_quoted, multispace0)).parse_next(s)?;
+    let _ = (';', multispace0).parse_next(s)?;
+    Ok(LogFormatDirective {
+        name: name.to_string(),
+        escape: escape.unwrap_or_default(),
+        format: parts.concat(),
+    })
+}
+
+fn parse_quoted(s: &mut &str) -> PResult<String> {
+    fn quoted<'s>(quote: char) -> impl Parser<&'s str, String, winnow::error::ContextError> {
+        delimited(
+            quote,
+            repeat(
+                0..,
+                alt((
+                    preceded('\\', any),
+                    none_of(move |c: char| c == quote || c == '\\'),
+                )),
+            ),
+            quote,
+        )
+    }
+    alt((quoted('\''), quoted('"'))).parse_next(s)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Utf8,
+    Dictionary,
+    UInt16,
+    UInt64,
+    Float64,
+    Timestamp,
+    Ip,
+    List,
+    Map,
+    Boolean,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub ty: ColumnType,
+}
+
+impl Column {
+    fn new(name: impl Into<String>, ty: ColumnType) -> Self {
+        Self {
+            name: name.into(),
+            ty,
+        }
+    }
+
+    pub fn field(&self) -> Field {
+        let name = self.name.as_str();
+        match self.ty {
+            ColumnType::Utf8 => Option::<String>::field(name),
+            ColumnType::Dictionary => Field::new(
+                name,
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+                true,
+            ),
+            ColumnType::UInt16 => Option::<u16>::field(name),
+            ColumnType::UInt64 => Option::<u64>::field(name),
+            ColumnType::Float64 => Option::<f64>::field(name),
+            ColumnType::Timestamp => Option::<DateTime<Utc>>::field(name),
+            ColumnType::Ip => Option::<IpAddr>::field(name),
+            ColumnType::Map => Option::<QueryParams>::field(name),
+            ColumnType::Boolean => Option::<bool>::field(name),
+            ColumnType::List => {
+                Field::new_list(name, Field::new_list_field(DataType::Utf8, true), true)
+            }
+        }
+    }
+
+    fn new_builder(&self, capacity: usize) -> Box<dyn ArrayBuilder> {
+        match self.ty {
+            ColumnType::Utf8 => Box::new(String::new_builder(capacity)),
+            ColumnType::Dictionary => Box::new(DictionaryBuilder::with_capacity(capacity, 16, 256)),
+            ColumnType::UInt16 => Box::new(u16::new_builder(capacity)),
+            ColumnType::UInt64 => Box::new(u64::new_builder(capacity)),
+            ColumnType::Float64 => Box::new(f64::new_builder(capacity)),
+            ColumnType::Timestamp => Box::new(DateTime::<Utc>::new_builder(capacity)),
+            ColumnType::Ip => Box::new(IpAddr::new_builder(capacity)),
+            ColumnType::Map => Box::new(QueryParams::new_builder(capacity)),
+            ColumnType::Boolean => Box::new(bool::new_builder(capacity)),
+            ColumnType::List => {
+                Box::new(ListBuilder::with_capacity(StringBuilder::new(), capacity))
+            }
+        }
+    }
+}
+
+/// A parsed field value; `-` is logged by nginx for empty values and becomes `Null`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Str(String),
+    UInt(u64),
+    Float(f64),
+    Time(DateTime<Utc>),
+    Ip(IpAddr),
+    List(Vec<String>),
+    Query(QueryParams),
+    Bool(bool),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn append_to(&self, ty: ColumnType, builder: &mut dyn ArrayBuilder) {
+        fn downcast<B: 'static>(builder: &mut dyn ArrayBuilder) -> &mut B {
+            builder
+                .as_any_mut()
+                .downcast_mut::<B>()
+                .expect("column builder does not match the column type")
+        }
+        match (ty, self) {
+            (ColumnType::Utf8, Value::Str(v)) => Some(v.clone()).append_dyn(builder),
+            (ColumnType::Dictionary, Value::Str(v)) => {
+                downcast::<DictionaryBuilder>(builder).append_value(v)
+            }
+            (ColumnType::Dictionary, _) => downcast::<DictionaryBuilder>(builder).append_null(),
+            (ColumnType::UInt16, Value::UInt(v)) => Some(*v as u16).append_dyn(builder),
+            (ColumnType::UInt64, Value::UInt(v)) => Some(*v).append_dyn(builder),
+            (ColumnType::Float64, Value::Float(v)) => Some(*v).append_dyn(builder),
+            (ColumnType::Timestamp, Value::Time(v)) => Some(*v).append_dyn(builder),
+            (ColumnType::Ip, Value::Ip(v)) => Some(*v).append_dyn(builder),
+            (ColumnType::List, Value::List(v)) => {
+                let builder = downcast::<ListBuilder<StringBuilder>>(builder);
+                for item in v {
+                    builder.values().append_value(item);
+                }
+                builder.append(true);
+            }
+            (ColumnType::Map, Value::Query(v)) => v.append_dyn(builder),
+            (ColumnType::Boolean, Value::Bool(v)) => Some(*v).append_dyn(builder),
+            (ColumnType::Map, _) => {
+                QueryParams::append_null(downcast::<<QueryParams as ArrowField>::Builder>(builder))
+            }
+            (ColumnType::List, _) => downcast::<ListBuilder<StringBuilder>>(builder).append_null(),
+            (ColumnType::Utf8, _) => None::<String>.append_dyn(builder),
+            (ColumnType::UInt16, _) => None::<u16>.append_dyn(builder),
+            (ColumnType::UInt64, _) => None::<u64>.append_dyn(builder),
+            (ColumnType::Float64, _) => None::<f64>.append_dyn(builder),
+            (ColumnType::Timestamp, _) => None::<DateTime<Utc>>.append_dyn(builder),
+            (ColumnType::Ip, _) => None::<IpAddr>.append_dyn(builder),
+            (ColumnType::Boolean, _) => None::<bool>.append_dyn(builder),
+        }
+    }
+}
+
+/// A line parsed with a [`LogFormat`], one value per column of its schema.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    columns: Arc<[Column]>,
+    values: Vec<Value>,
+}
+
+impl LogRecord {
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let idx = self.columns.iter().position(|c| c.name == name)?;
+        self.values.get(idx)
+    }
+}
+
+impl AppendRecord for LogRecord {
+    fn append_to(&self, builders: &mut [Box<dyn ArrayBuilder>]) {
+        for ((column, value), builder) in self.columns.iter().zip(&self.values).zip(builders) {
+            value.append_to(column.ty, builder.as_mut());
+        }
+    }
+}
+
+/// How a variable's text is turned into values, for the variables nginx documents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Addr,
+    TimeLocal,
+    TimeIso8601,
+    /// A strftime-style format, as in Apache's `%{format}t`.
+    TimeFormat(String),
+    Msec,
+    EpochMillis,
+    EpochMicros,
+    Request,
+    Status,
+    Count,
+    Seconds,
+    Millis,
+    Micros,
+    ForwardedFor,
+    UserAgent,
+    Text,
+}
+
+impl Kind {
+    fn of(variable: &str) -> Self {
+        match variable {
+            "remote_addr" | "realip_remote_addr" | "server_addr" => Kind::Addr,
+            "time_local" => Kind::TimeLocal,
+            "time_iso8601" => Kind::TimeIso8601,
+            "msec" => Kind::Msec,
+            "request" => Kind::Request,
+            "status" => Kind::Status,
+            "body_bytes_sent"
+            | "bytes_sent"
+            | "request_length"
+            | "connection"
+            | "connection_requests"
+            | "remote_port"
+            | "server_port"
+            | "pid" => Kind::Count,
+            "request_time"
+            | "upstream_response_time"
+            | "upstream_connect_time"
+            | "upstream_header_time" => Kind::Seconds,
+            "http_x_forwarded_for" | "proxy_add_x_forwarded_for" => Kind::ForwardedFor,
+            "http_user_agent" => Kind::UserAgent,
+            _ => Kind::Text,
+        }
+    }
+
+    /// Column names chosen to match [`NginxLog`](crate::nginx::NginxLog) for the combined format.
+    fn columns(&self, variable: &str) -> Vec<Column> {
+        let name = match variable {
+            "remote_addr" => "ip",
+            "time_local" | "time_iso8601" | "msec" => "datetime",
+            "body_bytes_sent" => "body_bytes",
+            "http_referer" => "referrer",
+            "http_user_agent" => "ua",
+            v => v,
+        };
+        match self {
+            Kind::Addr => vec![Column::new(name, ColumnType::Ip)],
+            Kind::TimeLocal
+            | Kind::TimeIso8601
+            | Kind::TimeFormat(_)
+            | Kind::Msec
+            | Kind::EpochMillis
+            | Kind::EpochMicros => vec![Column::new(name, ColumnType::Timestamp)],
+            Kind::Request => vec![
+                Column::new("method", ColumnType::Dictionary),
+                Column::new("url", ColumnType::Utf8),
+                Column::new("path", ColumnType::Utf8),
+                Column::new("query", ColumnType::Map),
+                Column::new("route", ColumnType::Utf8),
+                Column::new("protocol", ColumnType::Dictionary),
+                Column::new("malformed_request", ColumnType::Utf8),
+            ],
+            Kind::Status => vec![Column::new(name, ColumnType::UInt16)],
+            Kind::Count => vec![Column::new(name, ColumnType::UInt64)],
+            Kind::Seconds | Kind::Millis | Kind::Micros => {
+                vec![Column::new(name, ColumnType::Float64)]
+            }
+            Kind::ForwardedFor => vec![Column::new(name, ColumnType::List)],
+            Kind::UserAgent => vec![
+                Column::new(name, ColumnType::Utf8),
+                Column::new("ua_family", ColumnType::Dictionary),
+                Column::new("ua_major", ColumnType::Utf8),
+                Column::new("ua_minor", ColumnType::Utf8),
+                Column::new("os_family", ColumnType::Dictionary),
+                Column::new("os_major", ColumnType::Utf8),
+                Column::new("os_minor", ColumnType::Utf8),
+                Column::new("device_type", ColumnType::Dictionary),
+                Column::new("is_bot", ColumnType::Boolean),
+            ],
+            Kind::Text => vec![Column::new(name, ColumnType::Utf8)],
+        }
+    }
+
+    fn parse(&self, raw: &str, values: &mut Vec<Value>) -> Result<()> {
+        if raw == "-" || raw.is_empty() {
+            let n = match self {
+                Kind::Request => 7,
+                Kind::UserAgent => 9,
+                _ => 1,
+            };
+            values.extend(std::iter::repeat_n(Value::Null, n));
+            return Ok(());
+        }
+        match self {
+            // the same parser as the combined format, so zone IDs are accepted and errors read
+            // the same
+            Kind::Addr => {
+                let ip = parse_ip.parse(raw).map_err(|e| anyhow!("{}", e.inner()))?;
+                values.push(Value::Ip(ip));
+            }
+            Kind::TimeLocal => values.push(Value::Time(parse_time_local(raw)?)),
+            Kind::TimeIso8601 => values.push(Value::Time(
+                DateTime::parse_from_rfc3339(raw)?.with_timezone(&Utc),
+            )),
+            Kind::TimeFormat(format) => {
+                // formats without an offset are taken as UTC
+                let dt = match DateTime::parse_from_str(raw, format) {
+                    Ok(dt) => dt.with_timezone(&Utc),
+                    Err(_) => NaiveDateTime::parse_from_str(raw, format)?.and_utc(),
+                };
+                values.push(Value::Time(dt));
+            }
+            Kind::Msec => {
+                let (secs, frac) = raw.split_once('.').unwrap_or((raw, "0"));
+                if frac.is_empty() || !frac.bytes().all(|b| b.is_ascii_digit()) {
+                    bail!("fraction {frac:?} is not a number");
+                }
+                // digits past microseconds are dropped
+                let micros = format!("{frac:0<6}")[..6].parse::<i64>()?;
+                let dt = DateTime::from_timestamp(secs.parse()?, 0)
+                    .and_then(|dt| dt.checked_add_signed(chrono::Duration::microseconds(micros)))
+                    .ok_or_else(|| anyhow!("timestamp out of range"))?;
+                values.push(Value::Time(dt));
+            }
+            Kind::EpochMillis => {
+                let dt = DateTime::from_timestamp_millis(raw.parse()?)
+                    .ok_or_else(|| anyhow!("timestamp out of range"))?;
+                values.push(Value::Time(dt));
+            }
+            Kind::EpochMicros => {
+                let dt = DateTime::from_timestamp_micros(raw.parse()?)
+                    .ok_or_else(|| anyhow!("timestamp out of range"))?;
+                values.push(Value::Time(dt));
+            }
+            Kind::Request => {
+                let (method, url, protocol, malformed) = parse_request(raw).into_parts();
+                let str_or_null = |s: Option<&str>| s.map_or(Value::Null, |s| Value::Str(s.into()));
+                values.push(str_or_null(method.as_ref().map(AsRef::as_ref)));
+                values.push(str_or_null(url.as_deref()));
+                match url.as_deref().map(RequestTarget::parse) {
+                    Some(t) => values.extend([
+                        Value::Str(t.path),
+                        Value::Query(t.query),
+                        Value::Str(t.route),
+                    ]),
+                    None => values.extend([Value::Null, Value::Null, Value::Null]),
+                }
+                values.push(str_or_null(protocol.as_ref().map(AsRef::as_ref)));
+                values.push(str_or_null(malformed.as_deref()));
+            }
+            Kind::Status => {
+                let status: u16 = raw.parse()?;
+                values.push(Value::UInt(status as u64));
+            }
+            Kind::Count => values.push(Value::UInt(raw.parse()?)),
+            Kind::Seconds => {
+                // upstream times list one value per upstream tried, e.g. `0.002, 0.010 : 0.004`
+                let mut total = None;
+                for part in raw.split([',', ':']).map(str::trim) {
+                    if part != "-" {
+                        *total.get_or_insert(0.0) += part.parse::<f64>()?;
+                    }
+                }
+                values.push(total.map_or(Value::Null, Value::Float));
+            }
+            Kind::Millis => values.push(Value::Float(raw.parse::<f64>()? / 1e3)),
+            Kind::Micros => values.push(Value::Float(raw.parse::<f64>()? / 1e6)),
+            Kind::ForwardedFor => {
+                let addrs = match parse_forwarded_for.parse(raw) {
+                    Ok(addrs) => addrs.iter().map(|a| a.to_string()).collect(),
+                    // keep whatever clients sent, the header is not trustworthy
+                    Err(_) => raw.split(',').map(|a| a.trim().to_string()).collect(),
+                };
+                values.push(Value::List(addrs));
+            }
+            Kind::UserAgent => {
+                let agent = UaParser::bundled().parse(raw);
+                let str_or_null = |s: Option<String>| s.map_or(Value::Null, Value::Str);
+                values.extend([
+                    Value::Str(raw.to_string()),
+                    Value::Str(agent.browser.family),
+                    str_or_null(agent.browser.major),
+                    str_or_null(agent.browser.minor),
+                    Value::Str(agent.os.family),
+                    str_or_null(agent.os.major),
+                    str_or_null(agent.os.minor),
+                    Value::Str(agent.device_type.as_ref().to_string()),
+                    Value::Bool(agent.is_bot),
+                ]);
+            }
+            Kind::Text => values.push(Value::Str(raw.to_string())),
+        }
+        Ok(())
+    }
+}
+
+/// A format compiled down to literals and typed fields, whichever server syntax it came from.
+#[derive(Debug, Clone)]
+enum Piece {
+    Literal(String),
+    Field {
+        /// How the field is written in the format, e.g. `$status` or `%>s`.
+        label: String,
+        /// The equivalent nginx variable, which names the columns.
+        variable: String,
+        kind: Kind,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Literal(String),
+    Field {
+        label: String,
+        /// Reported as [`LineError::kind`], the column name or `request` for the request line.
+        error_kind: String,
+        kind: Kind,
+        until: Option<String>,
+        /// Indices of the columns the field fills.
+        columns: Range<usize>,
+    },
+}
+
+/// A line parser and Arrow schema compiled from an nginx `log_format` or an Apache `LogFormat`.
+#[derive(Debug, Clone)]
+pub struct LogFormat {
+    format: String,
+    escape: Escape,
+    steps: Vec<Step>,
+    /// Every column the format logs, whether projected or not.
+    all_columns: Arc<[Column]>,
+    /// Which of `all_columns` are parsed; fields with no wanted column are skipped.
+    wanted: Arc<[bool]>,
+    projection: Option<Arc<[usize]>>,
+    columns: Arc<[Column]>,
+    schema: SchemaRef,
+}
+
+impl LogFormat {
+    /// nginx's predefined `combined` format.
+    pub const COMBINED: &'static str = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent""#;
+
+    pub fn combined() -> Self {
+        Self::compile(Self::COMBINED).expect("the combined format compiles")
+    }
+
+    pub fn compile(format: &str) -> Result<Self> {
+        let segments = parse_log_format
+            .parse(format)
+            .map_err(|e| anyhow!("invalid log_format {format:?}: {e}"))?;
+        let pieces = segments
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Literal(l) => Piece::Literal(l),
+                Segment::Variable(variable) => Piece::Field {
+                    label: format!("${variable}"),
+                    kind: Kind::of(&variable),
+                    variable,
+                },
+            })
+            .collect();
+        Self::from_pieces(format, pieces)
+    }
+
+    fn from_pieces(format: &str, pieces: Vec<Piece>) -> Result<Self> {
+        let mut steps: Vec<Step> = Vec::with_capacity(pieces.len());
+        let mut columns: Vec<Column> = Vec::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            match piece {
+                Piece::Literal(l) => match steps.last_mut() {
+                    Some(Step::Literal(prev)) => prev.push_str(l),
+                    _ => steps.push(Step::Literal(l.clone())),
+                },
+                Piece::Field {
+                    label,
+                    variable,
+                    kind,
+                } => {
+                    let until = match &pieces[i + 1..] {
+                        [Piece::Literal(l), ..] => Some(l.clone()),
+                        [Piece::Field { label: next, .. }, ..] => {
+                            bail!("{label} and {next} must be separated by a literal to be parsed")
+                        }
+                        [] => None,
+                    };
+                    let first = columns.len();
+                    for mut column in kind.columns(variable) {
+                        if columns.iter().any(|c| c.name == column.name) {
+                            column.name = variable.clone();
+                        }
+                        if columns.iter().any(|c| c.name == column.name) {
+                            bail!("{label} is logged more than once");
+                        }
+                        columns.push(column);
+                    }
+                    let error_kind = match kind {
+                        Kind::Request => "request".to_string(),
+                        _ => columns[first].name.clone(),
+                    };
+                    steps.push(Step::Field {
+                        label: label.clone(),
+                        error_kind,
+                        kind: kind.clone(),
+                        until,
+                        columns: first..columns.len(),
+                    });
+                }
+            }
+        }
+
+        let schema = Arc::new(Schema::new(
+            columns.iter().map(Column::field).collect::<Vec<_>>(),
+        ));
+        let columns: Arc<[Column]> = columns.into();
+        Ok(Self {
+            format: format.to_string(),
+            escape: Escape::Default,
+            steps,
+            all_columns: columns.clone(),
+            wanted: vec![true; columns.len()].into(),
+            projection: None,
+            columns,
+            schema,
+        })
+    }
+
+    /// Compiles either a bare format string or a complete `log_format` directive.
+    pub fn from_config(s: &str) -> Result<Self> {
+        if s.trim_start().starts_with("log_format") {
+            let directive = parse_log_format_directive
+                .parse(s)
+                .map_err(|e| anyhow!("invalid log_format directive: {e}"))?;
+            Ok(Self::compile(&directive.format)?.with_escape(directive.escape))
+        } else {
+            Self::compile(s)
+        }
+    }
+
+    /// Sets how values were escaped when logged, see [`unescape`](crate::nginx::escape::unescape).
+    pub fn with_escape(mut self, escape: Escape) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Parses only the columns at `projection`, indices into the columns of the whole format.
+    ///
+    /// Fields none of whose columns are projected are skipped without being parsed or
+    /// checked, and user agents are only classified when one of the `ua_*`, `os_*`,
+    /// `device_type` or `is_bot` columns is projected.
+    pub fn with_projection(mut self, projection: &[usize]) -> Result<Self> {
+        let mut wanted = vec![false; self.all_columns.len()];
+        let mut columns = Vec::with_capacity(projection.len());
+        for &i in projection {
+            let Some(column) = self.all_columns.get(i) else {
+                bail!(
+                    "column {i} is out of range, the format has {}",
+                    wanted.len()
+                );
+            };
+            wanted[i] = true;
+            columns.push(column.clone());
+        }
+        self.schema = Arc::new(Schema::new(
+            columns.iter().map(Column::field).collect::<Vec<_>>(),
+        ));
+        self.wanted = wanted.into();
+        self.projection = Some(projection.into());
+        self.columns = columns.into();
+        Ok(self)
+    }
+
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    pub fn escape(&self) -> Escape {
+        self.escape
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    pub fn new_batch_builder(&self, capacity: usize) -> RecordBatchBuilder<LogRecord> {
+        let builders = self
+            .columns
+            .iter()
+            .map(|c| c.new_builder(capacity))
+            .collect();
+        RecordBatchBuilder::new(self.schema(), builders)
+    }
+
+    pub fn parse_line(&self, line: &str) -> Result<LogRecord, LineError> {
+        let input = &mut &*line;
+        let mut values = Vec::with_capacity(self.all_columns.len());
+        for step in &self.steps {
+            match step {
+                Step::Literal(l) => {
+                    if !input.starts_with(l.as_str()) {
+                        let message = format!("expected {l:?} at {:?}", truncate(input));
+                        return Err(LineError::new("syntax", message));
+                    }
+                    *input = &input[l.len()..];
+                }
+                Step::Field {
+                    label,
+                    error_kind,
+                    kind,
+                    until,
+                    columns,
+                } => {
+                    let raw = match until {
+                        Some(l) => take_field(input, l).ok_or_else(|| {
+                            LineError::new("syntax", format!("{label} is not terminated by {l:?}"))
+                        })?,
+                        None => std::mem::take(input),
+                    };
+                    // every field is validated so the rows a table returns do not depend on the
+                    // columns a query reads; only values nobody reads are dropped again
+                    let kind = match kind {
+                        // classifying cannot fail, so it is skipped when nothing reads it
+                        Kind::UserAgent if !columns.clone().skip(1).any(|i| self.wanted[i]) => {
+                            &Kind::Text
+                        }
+                        kind => kind,
+                    };
+                    let value = unescape_str(raw, self.escape);
+                    kind.parse(&value, &mut values).map_err(|e| {
+                        LineError::new(error_kind, format!("invalid {label} {raw:?}: {e:#}"))
+                    })?;
+                    if !columns.clone().any(|i| self.wanted[i]) {
+                        values.truncate(columns.start);
+                    }
+                    values.resize(columns.end, Value::Null);
+                }
+            }
+        }
+        if !input.is_empty() {
+            let message = format!("unexpected trailing text {:?}", truncate(input));
+            return Err(LineError::new("syntax", message));
+        }
+        if let Some(projection) = &self.projection {
+            values = projection.iter().map(|&i| values[i].clone()).collect();
+        }
+        Ok(LogRecord {
+            columns: self.columns.clone(),
+            values,
+        })
+    }
+}
+
+/// Takes input up to `until`; inside quotes a backslash escapes the next byte, so Apache's
+/// `\"` does not end the field.
+fn take_field<'s>(input: &mut &'s str, until: &str) -> Option<&'s str> {
+    let bytes = input.as_bytes();
+    let quoted = until.starts_with('"');
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(until.as_bytes()) {
+            let (raw, tail) = input.split_at(i);
+            *input = tail;
+            return Some(raw);
+        }
+        i += if quoted && bytes[i] == b'\\' { 2 } else { 1 };
+    }
+    None
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_config(s)
+    }
+}
+
+fn truncate(s: &str) -> &str {
+    match s.char_indices().nth(32) {
+        Some((i, _)) => &s[..i],
+        None => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::{Float64Type, UInt16Type};
+
+    use super::*;
+
+    const LINE: &str = r#"93.180.71.3 - - [17/May/2015:08:05:32 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)""#;
+
+    #[test]
+    fn test_parse_log_format() {
+        let v = parse_log_format
+            .parse("$remote_addr - ${remote_user}x [$time_local] $")
+            .unwrap();
+        assert_eq!(
+            v,
+            vec![
+                Segment::Variable("remote_addr".to_string()),
+                Segment::Literal(" - ".to_string()),
+                Segment::Variable("remote_user".to_string()),
+                Segment::Literal("x [".to_string()),
+                Segment::Variable("time_local".to_string()),
+                Segment::Literal("] $".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_log_format_directive() {
+        let s = r#"log_format main escape=json '$remote_addr - $remote_user '
+                       '"$request" \'$status\'';"#;
+        let v = parse_log_format_directive.parse(s).unwrap();
+        assert_eq!(v.name, "main");
+        assert_eq!(v.escape, Escape::Json);
+        assert_eq!(
+            v.format,
+            r#"$remote_addr - $remote_user "$request" '$status'"#
+        );
+    }
+
+    #[test]
+    fn test_combined_schema() {
+        let format = LogFormat::combined();
+        let names: Vec<_> = format.columns().iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "ip",
+                "remote_user",
+                "datetime",
+                "method",
+                "url",
+                "path",
+                "query",
+                "route",
+                "protocol",
+                "malformed_request",
+                "status",
+                "body_bytes",
+                "referrer",
+                "ua",
+                "ua_family",
+                "ua_major",
+                "ua_minor",
+                "os_family",
+                "os_major",
+                "os_minor",
+                "device_type",
+                "is_bot"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_combined_line() {
+        let record = LogFormat::combined().parse_line(LINE).unwrap();
+        assert_eq!(
+            record.get("ip"),
+            Some(&Value::Ip("93.180.71.3".parse().unwrap()))
+        );
+        assert_eq!(record.get("remote_user"), Some(&Value::Null));
+        assert_eq!(
+            record.get("datetime"),
+            Some(&Value::Time(
+                parse_time_local("17/May/2015:08:05:32 +0000").unwrap()
+            ))
+        );
+        assert_eq!(record.get("method").and_then(Value::as_str), Some("GET"));
+        assert_eq!(record.get("status"), Some(&Value::UInt(304)));
+        assert_eq!(record.get("referrer"), Some(&Value::Null));
+        assert_eq!(record.get("ua_family").and_then(Value::as_str), Some("APT"));
+        assert_eq!(record.get("is_bot"), Some(&Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_trailing_text() {
+        let err = LogFormat::combined()
+            .parse_line(&format!("{LINE} \"-\""))
+            .unwrap_err();
+        assert_eq!(err.kind, "syntax");
+        assert!(err.message.contains("trailing"), "{}", err.message);
+        // a last field takes the rest of the line, so leftovers fail its own parse
+        let format = LogFormat::compile("$status $request_time").unwrap();
+        assert!(format.parse_line("200 0.5 extra").is_err());
+    }
+
+    #[test]
+    fn test_custom_format() {
+        let format = LogFormat::from_config(
+            r#"log_format upstream '$host $remote_addr [$time_iso8601] "$request" $status '
+                '$request_time $upstream_response_time "$http_x_forwarded_for" $msec';"#,
+        )
+        .unwrap();
+        let line = r#"example.com 10.0.0.1 [2015-05-17T08:05:32+00:00] "POST /api HTTP/1.1" 502 0.105 0.050, 0.051 : - "203.0.113.7, 10.0.0.2" 1431849932.250"#;
+        let record = format.parse_line(line).unwrap();
+        assert_eq!(
+            record.get("host").and_then(Value::as_str),
+            Some("example.com")
+        );
+        assert_eq!(record.get("request_time"), Some(&Value::Float(0.105)));
+        let Some(Value::Float(upstream)) = record.get("upstream_response_time") else {
+            panic!("upstream_response_time is not a float");
+        };
+        assert!((upstream - 0.101).abs() < 1e-9);
+        assert_eq!(
+            record.get("http_x_forwarded_for"),
+            Some(&Value::List(vec![
+                "203.0.113.7".to_string(),
+                "10.0.0.2".to_string()
+            ]))
+        );
+        let Some(Value::Time(msec)) = record.get("msec") else {
+            panic!("msec is not a timestamp");
+        };
+        assert_eq!(msec.timestamp_micros(), 1_431_849_932_250_000);
+
+        let msec: LogFormat = "$msec $status".parse().unwrap();
+        let precise = msec.parse_line("1431849932.1234567 200").unwrap();
+        let Some(Value::Time(time)) = precise.get("datetime") else {
+            panic!("msec is not a timestamp");
+        };
+        assert_eq!(time.timestamp_micros(), 1_431_849_932_123_456);
+        let err = msec.parse_line("1431849932.12345é 200").unwrap_err();
+        assert_eq!(err.kind, "datetime");
+
+        let addr: LogFormat = "$remote_addr $status".parse().unwrap();
+        let zoned = addr.parse_line("fe80::1%eth0 200").unwrap();
+        assert_eq!(
+            zoned.get("ip"),
+            Some(&Value::Ip("fe80::1".parse().unwrap()))
+        );
+        let err = addr.parse_line("93.180.71.300 200").unwrap_err();
+        assert_eq!(err.kind, "ip");
+        assert!(err.message.contains("octet"), "{}", err.message);
+
+        let mut builder = format.new_batch_builder(2);
+        builder.append(&record);
+        let batch = builder.finish().unwrap();
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(
+            batch
+                .column_by_name("status")
+                .unwrap()
+                .as_primitive::<UInt16Type>()
+                .value(0),
+            502
+        );
+        assert_eq!(
+            batch
+                .column_by_name("request_time")
+                .unwrap()
+                .as_primitive::<Float64Type>()
+                .value(0),
+            0.105
+        );
+        assert_eq!(
+            batch
+                .column_by_name("http_x_forwarded_for")
+                .unwrap()
+                .as_list::<i32>()
+                .value(0)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_escaped_values() {
+        let line = r#"10.0.0.1 - - [17/May/2015:08:05:32 +0000] "GET /a\x22b HTTP/1.1" 200 0 "-" "Mozilla \x22x\x22 \xE4\xBD\xA0 \xFF""#;
+        let record = LogFormat::combined().parse_line(line).unwrap();
+        assert_eq!(record.get("url").and_then(Value::as_str), Some("/a\"b"));
+        // not UTF-8 once decoded, so the escaped text is kept
+        assert_eq!(
+            record.get("ua").and_then(Value::as_str),
+            Some(r"Mozilla \x22x\x22 \xE4\xBD\xA0 \xFF")
+        );
+
+        let format = LogFormat::from_config(
+            r#"log_format j escape=json '{"ua":"$http_user_agent","uri":"$request_uri"}';"#,
+        )
+        .unwrap();
+        assert_eq!(format.escape(), Escape::Json);
+        let record = format
+            .parse_line(r#"{"ua":"say \"hi\"\u0009","uri":"/\\x"}"#)
+            .unwrap();
+        assert_eq!(
+            record.get("ua").and_then(Value::as_str),
+            Some("say \"hi\"\t")
+        );
+        assert_eq!(
+            record.get("request_uri").and_then(Value::as_str),
+            Some("/\\x")
+        );
+    }
+
+    #[test]
+    fn test_null_values() {
+        let format =
+            LogFormat::compile("$remote_addr $upstream_response_time \"$request\"").unwrap();
+        let record = format.parse_line("::1 - \"-\"").unwrap();
+        assert_eq!(record.get("upstream_response_time"), Some(&Value::Null));
+        assert_eq!(record.get("url"), Some(&Value::Null));
+
+        let mut builder = format.new_batch_builder(1);
+        builder.append(&record);
+        let batch = builder.finish().unwrap();
+        assert!(batch
+            .column_by_name("upstream_response_time")
+            .unwrap()
+            .is_null(0));
+        assert!(batch.column_by_name("method").unwrap().is_null(0));
+    }
+
+    #[test]
+    fn test_projection() {
+        let combined = LogFormat::combined();
+        let index = |name: &str| {
+            let columns = combined.columns();
+            columns.iter().position(|c| c.name == name).unwrap()
+        };
+        let format = combined
+            .clone()
+            .with_projection(&[index("status"), index("ua")])
+            .unwrap();
+        let names: Vec<_> = format
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(names, ["status", "ua"]);
+        let record = format.parse_line(LINE).unwrap();
+        assert_eq!(record.values().len(), 2);
+        assert_eq!(record.get("status"), Some(&Value::UInt(304)));
+        assert!(record
+            .get("ua")
+            .and_then(Value::as_str)
+            .unwrap()
+            .starts_with("Debian"));
+
+        // fields outside the projection are still checked
+        let bad_time = LINE.replace("17/May/2015", "17/Foo/2015");
+        assert!(combined.parse_line(&bad_time).is_err());
+        assert_eq!(format.parse_line(&bad_time).unwrap_err().kind, "datetime");
+
+        let format = combined
+            .clone()
+            .with_projection(&[index("is_bot")])
+            .unwrap();
+        let record = format.parse_line(LINE).unwrap();
+        assert_eq!(record.values(), [Value::Bool(true)]);
+        assert!(combined.clone().with_projection(&[99]).is_err());
+
+        // a column projected twice, as for `SELECT status, status`
+        let status = index("status");
+        let format = combined.with_projection(&[status, status]).unwrap();
+        let record = format.parse_line(LINE).unwrap();
+        assert_eq!(record.values(), [Value::UInt(304), Value::UInt(304)]);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        assert!(LogFormat::compile("$remote_addr$remote_user").is_err());
+        assert!(LogFormat::compile("$status $status").is_err());
+        let format = LogFormat::compile("$status $body_bytes_sent").unwrap();
+        assert_eq!(format.parse_line("abc 0").unwrap_err().kind, "status");
+        assert!(format.parse_line("200").is_err());
+    }
+}
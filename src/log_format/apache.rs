@@ -0,0 +1,330 @@
+use anyhow::{anyhow, bail, Result};
+use winnow::{
+    ascii::{multispace0, multispace1},
+    combinator::{alt, delimited, opt, preceded, repeat},
+    token::{any, take_till, take_while},
+    PResult, Parser,
+};
+
+use super::{parse_quoted, Kind, LogFormat, Piece};
+
+/// Apache's `common` nickname.
+pub const COMMON: &str = r#"%h %l %u %t "%r" %>s %b"#;
+/// Apache's `combined` nickname.
+pub const COMBINED: &str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-agent}i""#;
+
+/// One piece of an Apache `LogFormat` string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directive {
+    Literal(String),
+    /// A `%` directive such as `%>s` or `%{User-Agent}i`; status conditions and `<`/`>` are
+    /// kept in `text` only, Apache logs `-` when a condition does not hold.
+    Field {
+        text: String,
+        arg: Option<String>,
+        letter: char,
+    },
+}
+
+pub fn parse_apache_format(s: &mut &str) -> PResult<Vec<Directive>> {
+    repeat(
+        0..,
+        alt((
+            "%%".value(Directive::Literal("%".to_string())),
+            parse_field,
+            take_till(1.., '%').map(|l: &str| Directive::Literal(l.to_string())),
+        )),
+    )
+    .parse_next(s)
+}
+
+fn parse_field(s: &mut &str) -> PResult<Directive> {
+    preceded(
+        '%',
+        (
+            take_while(0.., |c: char| "<>!,".contains(c) || c.is_ascii_digit()),
+            opt(delimited('{', take_till(0.., '}'), '}')),
+            any,
+        ),
+    )
+    .with_taken()
+    .map(
+        |((_, arg, letter), text): ((_, Option<&str>, char), &str)| Directive::Field {
+            text: text.to_string(),
+            arg: arg.map(str::to_string),
+            letter,
+        },
+    )
+    .parse_next(s)
+}
+
+/// Parses `LogFormat "..." [nickname]` and returns the format string.
+fn parse_log_format_directive(s: &mut &str) -> PResult<String> {
+    let _ = (multispace0, "LogFormat", multispace1).parse_next(s)?;
+    let format = parse_quoted.parse_next(s)?;
+    let _ = (
+        multispace0,
+        take_till(0.., char::is_whitespace),
+        multispace0,
+    )
+        .parse_next(s)?;
+    Ok(format)
+}
+
+impl LogFormat {
+    /// Compiles an Apache httpd `LogFormat` string.
+    ///
+    /// Fields get the same column names as the equivalent nginx variables, so `%h`, `%t`,
+    /// `%r` and `%{User-Agent}i` land in `ip`, `datetime`, `method`/`url`/`protocol` and `ua`.
+    /// `%D` and `%T` both become `request_time` in seconds.
+    pub fn compile_apache(format: &str) -> Result<Self> {
+        let directives = parse_apache_format
+            .parse(format)
+            .map_err(|e| anyhow!("invalid LogFormat {format:?}: {e}"))?;
+        let mut pieces = Vec::with_capacity(directives.len());
+        for directive in directives {
+            match directive {
+                Directive::Literal(l) => pieces.push(Piece::Literal(l)),
+                Directive::Field { text, arg, letter } => {
+                    if letter == 't' && arg.is_none() {
+                        // the default time format brings its own brackets
+                        pieces.push(Piece::Literal("[".to_string()));
+                        pieces.push(field(text, "time_local", Kind::TimeLocal));
+                        pieces.push(Piece::Literal("]".to_string()));
+                    } else {
+                        pieces.push(compile_field(text, arg.as_deref(), letter)?);
+                    }
+                }
+            }
+        }
+        Self::from_pieces(format, pieces)
+    }
+
+    pub fn apache_common() -> Self {
+        Self::compile_apache(COMMON).expect("the common format compiles")
+    }
+
+    pub fn apache_combined() -> Self {
+        Self::compile_apache(COMBINED).expect("the combined format compiles")
+    }
+
+    /// Compiles a bare format string, a `LogFormat` line, or the `common`/`combined` nicknames.
+    pub fn from_apache_config(s: &str) -> Result<Self> {
+        match s.trim() {
+            "common" => Ok(Self::apache_common()),
+            "combined" => Ok(Self::apache_combined()),
+            t if t.starts_with("LogFormat") => {
+                let format = parse_log_format_directive
+                    .parse(t)
+                    .map_err(|e| anyhow!("invalid LogFormat directive: {e}"))?;
+                Self::compile_apache(&format)
+            }
+            _ => Self::compile_apache(s),
+        }
+    }
+}
+
+fn field(label: String, variable: &str, kind: Kind) -> Piece {
+    Piece::Field {
+        label,
+        variable: variable.to_string(),
+        kind,
+    }
+}
+
+fn compile_field(text: String, arg: Option<&str>, letter: char) -> Result<Piece> {
+    let named = |prefix: &str| -> Result<String> {
+        let arg = arg.ok_or_else(|| anyhow!("{text} needs a {{name}}"))?;
+        Ok(format!(
+            "{prefix}{}",
+            arg.to_ascii_lowercase().replace('-', "_")
+        ))
+    };
+    let variable = match letter {
+        'a' | 'h' => "remote_addr".to_string(),
+        'A' => "server_addr".to_string(),
+        'b' | 'B' => "body_bytes_sent".to_string(),
+        'C' => named("cookie_")?,
+        'D' => return Ok(field(text, "request_time", Kind::Micros)),
+        'e' => named("")?,
+        'f' => "request_filename".to_string(),
+        'H' => "server_protocol".to_string(),
+        'i' => named("http_")?,
+        'I' => "request_length".to_string(),
+        'k' => "connection_requests".to_string(),
+        'l' => "remote_ident".to_string(),
+        'L' => "request_id".to_string(),
+        'm' => "request_method".to_string(),
+        'n' => named("note_")?,
+        'o' => named("sent_http_")?,
+        'O' => "bytes_sent".to_string(),
+        'p' if arg == Some("remote") => "remote_port".to_string(),
+        'p' => "server_port".to_string(),
+        'P' => "pid".to_string(),
+        'q' => "query_string".to_string(),
+        'r' => "request".to_string(),
+        'R' => "handler".to_string(),
+        's' => "status".to_string(),
+        'S' => return Ok(field(text, "bytes_transferred", Kind::Count)),
+        't' => return compile_time(text, arg.unwrap_or_default()),
+        'T' => {
+            let kind = match arg {
+                None | Some("s") => Kind::Seconds,
+                Some("ms") => Kind::Millis,
+                Some("us") => Kind::Micros,
+                Some(unit) => bail!("unknown time unit {unit:?} in {text}"),
+            };
+            return Ok(field(text, "request_time", kind));
+        }
+        'u' => "remote_user".to_string(),
+        'U' => "uri".to_string(),
+        'v' => "server_name".to_string(),
+        'V' => "host".to_string(),
+        'X' => "connection_status".to_string(),
+        _ => bail!("unsupported LogFormat directive {text}"),
+    };
+    let kind = Kind::of(&variable);
+    Ok(field(text, &variable, kind))
+}
+
+fn compile_time(text: String, format: &str) -> Result<Piece> {
+    let format = format
+        .strip_prefix("begin:")
+        .or_else(|| format.strip_prefix("end:"))
+        .unwrap_or(format);
+    Ok(match format {
+        "sec" => field(text, "msec", Kind::Msec),
+        "msec" => field(text, "msec", Kind::EpochMillis),
+        "usec" => field(text, "msec", Kind::EpochMicros),
+        "msec_frac" | "usec_frac" => field(text, format, Kind::Text),
+        _ => field(text, "time_local", Kind::TimeFormat(format.to_string())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_format::Value;
+
+    #[test]
+    fn test_parse_apache_format() {
+        let v = parse_apache_format
+            .parse("%h %!200,304{Referer}i 100%% %>s")
+            .unwrap();
+        assert_eq!(
+            v,
+            vec![
+                Directive::Field {
+                    text: "%h".to_string(),
+                    arg: None,
+                    letter: 'h'
+                },
+                Directive::Literal(" ".to_string()),
+                Directive::Field {
+                    text: "%!200,304{Referer}i".to_string(),
+                    arg: Some("Referer".to_string()),
+                    letter: 'i'
+                },
+                Directive::Literal(" 100".to_string()),
+                Directive::Literal("%".to_string()),
+                Directive::Literal(" ".to_string()),
+                Directive::Field {
+                    text: "%>s".to_string(),
+                    arg: None,
+                    letter: 's'
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_common() {
+        let format = LogFormat::apache_common();
+        let names: Vec<_> = format.columns().iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "ip",
+                "remote_ident",
+                "remote_user",
+                "datetime",
+                "method",
+                "url",
+                "path",
+                "query",
+                "route",
+                "protocol",
+                "malformed_request",
+                "status",
+                "body_bytes"
+            ]
+        );
+        let record = format
+            .parse_line(r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 -"#)
+            .unwrap();
+        assert_eq!(
+            record.get("remote_user").and_then(Value::as_str),
+            Some("frank")
+        );
+        assert_eq!(record.get("status"), Some(&Value::UInt(200)));
+        assert_eq!(record.get("body_bytes"), Some(&Value::Null));
+        let Some(Value::Time(t)) = record.get("datetime") else {
+            panic!("datetime is not a timestamp");
+        };
+        assert_eq!(t.to_rfc3339(), "2000-10-10T20:55:36+00:00");
+    }
+
+    #[test]
+    fn test_combined_escaped_quotes() {
+        let line = r#"10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326 "http://example.com/" "Mozilla/5.0 \"quoted\" (X11)""#;
+        let record = LogFormat::apache_combined().parse_line(line).unwrap();
+        assert_eq!(
+            record.get("referrer").and_then(Value::as_str),
+            Some("http://example.com/")
+        );
+        assert_eq!(
+            record.get("ua").and_then(Value::as_str),
+            Some(r#"Mozilla/5.0 "quoted" (X11)"#)
+        );
+    }
+
+    #[test]
+    fn test_custom_format() {
+        let format = LogFormat::from_apache_config(
+            r#"LogFormat "%a %V [%{%Y-%m-%d %H:%M:%S}t] %{ms}T %P \"%{X-Forwarded-For}i\" %{msec}t" timing"#,
+        )
+        .unwrap();
+        let line = r#"::1 example.com [2015-05-17 08:05:32] 105 4242 "203.0.113.7, 10.0.0.2" 1431849932250"#;
+        let record = format.parse_line(line).unwrap();
+        assert_eq!(
+            record.get("host").and_then(Value::as_str),
+            Some("example.com")
+        );
+        let Some(Value::Time(t)) = record.get("datetime") else {
+            panic!("datetime is not a timestamp");
+        };
+        assert_eq!(t.to_rfc3339(), "2015-05-17T08:05:32+00:00");
+        assert_eq!(record.get("request_time"), Some(&Value::Float(0.105)));
+        assert_eq!(record.get("pid"), Some(&Value::UInt(4242)));
+        assert_eq!(
+            record.get("http_x_forwarded_for"),
+            Some(&Value::List(vec![
+                "203.0.113.7".to_string(),
+                "10.0.0.2".to_string()
+            ]))
+        );
+        let Some(Value::Time(msec)) = record.get("msec") else {
+            panic!("msec is not a timestamp");
+        };
+        assert_eq!(msec.timestamp_millis(), 1_431_849_932_250);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        assert!(LogFormat::compile_apache("%h%l").is_err());
+        assert!(LogFormat::compile_apache("%{foo}T").is_err());
+        assert!(LogFormat::compile_apache("%i").is_err());
+        assert!(LogFormat::compile_apache("%Z").is_err());
+        assert!(LogFormat::compile_apache("%D %T").is_err());
+    }
+}
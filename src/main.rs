@@ -1,3 +1,513 @@
-fn main() {
-    println!("Hello, world!");
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser as _, ValueEnum};
+use datafusion::prelude::{CsvReadOptions, SessionConfig, SessionContext};
+use grammar::query::{self, OutputFormat, NGINX_TABLE};
+use rustyline::{
+    completion::{Completer, FilenameCompleter, Pair},
+    error::ReadlineError,
+    history::DefaultHistory,
+    Context, Editor, Helper, Highlighter, Hinter, Validator,
+};
+use winnow::{
+    ascii::{space0, space1},
+    combinator::{alt, delimited, preceded, repeat},
+    token::{take_till, take_while},
+    PResult, Parser,
+};
+
+const HISTORY_FILE: &str = ".grammar_history";
+
+const HELP: &str = r#"\load nginx <path> [table]   Parquet file or dataset directory, otherwise raw access logs
+\load csv <path> [table]     CSV with a header row, named after the file by default
+\format [table|csv|json]     show or set how results are printed
+\timing [on|off]             show or toggle query timing
+\tables                      list the registered tables
+\help                        this text
+\q                           quit
+Anything else is SQL, run once a line ends with `;`."#;
+
+const COMMANDS: &[&str] = &[
+    "\\load", "\\format", "\\timing", "\\tables", "\\help", "\\quit", "\\q",
+];
+
+const KEYWORDS: &[&str] = &[
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "GROUP BY",
+    "ORDER BY",
+    "HAVING",
+    "LIMIT",
+    "OFFSET",
+    "AS",
+    "AND",
+    "OR",
+    "NOT",
+    "IN",
+    "IS NULL",
+    "IS NOT NULL",
+    "LIKE",
+    "BETWEEN",
+    "DISTINCT",
+    "COUNT",
+    "SUM",
+    "AVG",
+    "MIN",
+    "MAX",
+    "CASE",
+    "WHEN",
+    "THEN",
+    "ELSE",
+    "END",
+    "JOIN",
+    "LEFT JOIN",
+    "ON",
+    "WITH",
+    "DESC",
+    "ASC",
+    "DESCRIBE",
+    "EXPLAIN",
+    "SHOW TABLES",
+    "CREATE EXTERNAL TABLE",
+    "STORED AS",
+    "LOCATION",
+    "date_trunc",
+    "cidr_contains",
+    "ip_to_int",
+    "url_path",
+    "url_param",
+    "ua_family",
+    "status_class",
+    "json_get",
+    "json_get_int",
+    "json_typeof",
+    "json_agg",
+    "json_agg_raw",
+];
+
+/// Interactive SQL over parsed access logs and CSV files.
+#[derive(Debug, clap::Parser)]
+struct Cli {
+    /// How results are printed.
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+    /// Print how long each statement took.
+    #[arg(long)]
+    timing: bool,
+    /// Run these commands or statements and exit instead of prompting.
+    #[arg(short, long)]
+    execute: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Source {
+    /// Parquet written by the exporters, or raw logs parsed as they are queried.
+    Nginx,
+    Csv,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Load {
+        source: Source,
+        path: PathBuf,
+        table: Option<String>,
+    },
+    Format(Option<OutputFormat>),
+    Timing(Option<bool>),
+    Tables,
+    Help,
+    Quit,
+}
+
+/// A backslash command name and its arguments, which may be quoted.
+fn command_words(input: &mut &str) -> PResult<(String, Vec<String>)> {
+    let word = || {
+        alt((
+            delimited('\'', take_till(0.., '\''), '\''),
+            delimited('"', take_till(0.., '"'), '"'),
+            take_till(1.., char::is_whitespace),
+        ))
+        .map(str::to_string)
+    };
+    let name = preceded(
+        '\\',
+        take_while(1.., |c: char| c.is_alphanumeric() || c == '?'),
+    );
+    delimited(
+        space0,
+        (
+            name.map(str::to_lowercase),
+            repeat(0.., preceded(space1, word())),
+        ),
+        space0,
+    )
+    .parse_next(input)
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self> {
+        let (name, args) = command_words
+            .parse(line)
+            .map_err(|e| anyhow!("invalid command: {e}"))?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let command = match (name.as_str(), args.as_slice()) {
+            ("load", [source, path, table @ ..]) if table.len() <= 1 => Command::Load {
+                source: Source::from_str(source, true).map_err(|e| anyhow!(e))?,
+                path: PathBuf::from(path),
+                table: table.first().map(|t| t.to_string()),
+            },
+            ("load", _) => bail!("usage: \\load nginx|csv <path> [table]"),
+            ("format", []) => Command::Format(None),
+            ("format", [format]) => Command::Format(Some(
+                OutputFormat::from_str(format, true).map_err(|e| anyhow!(e))?,
+            )),
+            ("timing", []) => Command::Timing(None),
+            ("timing", ["on"]) => Command::Timing(Some(true)),
+            ("timing", ["off"]) => Command::Timing(Some(false)),
+            ("tables", []) => Command::Tables,
+            ("help" | "?", []) => Command::Help,
+            ("q" | "quit", []) => Command::Quit,
+            _ => bail!("unknown command \\{name}, try \\help"),
+        };
+        Ok(command)
+    }
+}
+
+/// Completes commands, file names after `\load`, SQL keywords and the tables and columns
+/// registered so far.
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ReplHelper {
+    files: FilenameCompleter,
+    names: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let words: Vec<&str> = before.split_whitespace().collect();
+        let finished = before.ends_with(char::is_whitespace);
+        let index = words.len() - usize::from(!finished && !words.is_empty());
+        if before.trim_start().starts_with('\\') {
+            let candidates: &[&str] = match (words[0], index) {
+                (_, 0) => COMMANDS,
+                ("\\load", 1) => &["nginx", "csv"],
+                ("\\load", 2) => return self.files.complete(line, pos, ctx),
+                ("\\format", 1) => &["table", "csv", "json"],
+                ("\\timing", 1) => &["on", "off"],
+                _ => &[],
+            };
+            let start = pos - if finished { 0 } else { words[index].len() };
+            return Ok((
+                start,
+                matching(&before[start..], candidates.iter().copied()),
+            ));
+        }
+        let start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let prefix = &before[start..];
+        if prefix.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+        let lower = prefix.chars().all(|c| !c.is_uppercase());
+        let keywords = KEYWORDS.iter().map(|k| match lower {
+            true => Cow::Owned(k.to_lowercase()),
+            false => Cow::Borrowed(*k),
+        });
+        let names = self.names.iter().map(|n| Cow::Borrowed(n.as_str()));
+        Ok((start, matching(prefix, names.chain(keywords))))
+    }
+}
+
+fn matching<'a>(prefix: &str, words: impl Iterator<Item = impl Into<Cow<'a, str>>>) -> Vec<Pair> {
+    let mut pairs: Vec<Pair> = words
+        .map(Into::into)
+        .filter(|w| w.to_lowercase().starts_with(&prefix.to_lowercase()))
+        .map(|w| Pair {
+            display: w.to_string(),
+            replacement: w.into_owned(),
+        })
+        .collect();
+    pairs.dedup_by(|a, b| a.replacement == b.replacement);
+    pairs
+}
+
+struct Repl {
+    ctx: SessionContext,
+    format: OutputFormat,
+    timing: bool,
+}
+
+impl Repl {
+    fn new(cli: &Cli) -> Result<Self> {
+        let config = SessionConfig::new().with_information_schema(true);
+        let ctx = SessionContext::new_with_config(config);
+        // lets `\load` and statements create `STORED AS NGINX` tables over raw logs
+        query::register_nginx_format(&ctx)?;
+        query::register_udfs(&ctx);
+        Ok(Self {
+            ctx,
+            format: cli.format,
+            timing: cli.timing,
+        })
+    }
+
+    /// Returns `false` once the user asks to quit.
+    async fn command(&mut self, line: &str) -> Result<bool> {
+        match Command::parse(line)? {
+            Command::Load {
+                source,
+                path,
+                table,
+            } => {
+                let table = match (table, source) {
+                    (Some(table), _) => table,
+                    (None, Source::Nginx) => NGINX_TABLE.to_string(),
+                    (None, Source::Csv) => file_stem(&path)?,
+                };
+                self.load(source, &path, &table).await?;
+                println!("loaded {} as {table}", path.display());
+            }
+            Command::Format(None) => println!("{:?}", self.format),
+            Command::Format(Some(format)) => self.format = format,
+            Command::Timing(timing) => {
+                self.timing = timing.unwrap_or(!self.timing);
+                println!("timing is {}", if self.timing { "on" } else { "off" });
+            }
+            Command::Tables => {
+                self.sql(
+                    "SELECT table_name, table_type FROM information_schema.tables \
+                          WHERE table_schema = 'public' ORDER BY table_name",
+                )
+                .await?
+            }
+            Command::Help => println!("{HELP}"),
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Replaces any table already registered under `table`, which is kept when loading fails.
+    async fn load(&self, source: Source, path: &Path, table: &str) -> Result<()> {
+        if !path.exists() {
+            bail!("{} does not exist", path.display());
+        }
+        let old = self.ctx.deregister_table(table)?;
+        let loaded = match source {
+            Source::Csv => {
+                let location = path.to_string_lossy();
+                self.ctx
+                    .register_csv(table, &location, CsvReadOptions::new())
+                    .await
+                    .map_err(Into::into)
+            }
+            Source::Nginx => query::register_access_logs(&self.ctx, table, path).await,
+        };
+        if loaded.is_err() {
+            if let Some(old) = old {
+                self.ctx.register_table(table, old)?;
+            }
+        }
+        loaded
+    }
+
+    async fn sql(&self, sql: &str) -> Result<()> {
+        let start = Instant::now();
+        let batches = query::run_sql(&self.ctx, sql).await?;
+        let output = self.format.render(&batches)?;
+        print!("{output}");
+        if self.format == OutputFormat::Table && !output.is_empty() {
+            println!();
+        }
+        if self.timing {
+            let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+            eprintln!("{rows} rows in {:?}", start.elapsed());
+        }
+        Ok(())
+    }
+
+    /// The registered tables and their columns, for completion.
+    async fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        let catalog = self.ctx.copied_config().options().catalog.clone();
+        let Some(schema) = self
+            .ctx
+            .catalog(&catalog.default_catalog)
+            .and_then(|c| c.schema(&catalog.default_schema))
+        else {
+            return names;
+        };
+        for table in schema.table_names() {
+            if let Ok(Some(provider)) = schema.table(&table).await {
+                names.extend(provider.schema().fields().iter().map(|f| f.name().clone()));
+            }
+            names.push(table);
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+fn file_stem(path: &Path) -> Result<String> {
+    let stem = path
+        .file_stem()
+        .ok_or_else(|| anyhow!("cannot name a table after {}", path.display()))?;
+    Ok(stem
+        .to_string_lossy()
+        .replace(|c: char| !c.is_alphanumeric(), "_"))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let mut repl = Repl::new(&cli)?;
+    if !cli.execute.is_empty() {
+        for line in &cli.execute {
+            let line = line.trim();
+            if line.starts_with('\\') {
+                if !repl.command(line).await? {
+                    break;
+                }
+            } else {
+                repl.sql(line).await?;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper {
+        files: FilenameCompleter::new(),
+        names: Vec::new(),
+    }));
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(history) = &history {
+        // there is no history on the first run
+        let _ = editor.load_history(history);
+    }
+    println!("Type \\help for commands; SQL runs once a line ends with `;`.");
+
+    let mut statement = String::new();
+    loop {
+        let prompt = if statement.is_empty() {
+            "grammar> "
+        } else {
+            "     ..> "
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                statement.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(trimmed)?;
+        let result = if statement.is_empty() && trimmed.starts_with('\\') {
+            match repl.command(trimmed).await {
+                Ok(true) => Ok(()),
+                Ok(false) => break,
+                Err(err) => Err(err),
+            }
+        } else {
+            statement.push_str(&line);
+            statement.push('\n');
+            if !trimmed.ends_with(';') {
+                continue;
+            }
+            let sql = std::mem::take(&mut statement);
+            repl.sql(&sql).await
+        };
+        if let Err(err) = result {
+            eprintln!("error: {err}");
+        }
+        let names = repl.names().await;
+        if let Some(helper) = editor.helper_mut() {
+            helper.names = names;
+        }
+    }
+    if let Some(history) = &history {
+        editor.save_history(history)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            Command::parse(r#"\load csv "my data/players.csv" players"#).unwrap(),
+            Command::Load {
+                source: Source::Csv,
+                path: PathBuf::from("my data/players.csv"),
+                table: Some("players".to_string()),
+            }
+        );
+        assert_eq!(
+            Command::parse(r"\LOAD nginx assets/nginx_log_2.parquet ").unwrap(),
+            Command::Load {
+                source: Source::Nginx,
+                path: PathBuf::from("assets/nginx_log_2.parquet"),
+                table: None,
+            }
+        );
+        assert_eq!(
+            Command::parse(r"\format JSON").unwrap(),
+            Command::Format(Some(OutputFormat::Json))
+        );
+        assert_eq!(Command::parse(r"\timing").unwrap(), Command::Timing(None));
+        assert_eq!(Command::parse(r"\q").unwrap(), Command::Quit);
+        assert!(Command::parse(r"\load parquet x.parquet").is_err());
+        assert!(Command::parse(r"\format xml").is_err());
+        assert!(Command::parse(r"\nope").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load() {
+        let cli = Cli::parse_from(["grammar"]);
+        let mut repl = Repl::new(&cli).unwrap();
+        repl.command(r"\load csv assets/juventus.csv")
+            .await
+            .unwrap();
+        repl.command(r"\load nginx assets/nginx_log_2.parquet")
+            .await
+            .unwrap();
+        let names = repl.names().await;
+        for name in ["juventus", "nginx", "Kit Number", "status"] {
+            assert!(names.iter().any(|n| n == name), "{name} in {names:?}");
+        }
+        assert!(repl.command(r"\load csv missing.csv").await.is_err());
+
+        // a failed load leaves the table it would have replaced in place
+        let dir = tempfile::tempdir().unwrap();
+        let bad = dir.path().join("bad.parquet");
+        std::fs::write(&bad, "not parquet").unwrap();
+        let load = format!(r"\load nginx {} juventus", bad.display());
+        assert!(repl.command(&load).await.is_err());
+        assert!(repl.names().await.iter().any(|n| n == "Kit Number"));
+        assert!(!repl.command(r"\quit").await.unwrap());
+    }
 }
@@ -0,0 +1,479 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use winnow::{
+    ascii::{digit1, space0},
+    combinator::{alt, cut_err, not, opt, peek, preceded, separated, terminated, trace},
+    error::{ErrMode, ErrorKind, ParserError, StrContext, StrContextValue},
+    stream::{AsChar, Stream},
+    token::take_while,
+    PResult, Parser,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddrError {
+    Empty,
+    InvalidIpv4(String),
+    InvalidIpv6(String),
+    InvalidHostname(String),
+    InvalidCidr(String),
+}
+
+impl fmt::Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrError::Empty => write!(f, "empty address"),
+            AddrError::InvalidIpv4(s) => write!(
+                f,
+                "invalid IPv4 address {s:?}: expected four decimal octets 0-255 without leading zeros"
+            ),
+            AddrError::InvalidIpv6(s) => write!(f, "invalid IPv6 address {s:?}"),
+            AddrError::InvalidHostname(s) => write!(f, "invalid hostname {s:?}"),
+            AddrError::InvalidCidr(s) => write!(
+                f,
+                "invalid CIDR {s:?}: expected an address with an optional /prefix length"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AddrError {}
+
+/// What nginx puts in a client address slot: an IP, or a name when the format logs `$host`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientAddr {
+    Ip(IpAddr),
+    Host(String),
+}
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientAddr::Ip(ip) => write!(f, "{ip}"),
+            ClientAddr::Host(host) => write!(f, "{host}"),
+        }
+    }
+}
+
+impl FromStr for ClientAddr {
+    type Err = AddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(AddrError::Empty);
+        }
+        if s.contains(':') {
+            return parse_ipv6
+                .parse(s)
+                .map(|ip| ClientAddr::Ip(IpAddr::V6(ip)))
+                .map_err(|_| AddrError::InvalidIpv6(s.to_string()));
+        }
+        if s.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+            return parse_ipv4
+                .parse(s)
+                .map(|ip| ClientAddr::Ip(IpAddr::V4(ip)))
+                .map_err(|_| AddrError::InvalidIpv4(s.to_string()));
+        }
+        parse_hostname
+            .parse(s)
+            .map(|host| ClientAddr::Host(host.to_string()))
+            .map_err(|_| AddrError::InvalidHostname(s.to_string()))
+    }
+}
+
+pub fn parse_ip(s: &mut &str) -> PResult<IpAddr> {
+    alt((parse_ipv4.map(IpAddr::V4), parse_ipv6.map(IpAddr::V6))).parse_next(s)
+}
+
+pub fn parse_client_addr(s: &mut &str) -> PResult<ClientAddr> {
+    alt((
+        parse_ip.map(ClientAddr::Ip),
+        parse_hostname.map(|host| ClientAddr::Host(host.to_string())),
+    ))
+    .parse_next(s)
+}
+
+/// Parses an `X-Forwarded-For` value such as `203.0.113.7, 10.0.0.1`, client first.
+pub fn parse_forwarded_for(s: &mut &str) -> PResult<Vec<ClientAddr>> {
+    separated(1.., parse_client_addr, (space0, ',', space0)).parse_next(s)
+}
+
+/// A network such as `10.0.0.0/8` or `2001:db8::/32`; a bare address is a network of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    /// IPv4 networks also contain the IPv4-mapped IPv6 form of their addresses.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let prefix = match self.addr {
+            IpAddr::V4(_) => self.prefix as u32 + 96,
+            IpAddr::V6(_) => self.prefix as u32,
+        };
+        let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+        (mapped(ip) ^ mapped(&self.addr)) & mask == 0
+    }
+}
+
+fn mapped(ip: &IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().into(),
+        IpAddr::V6(v6) => (*v6).into(),
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = AddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_cidr
+            .parse(s)
+            .map_err(|_| AddrError::InvalidCidr(s.to_string()))
+    }
+}
+
+pub fn parse_cidr(s: &mut &str) -> PResult<Cidr> {
+    let addr = parse_ip.parse_next(s)?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = opt(preceded(
+        '/',
+        cut_err(digit1.try_map(str::parse::<u8>).verify(|p| *p <= max))
+            .context(StrContext::Label("prefix length")),
+    ))
+    .parse_next(s)?;
+    Ok(Cidr {
+        addr,
+        prefix: prefix.unwrap_or(max),
+    })
+}
+
+//parse 54.194.175.38
+pub fn parse_ipv4(s: &mut &str) -> PResult<Ipv4Addr> {
+    trace("ipv4", |s: &mut &str| {
+        // four dot-separated numbers can only be an IPv4 address, so report bad octets
+        // instead of letting the caller try other alternatives
+        peek((digit1, '.', digit1, '.', digit1, '.', digit1)).parse_next(s)?;
+        let (a, _, b, _, c, _, d) = cut_err((
+            parse_octet,
+            '.',
+            parse_octet,
+            '.',
+            parse_octet,
+            '.',
+            parse_octet,
+        ))
+        .context(StrContext::Label("ipv4 address"))
+        .parse_next(s)?;
+        Ok(Ipv4Addr::new(a, b, c, d))
+    })
+    .parse_next(s)
+}
+
+fn parse_octet(s: &mut &str) -> PResult<u8> {
+    digit1
+        .verify(|d: &str| d == "0" || !d.starts_with('0'))
+        .parse_to::<u8>()
+        .context(StrContext::Expected(StrContextValue::Description(
+            "decimal octet 0-255 without leading zeros",
+        )))
+        .parse_next(s)
+}
+
+/// Parses an RFC 1123 host name, e.g. `www.example.com`.
+pub fn parse_hostname<'s>(s: &mut &'s str) -> PResult<&'s str> {
+    let label = take_while(1..=63, |c: char| c.is_ascii_alphanumeric() || c == '-')
+        .verify(|l: &str| !l.starts_with('-') && !l.ends_with('-'));
+    separated::<_, _, (), _, _, _, _>(1.., label, '.')
+        .take()
+        .verify(|h: &str| {
+            h.len() <= 253
+                && !h
+                    .rsplit('.')
+                    .next()
+                    .unwrap_or("")
+                    .bytes()
+                    .all(|b| b.is_ascii_digit())
+        })
+        .context(StrContext::Label("hostname"))
+        .parse_next(s)
+}
+
+/// Parses an IPv6 address, dropping the zone ID if there is one.
+pub fn parse_ipv6(s: &mut &str) -> PResult<Ipv6Addr> {
+    parse_ipv6_zoned.map(|(addr, _)| addr).parse_next(s)
+}
+
+/// Parses an IPv6 address as in RFC 4291 section 2.2, followed by an optional RFC 6874 zone ID
+/// such as `fe80::1%eth0`.
+//parse 2001:4801:7824:102:8bee:6e66:ff10:6aa2
+//parse 2a01:7e00::f03c:91ff:fe70:a4cc
+//parse ::ffff:1.2.3.4
+pub fn parse_ipv6_zoned(s: &mut &str) -> PResult<(Ipv6Addr, Option<String>)> {
+    trace("ipv6", |s: &mut &str| {
+        let start = s.checkpoint();
+        let head = parse_pieces(s)?;
+        let tail = opt(preceded("::", parse_pieces)).parse_next(s)?;
+        let Some(addr) = build_ipv6(&head, tail.as_deref()) else {
+            s.reset(&start);
+            return Err(ErrMode::from_error_kind(s, ErrorKind::Verify));
+        };
+        let zone = opt(preceded('%', parse_zone)).parse_next(s)?;
+        Ok((addr, zone.map(str::to_string)))
+    })
+    .parse_next(s)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Piece {
+    Group(u16),
+    V4(Ipv4Addr),
+}
+
+fn parse_h16(s: &mut &str) -> PResult<u16> {
+    take_while(1..=4, AsChar::is_hex_digit)
+        .try_map(|h| u16::from_str_radix(h, 16))
+        .parse_next(s)
+}
+
+fn parse_piece(s: &mut &str) -> PResult<Piece> {
+    alt((parse_ipv4.map(Piece::V4), parse_h16.map(Piece::Group))).parse_next(s)
+}
+
+// a single ':' separates groups, '::' marks the compressed zeros
+fn parse_pieces(s: &mut &str) -> PResult<Vec<Piece>> {
+    separated(0..=8, parse_piece, terminated(':', not(':'))).parse_next(s)
+}
+
+fn parse_zone<'s>(s: &mut &'s str) -> PResult<&'s str> {
+    take_while(1.., |c: char| {
+        c.is_ascii_alphanumeric() || "-_.~".contains(c)
+    })
+    .parse_next(s)
+}
+
+fn build_ipv6(head: &[Piece], tail: Option<&[Piece]>) -> Option<Ipv6Addr> {
+    // an embedded IPv4 address may only be the last 32 bits
+    let v4_last_only = |pieces: &[Piece], may_end_with_v4: bool| {
+        pieces.iter().enumerate().all(|(i, p)| match p {
+            Piece::Group(_) => true,
+            Piece::V4(_) => may_end_with_v4 && i == pieces.len() - 1,
+        })
+    };
+    let words = |pieces: &[Piece]| {
+        pieces
+            .iter()
+            .flat_map(|p| match p {
+                Piece::Group(g) => vec![*g],
+                Piece::V4(v4) => {
+                    let [a, b, c, d] = v4.octets();
+                    vec![u16::from_be_bytes([a, b]), u16::from_be_bytes([c, d])]
+                }
+            })
+            .collect::<Vec<u16>>()
+    };
+
+    let words = match tail {
+        None => {
+            if !v4_last_only(head, true) {
+                return None;
+            }
+            words(head)
+        }
+        Some(tail) => {
+            if !v4_last_only(head, false) || !v4_last_only(tail, true) {
+                return None;
+            }
+            let (head, tail) = (words(head), words(tail));
+            // '::' stands for at least one group of zeros
+            if head.len() + tail.len() > 7 {
+                return None;
+            }
+            let mut all = head;
+            all.resize(8 - tail.len(), 0);
+            all.extend(tail);
+            all
+        }
+    };
+    let words: [u16; 8] = words.try_into().ok()?;
+    Some(Ipv6Addr::from(words))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ipv4() {
+        let v = parse_ipv4.parse("93.180.71.3").unwrap();
+        assert_eq!(v, Ipv4Addr::new(93, 180, 71, 3));
+        let v = parse_ipv4.parse("0.0.0.0").unwrap();
+        assert_eq!(v, Ipv4Addr::UNSPECIFIED);
+    }
+
+    #[test]
+    fn test_cidr() {
+        let net: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains(&"10.20.30.40".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.1.1.1".parse().unwrap()));
+        assert!(!net.contains(&"11.0.0.1".parse().unwrap()));
+        let net: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(&"2001:db8:1::7".parse().unwrap()));
+        assert!(!net.contains(&"2001:db9::".parse().unwrap()));
+        let host: Cidr = "93.180.71.3".parse().unwrap();
+        assert_eq!(host.prefix, 32);
+        assert!(host.contains(&"93.180.71.3".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&"1.2.3.4".parse().unwrap()));
+        for s in ["10.0.0.0/33", "10.0.0.0/", "10.0.0/8", "::/129"] {
+            assert!(s.parse::<Cidr>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_parse_ipv4_invalid() {
+        for s in [
+            "999.1.1.1",
+            "1.2.3.256",
+            "01.2.3.4",
+            "1.2.3.04",
+            "1.2.3",
+            "1.2.3.4.5",
+        ] {
+            assert!(parse_ipv4.parse(s).is_err(), "{s}");
+        }
+        // bad octets are reported as such instead of falling through to IPv6
+        let err = parse_ip(&mut "999.1.1.1 - -").unwrap_err();
+        let ErrMode::Cut(err) = err else {
+            panic!("expected a cut error, got {err:?}");
+        };
+        assert!(err.to_string().starts_with("invalid ipv4 address"), "{err}");
+    }
+
+    #[test]
+    fn test_client_addr_from_str() {
+        assert_eq!(
+            "10.0.0.1".parse::<ClientAddr>(),
+            Ok(ClientAddr::Ip(Ipv4Addr::new(10, 0, 0, 1).into()))
+        );
+        assert_eq!(
+            "::1".parse::<ClientAddr>(),
+            Ok(ClientAddr::Ip(Ipv6Addr::LOCALHOST.into()))
+        );
+        assert_eq!(
+            "www.example.com".parse::<ClientAddr>(),
+            Ok(ClientAddr::Host("www.example.com".to_string()))
+        );
+        assert_eq!(
+            "999.1.1.1".parse::<ClientAddr>(),
+            Err(AddrError::InvalidIpv4("999.1.1.1".to_string()))
+        );
+        assert_eq!(
+            "1::2::3".parse::<ClientAddr>(),
+            Err(AddrError::InvalidIpv6("1::2::3".to_string()))
+        );
+        assert_eq!(
+            "-bad-.com".parse::<ClientAddr>(),
+            Err(AddrError::InvalidHostname("-bad-.com".to_string()))
+        );
+        assert_eq!("".parse::<ClientAddr>(), Err(AddrError::Empty));
+    }
+
+    #[test]
+    fn test_parse_hostname() {
+        assert_eq!(parse_hostname.parse("localhost").unwrap(), "localhost");
+        assert_eq!(
+            parse_hostname.parse("123.example.com").unwrap(),
+            "123.example.com"
+        );
+        assert!(parse_hostname.parse("1.2.3.4").is_err());
+        assert!(parse_hostname.parse("a..b").is_err());
+    }
+
+    #[test]
+    fn test_parse_forwarded_for() {
+        let addrs = parse_forwarded_for
+            .parse("203.0.113.7, 2001:db8::1,proxy.internal")
+            .unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                ClientAddr::Ip("203.0.113.7".parse().unwrap()),
+                ClientAddr::Ip("2001:db8::1".parse().unwrap()),
+                ClientAddr::Host("proxy.internal".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ipv6() {
+        for s in [
+            "2001:4801:7824:102:8bee:6e66:ff10:6aa2",
+            "2a01:7e00::f03c:91ff:fe70:a4cc",
+            "::",
+            "::1",
+            "1::",
+            "fe80::",
+            "1:2:3:4:5:6:7::",
+            "::2:3:4:5:6:7:8",
+            "1:2:3:4::6:7:8",
+            "2001:DB8:0:0:1::1",
+            "::ffff:1.2.3.4",
+            "64:ff9b::192.0.2.33",
+            "1:2:3:4:5:6:1.2.3.4",
+            "::1.2.3.4",
+        ] {
+            let v = parse_ipv6.parse(s).unwrap();
+            assert_eq!(v, s.parse::<Ipv6Addr>().unwrap(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_parse_ipv6_invalid() {
+        for s in [
+            "1:2:3:4:5:6:7",
+            "1:2:3:4:5:6:7:8:9",
+            "1::2::3",
+            "1:2:3:4:5:6:7::8",
+            "1.2.3.4::1",
+            "::1.2.3.4:1",
+            "12345::1",
+            ":1:2:3:4:5:6:7",
+            "",
+        ] {
+            assert!(parse_ipv6.parse(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_parse_ipv6_zoned() {
+        let (addr, zone) = parse_ipv6_zoned.parse("fe80::1%eth0").unwrap();
+        assert_eq!(addr, "fe80::1".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(zone.as_deref(), Some("eth0"));
+
+        let (_, zone) = parse_ipv6_zoned.parse("fe80::1").unwrap();
+        assert_eq!(zone, None);
+    }
+
+    #[test]
+    fn test_parse_ip_in_line() {
+        let s = &mut "2a01:7e00::f03c:91ff:fe70:a4cc - - [18/May/2015:16:05:29 +0000]";
+        let ip = parse_ip(s).unwrap();
+        assert_eq!(
+            ip,
+            "2a01:7e00::f03c:91ff:fe70:a4cc".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(*s, " - - [18/May/2015:16:05:29 +0000]");
+
+        let s = &mut "::ffff:10.0.0.1 -";
+        let ip = parse_ip(s).unwrap();
+        assert_eq!(ip, "::ffff:10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(*s, " -");
+    }
+}
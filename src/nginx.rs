@@ -0,0 +1,507 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use winnow::{
+    ascii::{digit1, space1},
+    combinator::{delimited, eof, opt, preceded, terminated},
+    error::StrContext::Label,
+    token::{take_till, take_until, take_while},
+    PResult, Parser,
+};
+
+use crate::{
+    ingest::LineError,
+    net::parse_ip,
+    ua::{DeviceType, UaParser},
+    url::{QueryParams, RequestTarget},
+};
+use escape::{parse_quoted_field, unescape, unescape_str, Escape, Unescaped};
+
+pub mod escape;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpMethod {
+    Get,
+    Post,
+    Put,
+    Delete,
+    Head,
+    Options,
+    Connect,
+    Trace,
+    Patch,
+    /// WebDAV and anything else that is a valid token, e.g. `PROPFIND`.
+    Other(String),
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpProtocol {
+    HTTP1_0,
+    HTTP1_1,
+    HTTP2_0,
+    HTTP3_0,
+    /// Any other `name/version`, e.g. `HTTP/0.9` or `RTSP/1.0`.
+    Other(String),
+}
+/// The request line logged as `$request`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// `-` or an empty string, logged when the client sent no request line at all.
+    Empty,
+    Line {
+        method: HttpMethod,
+        url: String,
+        /// Missing for HTTP/0.9 style `GET /` requests.
+        protocol: Option<HttpProtocol>,
+    },
+    /// Anything that is not a request line, such as a TLS handshake sent to a plain HTTP port.
+    Malformed(String),
+}
+#[derive(Debug, Copy, Clone)]
+pub enum StatusClass {
+    Informational,
+    Success,
+    Redirection,
+    ClientError,
+    ServerError,
+    Unknown,
+}
+
+crate::arrow_record! {
+    #[derive(Debug, Clone)]
+    pub struct NginxLog {
+        #[arrow(name = "ip")]
+        pub addr: IpAddr,
+        pub ident: Option<String>,
+        pub remote_user: Option<String>,
+        pub datetime: DateTime<Utc>,
+        pub method: Option<HttpMethod>,
+        pub url: Option<String>,
+        pub path: Option<String>,
+        pub query: Option<QueryParams>,
+        pub route: Option<String>,
+        pub protocol: Option<HttpProtocol>,
+        pub malformed_request: Option<String>,
+        pub status: u16,
+        pub status_class: StatusClass,
+        pub body_bytes: u64,
+        pub referrer: String,
+        pub ua: String,
+        pub ua_family: String,
+        pub ua_major: Option<String>,
+        pub ua_minor: Option<String>,
+        pub os_family: String,
+        pub os_major: Option<String>,
+        pub os_minor: Option<String>,
+        pub device_type: DeviceType,
+        pub is_bot: bool,
+    }
+}
+impl AsRef<str> for HttpProtocol {
+    fn as_ref(&self) -> &str {
+        match self {
+            HttpProtocol::HTTP1_0 => "HTTP/1.0",
+            HttpProtocol::HTTP1_1 => "HTTP/1.1",
+            // the names RFC 9113 and RFC 9114 use, whichever one nginx logged
+            HttpProtocol::HTTP2_0 => "HTTP/2",
+            HttpProtocol::HTTP3_0 => "HTTP/3",
+            HttpProtocol::Other(s) => s,
+        }
+    }
+}
+impl AsRef<str> for HttpMethod {
+    fn as_ref(&self) -> &str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Connect => "CONNECT",
+            HttpMethod::Trace => "TRACE",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Other(s) => s,
+        }
+    }
+}
+impl From<&str> for HttpMethod {
+    fn from(s: &str) -> Self {
+        match s {
+            "GET" => HttpMethod::Get,
+            "POST" => HttpMethod::Post,
+            "PUT" => HttpMethod::Put,
+            "DELETE" => HttpMethod::Delete,
+            "HEAD" => HttpMethod::Head,
+            "OPTIONS" => HttpMethod::Options,
+            "CONNECT" => HttpMethod::Connect,
+            "TRACE" => HttpMethod::Trace,
+            "PATCH" => HttpMethod::Patch,
+            other => HttpMethod::Other(other.to_string()),
+        }
+    }
+}
+impl From<&str> for HttpProtocol {
+    fn from(s: &str) -> Self {
+        match s {
+            "HTTP/1.0" => HttpProtocol::HTTP1_0,
+            "HTTP/1.1" => HttpProtocol::HTTP1_1,
+            "HTTP/2" | "HTTP/2.0" => HttpProtocol::HTTP2_0,
+            "HTTP/3" | "HTTP/3.0" => HttpProtocol::HTTP3_0,
+            other => HttpProtocol::Other(other.to_string()),
+        }
+    }
+}
+impl Request {
+    /// Splits into the `method`, `url`, `protocol` and `malformed_request` columns.
+    pub fn into_parts(
+        self,
+    ) -> (
+        Option<HttpMethod>,
+        Option<String>,
+        Option<HttpProtocol>,
+        Option<String>,
+    ) {
+        match self {
+            Request::Empty => (None, None, None, None),
+            Request::Line {
+                method,
+                url,
+                protocol,
+            } => (Some(method), Some(url), protocol, None),
+            Request::Malformed(raw) => (None, None, None, Some(raw)),
+        }
+    }
+}
+impl From<u16> for StatusClass {
+    fn from(status: u16) -> Self {
+        match status {
+            100..=199 => StatusClass::Informational,
+            200..=299 => StatusClass::Success,
+            300..=399 => StatusClass::Redirection,
+            400..=499 => StatusClass::ClientError,
+            500..=599 => StatusClass::ServerError,
+            _ => StatusClass::Unknown,
+        }
+    }
+}
+impl AsRef<str> for StatusClass {
+    fn as_ref(&self) -> &str {
+        match self {
+            StatusClass::Informational => "1xx",
+            StatusClass::Success => "2xx",
+            StatusClass::Redirection => "3xx",
+            StatusClass::ClientError => "4xx",
+            StatusClass::ServerError => "5xx",
+            StatusClass::Unknown => "other",
+        }
+    }
+}
+crate::arrow_dictionary!(HttpMethod, HttpProtocol, StatusClass);
+
+//parse this log
+//93.180.71.3 - - [17/May/2015:08:05:32 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
+pub fn parse_nginx_log(mut s: &str) -> PResult<NginxLog> {
+    let input = &mut s;
+    let ip = terminated(parse_ip, space1)
+        .context(Label("ip"))
+        .parse_next(input)?;
+    let ident = parse_user.context(Label("ident")).parse_next(input)?;
+    let remote_user = parse_user.context(Label("remote_user")).parse_next(input)?;
+    let datetime = parse_datetime
+        .context(Label("datetime"))
+        .parse_next(input)?;
+    let (method, url, protocol, malformed_request) = parse_http
+        .context(Label("request"))
+        .parse_next(input)?
+        .into_parts();
+    let (path, query, route) = match url.as_deref().map(RequestTarget::parse) {
+        Some(t) => (Some(t.path), Some(t.query), Some(t.route)),
+        None => (None, None, None),
+    };
+    let status = parse_status.context(Label("status")).parse_next(input)?;
+    let body_bytes = parse_body_bytes
+        .context(Label("body_bytes"))
+        .parse_next(input)?;
+    let referrer = parse_referrer
+        .context(Label("referrer"))
+        .parse_next(input)?;
+    let ua = parse_ua.context(Label("ua")).parse_next(input)?;
+    let agent = UaParser::bundled().parse(&ua);
+    Ok(NginxLog {
+        addr: ip,
+        ident,
+        remote_user,
+        datetime,
+        method,
+        url,
+        path,
+        query,
+        route,
+        protocol,
+        malformed_request,
+        status,
+        status_class: status.into(),
+        body_bytes,
+        referrer,
+        ua,
+        ua_family: agent.browser.family,
+        ua_major: agent.browser.major,
+        ua_minor: agent.browser.minor,
+        os_family: agent.os.family,
+        os_major: agent.os.major,
+        os_minor: agent.os.minor,
+        device_type: agent.device_type,
+        is_bot: agent.is_bot,
+    })
+}
+
+/// Like [`parse_nginx_log`], reporting which field failed.
+pub fn parse_nginx_line(s: &str) -> Result<NginxLog, LineError> {
+    Ok(parse_nginx_log(s)?)
+}
+
+/// Parses the ident or `$remote_user` field, logged as `-` when unset.
+pub fn parse_user(s: &mut &str) -> PResult<Option<String>> {
+    let ret = take_till(1.., ' ').parse_next(s)?;
+    space1(s)?;
+    Ok((ret != "-").then(|| ret.to_string()))
+}
+
+pub fn parse_datetime(s: &mut &str) -> PResult<DateTime<Utc>> {
+    let ret = delimited('[', take_until(1.., ']').try_map(parse_time_local), ']').parse_next(s)?;
+    space1(s)?;
+    Ok(ret)
+}
+
+/// Parses nginx's `$time_local`, e.g. `17/May/2015:08:05:32 +0000`.
+pub fn parse_time_local(s: &str) -> chrono::ParseResult<DateTime<Utc>> {
+    DateTime::parse_from_str(s, "%d/%b/%Y:%H:%M:%S %z").map(|dt| dt.with_timezone(&Utc))
+}
+
+pub fn parse_http(s: &mut &str) -> PResult<Request> {
+    let raw = parse_quoted_field.parse_next(s)?;
+    space1(s)?;
+    // keep what was logged if the line is binary, the decoded bytes would not be a request
+    let ret = match unescape(raw, Escape::Default) {
+        Unescaped::Text(line) => match parse_request(&line) {
+            Request::Malformed(_) => Request::Malformed(raw.to_string()),
+            request => request,
+        },
+        Unescaped::Binary(_) => Request::Malformed(raw.to_string()),
+    };
+    Ok(ret)
+}
+
+/// Parses the request line logged as `$request`, e.g. `GET /downloads/product_1 HTTP/1.1`;
+/// never fails, lines that are not requests come back as [`Request::Malformed`].
+pub fn parse_request(raw: &str) -> Request {
+    if raw.is_empty() || raw == "-" {
+        return Request::Empty;
+    }
+    match parse_request_line.parse(raw) {
+        Ok((method, url, protocol)) => Request::Line {
+            method,
+            url,
+            protocol,
+        },
+        Err(_) => Request::Malformed(raw.to_string()),
+    }
+}
+
+pub fn parse_request_line(s: &mut &str) -> PResult<(HttpMethod, String, Option<HttpProtocol>)> {
+    let method = parse_method.parse_next(s)?;
+    let url = parse_url.parse_next(s)?;
+    let protocol = opt(preceded(space1, parse_protocol)).parse_next(s)?;
+    eof.parse_next(s)?;
+    Ok((method, url, protocol))
+}
+
+/// Parses a method token (RFC 9110 `tchar`s) and the space after it.
+pub fn parse_method(s: &mut &str) -> PResult<HttpMethod> {
+    let ret = take_while(1.., |c: char| {
+        c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+    })
+    .parse_next(s)?;
+    space1(s)?;
+    Ok(ret.into())
+}
+
+pub fn parse_url(s: &mut &str) -> PResult<String> {
+    let ret = take_till(1.., ' ').parse_next(s)?;
+    Ok(ret.to_string())
+}
+
+pub fn parse_protocol(s: &mut &str) -> PResult<HttpProtocol> {
+    let ret = (
+        take_while(1.., |c: char| c.is_ascii_uppercase()),
+        '/',
+        take_while(1.., |c: char| c.is_ascii_digit() || c == '.'),
+    )
+        .take()
+        .parse_next(s)?;
+    Ok(ret.into())
+}
+
+pub fn parse_status(s: &mut &str) -> PResult<u16> {
+    let ret = digit1.parse_to().parse_next(s)?;
+    space1(s)?;
+    Ok(ret)
+}
+
+pub fn parse_body_bytes(s: &mut &str) -> PResult<u64> {
+    let ret = digit1.parse_to().parse_next(s)?;
+    space1(s)?;
+    Ok(ret)
+}
+
+pub fn parse_referrer(s: &mut &str) -> PResult<String> {
+    let ret = parse_quoted_field.parse_next(s)?;
+    space1(s)?;
+    Ok(unescape_str(ret, Escape::Default).into_owned())
+}
+
+pub fn parse_ua(s: &mut &str) -> PResult<String> {
+    let ret = parse_quoted_field.parse_next(s)?;
+    Ok(unescape_str(ret, Escape::Default).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Array, AsArray};
+
+    use super::*;
+    use crate::ToArrowRecord;
+
+    #[test]
+    fn test_parse_nginx_log() {
+        let s = r#"93.180.71.3 - - [17/May/2015:08:05:32 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)""#;
+        let log = parse_nginx_log(s).unwrap();
+        assert_eq!(log.addr, "93.180.71.3".parse::<IpAddr>().unwrap());
+        assert_eq!(log.ident, None);
+        assert_eq!(log.remote_user, None);
+        assert_eq!(log.datetime.to_rfc3339(), "2015-05-17T08:05:32+00:00");
+        assert_eq!(log.method, Some(HttpMethod::Get));
+        assert_eq!(log.url.as_deref(), Some("/downloads/product_1"));
+        assert_eq!(log.route.as_deref(), Some("/downloads/product_1"));
+        assert_eq!(log.protocol, Some(HttpProtocol::HTTP1_1));
+        assert_eq!(log.malformed_request, None);
+        assert_eq!(log.status, 304);
+        assert_eq!(log.body_bytes, 0);
+        assert_eq!(log.referrer, "-");
+        assert_eq!(log.ua, "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)");
+        assert_eq!(log.ua_family, "APT");
+        assert_eq!(log.os_family, "Ubuntu");
+        assert!(log.is_bot);
+    }
+
+    #[test]
+    fn test_parse_nginx_log_ipv6() {
+        let s = r#"2a01:7e00::f03c:91ff:fe70:a4cc - - [18/May/2015:16:05:29 +0000] "GET /downloads/product_1 HTTP/1.1" 200 85619205 "-" "Chef Client/12.0.3 (ruby-2.1.4-p265; ohai-8.0.1; x86_64-linux; +http://opscode.com)""#;
+        let log = parse_nginx_log(s).unwrap();
+        assert_eq!(
+            log.addr,
+            "2a01:7e00::f03c:91ff:fe70:a4cc".parse::<IpAddr>().unwrap()
+        );
+        assert!(matches!(log.status_class, StatusClass::Success));
+        assert_eq!(log.ua_family, "Chef Client");
+        assert_eq!(log.ua_major.as_deref(), Some("12"));
+        assert_eq!(log.device_type, DeviceType::Bot);
+
+        let batch = NginxLog::to_record_batch(&[log]).unwrap();
+        let is_bot = batch.column_by_name("is_bot").unwrap().as_boolean();
+        assert!(is_bot.value(0));
+    }
+
+    #[test]
+    fn test_parse_nginx_log_remote_user() {
+        let s = r#"10.0.0.1 - alice [17/May/2015:08:05:32 +0000] "GET /admin/7?tab=keys&x=1 HTTP/1.1" 401 0 "-" "curl/7.38.0""#;
+        let log = parse_nginx_log(s).unwrap();
+        assert_eq!(log.ident, None);
+        assert_eq!(log.remote_user.as_deref(), Some("alice"));
+
+        let batch = NginxLog::to_record_batch(&[log]).unwrap();
+        let field = batch
+            .schema()
+            .field_with_name("remote_user")
+            .unwrap()
+            .clone();
+        assert!(field.is_nullable());
+        assert!(batch.column_by_name("ident").unwrap().is_null(0));
+        let query = batch.column_by_name("query").unwrap().as_map();
+        assert_eq!(query.value(0).len(), 2);
+        let route = batch.column_by_name("route").unwrap().as_string::<i32>();
+        assert_eq!(route.value(0), "/admin/:id");
+    }
+
+    #[test]
+    fn test_parse_nginx_line_errors() {
+        let err = parse_nginx_line("\x16\x03\x01\x00").unwrap_err();
+        assert_eq!(err.kind, "ip");
+        let s = r#"10.0.0.1 - - [17/Mat/2015:08:05:32 +0000] "GET / HTTP/1.1" 200 0 "-" "-""#;
+        assert_eq!(parse_nginx_line(s).unwrap_err().kind, "datetime");
+        let s = r#"10.0.0.1 - - [17/May/2015:08:05:32 +0000] "GET / HTTP/1.1 200 0 "-" "-""#;
+        assert_eq!(parse_nginx_line(s).unwrap_err().kind, "request");
+    }
+
+    #[test]
+    fn test_parse_nginx_log_escaped() {
+        let s = r#"10.0.0.1 - - [17/May/2015:08:05:32 +0000] "GET /q?a=\x22b\x22 HTTP/1.1" 200 0 "" "Mozilla \x22x\x22""#;
+        let log = parse_nginx_log(s).unwrap();
+        assert_eq!(log.url.as_deref(), Some(r#"/q?a="b""#));
+        assert_eq!(log.path.as_deref(), Some("/q"));
+        assert_eq!(log.query.unwrap().get("a"), Some(r#""b""#));
+        assert_eq!(log.referrer, "");
+        assert_eq!(log.ua, r#"Mozilla "x""#);
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            parse_request("PROPFIND /dav/ HTTP/2"),
+            Request::Line {
+                method: HttpMethod::Other("PROPFIND".to_string()),
+                url: "/dav/".to_string(),
+                protocol: Some(HttpProtocol::HTTP2_0),
+            }
+        );
+        assert_eq!(
+            parse_request("GET /"),
+            Request::Line {
+                method: HttpMethod::Get,
+                url: "/".to_string(),
+                protocol: None,
+            }
+        );
+        assert_eq!(
+            parse_request("GET / HTTP/0.9"),
+            Request::Line {
+                method: HttpMethod::Get,
+                url: "/".to_string(),
+                protocol: Some(HttpProtocol::Other("HTTP/0.9".to_string())),
+            }
+        );
+        assert_eq!(parse_request("-"), Request::Empty);
+        for (raw, exported) in [("HTTP/2.0", "HTTP/2"), ("HTTP/3", "HTTP/3")] {
+            assert_eq!(HttpProtocol::from(raw).as_ref(), exported);
+        }
+        for raw in [
+            r"\x16\x03\x01\x00\xA5\x01",
+            "GET",
+            "GET /a b HTTP/1.1",
+            "\u{1}\u{2}",
+        ] {
+            assert_eq!(parse_request(raw), Request::Malformed(raw.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_parse_nginx_log_malformed_request() {
+        let s = r#"10.0.0.1 - - [17/May/2015:08:05:32 +0000] "\x16\x03\x01" 400 166 "-" "-""#;
+        let log = parse_nginx_log(s).unwrap();
+        assert_eq!(log.method, None);
+        assert_eq!(log.malformed_request.as_deref(), Some(r"\x16\x03\x01"));
+        let s = r#"10.0.0.1 - - [17/May/2015:08:05:32 +0000] "-" 400 0 "-" "-""#;
+        let log = parse_nginx_log(s).unwrap();
+        assert_eq!((log.url, log.malformed_request), (None, None));
+    }
+}
@@ -0,0 +1,148 @@
+use std::borrow::Cow;
+
+use winnow::{
+    combinator::{alt, delimited, preceded, repeat},
+    token::{any, none_of},
+    PResult, Parser,
+};
+
+/// The `escape=` parameter of a `log_format` directive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Escape {
+    /// `"`, `\` and bytes outside printable ASCII are logged as `\xHH`.
+    #[default]
+    Default,
+    /// JSON string escapes, e.g. `\"`, `\\`, `\n` and `\u0001`.
+    Json,
+    /// Values are logged as is.
+    None,
+}
+
+/// A decoded field: text when the bytes are valid UTF-8, the raw bytes otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unescaped<'a> {
+    Text(Cow<'a, str>),
+    Binary(Vec<u8>),
+}
+
+/// Parses a double-quoted field and returns its still escaped content. A backslash always
+/// escapes the next character, so `\"` (`escape=json`, Apache) does not end the field.
+pub fn parse_quoted_field<'s>(s: &mut &'s str) -> PResult<&'s str> {
+    delimited(
+        '"',
+        repeat::<_, _, (), _, _>(
+            0..,
+            alt((preceded('\\', any).void(), none_of(['"', '\\']).void())),
+        )
+        .take(),
+        '"',
+    )
+    .parse_next(s)
+}
+
+/// Decodes `\xHH` and JSON-style escapes; invalid sequences are kept as written. The two
+/// styles never clash, nginx's default escaping only ever writes a backslash as `\x5C`.
+pub fn unescape(raw: &str, escape: Escape) -> Unescaped<'_> {
+    if escape == Escape::None || !raw.contains('\\') {
+        return Unescaped::Text(Cow::Borrowed(raw));
+    }
+    let mut out = Vec::with_capacity(raw.len());
+    let mut rest = raw.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        let (decoded, len) = match rest {
+            [b'x' | b'X', h, l, ..] => match (hex(*h), hex(*l)) {
+                (Some(h), Some(l)) => (Some(h << 4 | l), 3),
+                _ => (None, 0),
+            },
+            [b'u', a, b, c, d, ..] => {
+                let code = [a, b, c, d]
+                    .into_iter()
+                    .try_fold(0u32, |acc, &x| Some(acc << 4 | hex(x)? as u32));
+                match code.and_then(char::from_u32) {
+                    Some(c) => {
+                        out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        rest = &rest[5..];
+                        continue;
+                    }
+                    None => (None, 0),
+                }
+            }
+            [b'"', ..] => (Some(b'"'), 1),
+            [b'\\', ..] => (Some(b'\\'), 1),
+            [b'/', ..] => (Some(b'/'), 1),
+            [b'n', ..] => (Some(b'\n'), 1),
+            [b'r', ..] => (Some(b'\r'), 1),
+            [b't', ..] => (Some(b'\t'), 1),
+            [b'b', ..] => (Some(0x08), 1),
+            [b'f', ..] => (Some(0x0c), 1),
+            _ => (None, 0),
+        };
+        match decoded {
+            Some(d) => {
+                out.push(d);
+                rest = &rest[len..];
+            }
+            None => out.push(b'\\'),
+        }
+    }
+    match String::from_utf8(out) {
+        Ok(s) => Unescaped::Text(Cow::Owned(s)),
+        Err(e) => Unescaped::Binary(e.into_bytes()),
+    }
+}
+
+/// Like [`unescape`], but keeps the escaped text when it does not decode to UTF-8, so nothing
+/// is lost in a string column.
+pub fn unescape_str(raw: &str, escape: Escape) -> Cow<'_, str> {
+    match unescape(raw, escape) {
+        Unescaped::Text(s) => s,
+        Unescaped::Binary(_) => Cow::Borrowed(raw),
+    }
+}
+
+fn hex(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quoted_field() {
+        let mut s = r#""a \"b\" \x22c\x22" rest"#;
+        assert_eq!(parse_quoted_field(&mut s).unwrap(), r#"a \"b\" \x22c\x22"#);
+        assert_eq!(s, " rest");
+        assert_eq!(parse_quoted_field.parse(r#""""#).unwrap(), "");
+        assert!(parse_quoted_field.parse(r#""abc"#).is_err());
+    }
+
+    #[test]
+    fn test_unescape() {
+        let text = |s: &str| Unescaped::Text(Cow::Owned(s.to_string()));
+        assert_eq!(
+            unescape(r"Mozilla \x22quoted\x22 \x5Cx", Escape::Default),
+            text(r#"Mozilla "quoted" \x"#)
+        );
+        assert_eq!(unescape(r"\xE4\xBD\xA0", Escape::Default), text("你"));
+        assert_eq!(
+            unescape(r"\x16\x03\x01\x00\xA5", Escape::Default),
+            Unescaped::Binary(vec![0x16, 0x03, 0x01, 0x00, 0xA5])
+        );
+        assert_eq!(
+            unescape(r#"a\"b\\c\n\u0001"#, Escape::Json),
+            text("a\"b\\c\n\u{1}")
+        );
+        assert_eq!(unescape(r"\x2 \q", Escape::Default), text(r"\x2 \q"));
+        assert_eq!(
+            unescape(r"\x22", Escape::None),
+            Unescaped::Text(Cow::Borrowed(r"\x22"))
+        );
+        assert_eq!(unescape_str(r"\xA5", Escape::Default), r"\xA5");
+    }
+}
@@ -0,0 +1,274 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Result};
+use arrow::{array::RecordBatch, datatypes::DataType, util::pretty::pretty_format_batches};
+use datafusion::{
+    datasource::{
+        listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+        listing_table_factory::ListingTableFactory,
+        MemTable,
+    },
+    prelude::{ParquetReadOptions, SessionContext},
+};
+
+use crate::{
+    export::{partition::MANIFEST_FILE, Manifest},
+    ToArrowRecord,
+};
+
+pub mod format;
+pub mod output;
+pub mod udf;
+
+pub use format::{NginxExec, NginxFormat, NginxFormatFactory, NGINX_FORMAT};
+pub use output::OutputFormat;
+pub use udf::{register_udfs, udfs};
+
+/// The table name the tools register parsed access logs under.
+pub const NGINX_TABLE: &str = "nginx";
+
+/// Registers a Parquet file, or a dataset directory written by
+/// [`PartitionedWriter`](crate::export::PartitionedWriter) with its partition columns.
+pub async fn register_parquet(
+    ctx: &SessionContext,
+    name: &str,
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut location = path.to_string_lossy().into_owned();
+    let mut partition_columns = Vec::new();
+    if path.is_dir() {
+        if path.join(MANIFEST_FILE).exists() {
+            partition_columns = Manifest::read(path)?
+                .partition_columns
+                .into_iter()
+                .map(|c| (c, DataType::Utf8))
+                .collect();
+        }
+        // a trailing slash makes DataFusion list the directory
+        if !location.ends_with('/') {
+            location.push('/');
+        }
+    } else if !path.exists() {
+        bail!("{} does not exist", path.display());
+    }
+    let options = ParquetReadOptions::default().table_partition_cols(partition_columns);
+    ctx.register_parquet(name, &location, options).await?;
+    Ok(())
+}
+
+/// Enables `CREATE EXTERNAL TABLE logs STORED AS NGINX LOCATION '/var/log/nginx/'`, which
+/// parses the logs as they are queried instead of converting them first.
+pub fn register_nginx_format(ctx: &SessionContext) -> Result<()> {
+    let state = ctx.state_ref();
+    let mut state = state.write();
+    state.register_file_format(Arc::new(NginxFormatFactory), true)?;
+    // external tables are created by the factory registered under the upper case name
+    state.table_factories_mut().insert(
+        NGINX_FORMAT.to_uppercase(),
+        Arc::new(ListingTableFactory::new()),
+    );
+    Ok(())
+}
+
+/// Registers exporter output with [`register_parquet`] and anything else as raw access logs
+/// in the combined format, parsed while they are queried.
+pub async fn register_access_logs(
+    ctx: &SessionContext,
+    name: &str,
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let path = path.as_ref();
+    if path.extension().is_some_and(|e| e == "parquet") || path.join(MANIFEST_FILE).exists() {
+        return register_parquet(ctx, name, path).await;
+    }
+    if !path.exists() {
+        bail!("{} does not exist", path.display());
+    }
+    let mut location = path.to_string_lossy().into_owned();
+    if path.is_dir() && !location.ends_with('/') {
+        location.push('/');
+    }
+    let format = NginxFormat::default();
+    let schema = format.log_format.schema();
+    // every file in a directory, whatever the rotation suffix
+    let options = ListingOptions::new(Arc::new(format))
+        .with_file_extension("")
+        .with_target_partitions(ctx.copied_config().target_partitions());
+    let config = ListingTableConfig::new(ListingTableUrl::parse(&location)?)
+        .with_listing_options(options)
+        .with_schema(schema);
+    ctx.register_table(name, Arc::new(ListingTable::try_new(config)?))?;
+    Ok(())
+}
+
+/// Registers batches already in memory, e.g. straight from the parser.
+pub fn register_batches(ctx: &SessionContext, name: &str, batches: Vec<RecordBatch>) -> Result<()> {
+    let Some(first) = batches.first() else {
+        bail!("no batches to register as {name}");
+    };
+    let table = MemTable::try_new(first.schema(), vec![batches])?;
+    ctx.register_table(name, Arc::new(table))?;
+    Ok(())
+}
+
+/// Registers `records` as batches of up to `batch_size` rows.
+pub fn register_records<T: ToArrowRecord>(
+    ctx: &SessionContext,
+    name: &str,
+    records: &[T],
+    batch_size: usize,
+) -> Result<()> {
+    let mut batches = records
+        .chunks(batch_size.max(1))
+        .map(T::to_record_batch)
+        .collect::<Result<Vec<_>, _>>()?;
+    if batches.is_empty() {
+        batches.push(RecordBatch::new_empty(T::schema()));
+    }
+    register_batches(ctx, name, batches)
+}
+
+pub async fn run_sql(ctx: &SessionContext, sql: &str) -> Result<Vec<RecordBatch>> {
+    Ok(ctx.sql(sql).await?.collect().await?)
+}
+
+/// Formats query results as an ASCII table.
+pub fn pretty(batches: &[RecordBatch]) -> Result<String> {
+    Ok(pretty_format_batches(batches)?.to_string())
+}
+
+/// Sample logs and a context to query them, for the tests of this module and its users.
+#[cfg(test)]
+pub(crate) mod testing {
+    use arrow::array::RecordBatch;
+    use datafusion::prelude::SessionContext;
+
+    use super::{register_records, register_udfs, run_sql, NGINX_TABLE};
+    use crate::nginx::{parse_nginx_log, NginxLog};
+
+    /// Two 304s from one client on the 17th, then a 404 and a 200 from others on the 18th.
+    pub const LINES: &str = r#"93.180.71.3 - - [17/May/2015:08:05:32 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
+93.180.71.3 - - [17/May/2015:08:05:23 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
+80.91.33.133 - - [18/May/2015:08:05:24 +0000] "GET /downloads/product_1 HTTP/1.1" 404 336 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.17)"
+217.168.17.5 - - [18/May/2015:08:05:34 +0000] "GET /downloads/product_1 HTTP/1.1" 200 490 "-" "Debian APT-HTTP/1.3 (0.8.10.3)""#;
+
+    pub fn logs(lines: &str) -> Vec<NginxLog> {
+        lines.lines().map(|l| parse_nginx_log(l).unwrap()).collect()
+    }
+
+    /// A context with the UDFs and `lines` in the `nginx` table, two rows per batch.
+    pub fn sample_context(lines: &str) -> SessionContext {
+        let ctx = SessionContext::new();
+        register_udfs(&ctx);
+        register_records(&ctx, NGINX_TABLE, &logs(lines), 2).unwrap();
+        ctx
+    }
+
+    pub async fn query_sample(lines: &str, sql: &str) -> Vec<RecordBatch> {
+        run_sql(&sample_context(lines), sql).await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use arrow::array::AsArray;
+    use arrow::datatypes::Int64Type;
+
+    use super::{testing::*, *};
+    use crate::{
+        export::{PartitionKey, PartitionOptions, PartitionedWriter},
+        nginx::NginxLog,
+        ExportOptions, ParquetSink,
+    };
+
+    const BY_STATUS: &str =
+        "SELECT status, count(*) AS n FROM nginx GROUP BY status ORDER BY status";
+
+    fn counts(batches: &[RecordBatch]) -> Vec<i64> {
+        batches
+            .iter()
+            .flat_map(|b| b.column(1).as_primitive::<Int64Type>().values().to_vec())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_query_records() {
+        let batches = query_sample(LINES, BY_STATUS).await;
+        assert_eq!(counts(&batches), vec![1, 2, 1]);
+        let table = pretty(&batches).unwrap();
+        assert!(table.contains("| 304    | 2 |"), "{table}");
+    }
+
+    #[tokio::test]
+    async fn test_query_parquet() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nginx.parquet");
+        let mut sink =
+            ParquetSink::try_new(File::create(&path).unwrap(), ExportOptions::default()).unwrap();
+        for log in logs(LINES) {
+            sink.write(&log).unwrap();
+        }
+        sink.close().unwrap();
+
+        let ctx = SessionContext::new();
+        register_parquet(&ctx, NGINX_TABLE, &path).await.unwrap();
+        let batches = run_sql(&ctx, BY_STATUS).await.unwrap();
+        assert_eq!(counts(&batches), vec![1, 2, 1]);
+        assert!(
+            register_parquet(&ctx, "missing", dir.path().join("missing.parquet"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_partitioned() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = PartitionKey::new("date", |log: &NginxLog| {
+            log.datetime.format("%Y-%m-%d").to_string()
+        });
+        let mut writer = PartitionedWriter::try_new(
+            dir.path(),
+            vec![key],
+            ExportOptions::default(),
+            PartitionOptions::default(),
+        )
+        .unwrap();
+        for log in logs(LINES) {
+            writer.write(&log).unwrap();
+        }
+        writer.close().unwrap();
+
+        let ctx = SessionContext::new();
+        register_parquet(&ctx, NGINX_TABLE, dir.path())
+            .await
+            .unwrap();
+        let sql = "SELECT date, count(*) FROM nginx GROUP BY date ORDER BY date";
+        let batches = run_sql(&ctx, sql).await.unwrap();
+        assert_eq!(counts(&batches), vec![2, 2]);
+        let dates = batches[0].column(0).as_string::<i32>();
+        assert_eq!(dates.value(0), "2015-05-17");
+    }
+
+    #[tokio::test]
+    async fn test_register_access_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("access.log.1"), LINES).unwrap();
+
+        let ctx = SessionContext::new();
+        // names go to DataFusion as given rather than spliced into SQL
+        let name = "raw logs'; DROP TABLE x; --";
+        register_access_logs(&ctx, name, dir.path()).await.unwrap();
+        let table = ctx.table(name).await.unwrap();
+        assert_eq!(table.count().await.unwrap(), 4);
+
+        register_access_logs(&ctx, NGINX_TABLE, dir.path())
+            .await
+            .unwrap();
+        let batches = run_sql(&ctx, BY_STATUS).await.unwrap();
+        assert_eq!(counts(&batches), vec![1, 2, 1]);
+    }
+}
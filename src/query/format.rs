@@ -0,0 +1,484 @@
+use std::{any::Any, collections::HashMap, fmt, io::BufReader, sync::Arc};
+
+use arrow::{array::RecordBatch, datatypes::SchemaRef, error::ArrowError};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use datafusion::{
+    common::{GetExt, Statistics},
+    datasource::{
+        file_format::{file_compression_type::FileCompressionType, FileFormat, FileFormatFactory},
+        physical_plan::{FileMeta, FileOpenFuture, FileOpener, FileScanConfig, FileStream},
+    },
+    error::{DataFusionError, Result},
+    execution::{SessionState, TaskContext},
+    physical_expr::{EquivalenceProperties, PhysicalExpr},
+    physical_plan::{
+        metrics::{ExecutionPlanMetricsSet, MetricsSet},
+        DisplayAs, DisplayFormatType, ExecutionMode, ExecutionPlan, Partitioning, PlanProperties,
+        SendableRecordBatchStream,
+    },
+};
+use futures::{StreamExt, TryStreamExt};
+use object_store::{GetResultPayload, ObjectMeta, ObjectStore};
+
+use crate::{
+    ingest::ErrorPolicy,
+    input::{BlockingLines, LineReader, StreamLines},
+    log_format::LogFormat,
+};
+
+/// The name used in `CREATE EXTERNAL TABLE ... STORED AS NGINX`.
+pub const NGINX_FORMAT: &str = "nginx";
+
+fn external(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> DataFusionError {
+    DataFusionError::External(err.into())
+}
+
+/// Makes `STORED AS NGINX` available to `CREATE EXTERNAL TABLE`.
+///
+/// Tables take the options `'log_format'`, an nginx format string or `log_format` directive
+/// that defaults to `combined`, and `'on_error'`, `fail-fast` or `skip`.
+#[derive(Debug, Default)]
+pub struct NginxFormatFactory;
+
+impl FileFormatFactory for NginxFormatFactory {
+    fn create(
+        &self,
+        _state: &SessionState,
+        format_options: &HashMap<String, String>,
+    ) -> Result<Arc<dyn FileFormat>> {
+        let mut format = NginxFormat::default();
+        for (key, value) in format_options {
+            match key.strip_prefix("format.").unwrap_or(key) {
+                "log_format" => {
+                    format.log_format = LogFormat::from_config(value).map_err(external)?
+                }
+                "on_error" => {
+                    format.on_error = ErrorPolicy::from_str(value, true).map_err(external)?
+                }
+                _ => {
+                    return Err(DataFusionError::Configuration(format!(
+                        "unknown option {key:?} for {NGINX_FORMAT} tables"
+                    )))
+                }
+            }
+        }
+        if format.on_error == ErrorPolicy::Quarantine {
+            return Err(DataFusionError::Configuration(format!(
+                "{NGINX_FORMAT} tables cannot quarantine lines, use 'skip'"
+            )));
+        }
+        Ok(Arc::new(format))
+    }
+
+    fn default(&self) -> Arc<dyn FileFormat> {
+        Arc::new(NginxFormat::default())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl GetExt for NginxFormatFactory {
+    fn get_ext(&self) -> String {
+        NGINX_FORMAT.to_string()
+    }
+}
+
+/// Access logs parsed with a [`LogFormat`] while they are scanned, plain or compressed.
+#[derive(Debug, Clone)]
+pub struct NginxFormat {
+    pub log_format: LogFormat,
+    pub on_error: ErrorPolicy,
+}
+
+impl Default for NginxFormat {
+    fn default() -> Self {
+        Self {
+            log_format: LogFormat::combined(),
+            on_error: ErrorPolicy::FailFast,
+        }
+    }
+}
+
+#[async_trait]
+impl FileFormat for NginxFormat {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn get_ext(&self) -> String {
+        NginxFormatFactory.get_ext()
+    }
+
+    /// Compression is detected from each file's first bytes, so rotations can sit together.
+    fn get_ext_with_compression(&self, _compression: &FileCompressionType) -> Result<String> {
+        Ok(self.get_ext())
+    }
+
+    async fn infer_schema(
+        &self,
+        _state: &SessionState,
+        _store: &Arc<dyn ObjectStore>,
+        _objects: &[ObjectMeta],
+    ) -> Result<SchemaRef> {
+        Ok(self.log_format.schema())
+    }
+
+    async fn infer_stats(
+        &self,
+        _state: &SessionState,
+        _store: &Arc<dyn ObjectStore>,
+        table_schema: SchemaRef,
+        _object: &ObjectMeta,
+    ) -> Result<Statistics> {
+        Ok(Statistics::new_unknown(&table_schema))
+    }
+
+    async fn create_physical_plan(
+        &self,
+        state: &SessionState,
+        conf: FileScanConfig,
+        _filters: Option<&Arc<dyn PhysicalExpr>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let batch_size = state.config().batch_size();
+        Ok(Arc::new(NginxExec::new(conf, self.clone(), batch_size)))
+    }
+}
+
+/// Scans one partition per file group, parsing only the projected columns.
+#[derive(Debug, Clone)]
+pub struct NginxExec {
+    conf: FileScanConfig,
+    format: NginxFormat,
+    batch_size: usize,
+    metrics: ExecutionPlanMetricsSet,
+    properties: PlanProperties,
+}
+
+impl NginxExec {
+    pub fn new(conf: FileScanConfig, format: NginxFormat, batch_size: usize) -> Self {
+        let (schema, _, orderings) = conf.project();
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new_with_orderings(schema, &orderings),
+            Partitioning::UnknownPartitioning(conf.file_groups.len()),
+            ExecutionMode::Bounded,
+        );
+        Self {
+            conf,
+            format,
+            batch_size,
+            metrics: ExecutionPlanMetricsSet::new(),
+            properties,
+        }
+    }
+
+    /// The projected columns that come from the files rather than from partition paths.
+    fn file_projection(&self) -> Option<Vec<usize>> {
+        let file_columns = self.conf.file_schema.fields().len();
+        let projection = self.conf.projection.as_ref()?;
+        Some(
+            projection
+                .iter()
+                .copied()
+                .filter(|&i| i < file_columns)
+                .collect(),
+        )
+    }
+}
+
+impl DisplayAs for NginxExec {
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NginxExec: ")?;
+        self.conf.fmt_as(t, f)
+    }
+}
+
+impl ExecutionPlan for NginxExec {
+    fn name(&self) -> &'static str {
+        "NginxExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        Vec::new()
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        let store = context
+            .runtime_env()
+            .object_store(&self.conf.object_store_url)?;
+        let log_format = match self.file_projection() {
+            Some(projection) => self
+                .format
+                .log_format
+                .clone()
+                .with_projection(&projection)
+                .map_err(external)?,
+            None => self.format.log_format.clone(),
+        };
+        let opener = NginxOpener {
+            store,
+            log_format,
+            on_error: self.format.on_error,
+            batch_size: self.batch_size,
+        };
+        let stream = FileStream::new(&self.conf, partition, opener, &self.metrics)?;
+        Ok(Box::pin(stream))
+    }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        Some(self.metrics.clone_inner())
+    }
+
+    fn statistics(&self) -> Result<Statistics> {
+        Ok(self.conf.project().1)
+    }
+}
+
+struct NginxOpener {
+    store: Arc<dyn ObjectStore>,
+    log_format: LogFormat,
+    on_error: ErrorPolicy,
+    batch_size: usize,
+}
+
+impl FileOpener for NginxOpener {
+    fn open(&self, file_meta: FileMeta) -> Result<FileOpenFuture> {
+        let store = self.store.clone();
+        let mut batches = Batches {
+            location: file_meta.location().to_string(),
+            lines: None,
+            log_format: self.log_format.clone(),
+            on_error: self.on_error,
+            batch_size: self.batch_size,
+            line: 0,
+        };
+        Ok(Box::pin(async move {
+            let got = store.get(file_meta.location()).await?;
+            let lines = match got.payload {
+                GetResultPayload::File(file, _) => {
+                    let lines = BlockingLines::open(move || Ok(Box::new(BufReader::new(file))))
+                        .await
+                        .map_err(external)?;
+                    LineReader::Read(Box::new(lines))
+                }
+                GetResultPayload::Stream(chunks) => {
+                    let chunks = chunks.map_err(anyhow::Error::from).boxed();
+                    LineReader::Stream(Box::new(StreamLines::new(chunks)))
+                }
+            };
+            batches.lines = Some(lines);
+            let stream = futures::stream::unfold(batches, |mut batches| async move {
+                match batches.next_batch().await {
+                    Ok(Some(batch)) => Some((Ok(batch), batches)),
+                    Ok(None) => None,
+                    Err(err) => Some((Err(ArrowError::ExternalError(err.into())), batches)),
+                }
+            });
+            Ok(stream.boxed())
+        }))
+    }
+}
+
+/// Parses a file a batch at a time, as the scan asks for more.
+struct Batches {
+    location: String,
+    lines: Option<LineReader>,
+    log_format: LogFormat,
+    on_error: ErrorPolicy,
+    batch_size: usize,
+    line: u64,
+}
+
+impl Batches {
+    async fn next_batch(&mut self) -> anyhow::Result<Option<RecordBatch>> {
+        let Some(lines) = self.lines.as_mut() else {
+            return Ok(None);
+        };
+        let mut builder = self.log_format.new_batch_builder(self.batch_size);
+        while builder.len() < self.batch_size {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => {
+                    self.lines = None;
+                    break;
+                }
+                Err(err) => {
+                    // errors end the file's stream rather than being reported again
+                    self.lines = None;
+                    return Err(err.context(self.location.clone()));
+                }
+            };
+            self.line += 1;
+            match self.log_format.parse_line(&line) {
+                Ok(record) => builder.append(&record),
+                Err(_) if self.on_error == ErrorPolicy::Skip => {}
+                Err(err) => {
+                    self.lines = None;
+                    anyhow::bail!("{}:{}: {err}", self.location, self.line);
+                }
+            }
+        }
+        if builder.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(builder.finish()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write, sync::Arc};
+
+    use object_store::ObjectStore;
+
+    use arrow::{array::AsArray, datatypes::Int64Type};
+    use datafusion::{execution::object_store::ObjectStoreUrl, prelude::SessionContext};
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::query::{register_nginx_format, run_sql, testing::LINES};
+
+    async fn count(ctx: &SessionContext, sql: &str) -> Vec<i64> {
+        let batches = run_sql(ctx, sql).await.unwrap();
+        batches
+            .iter()
+            .flat_map(|b| b.column(0).as_primitive::<Int64Type>().values().to_vec())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_external_table() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("access.log"), LINES).unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(LINES.as_bytes()).unwrap();
+        fs::write(dir.path().join("access.log.2.gz"), gz.finish().unwrap()).unwrap();
+
+        let ctx = SessionContext::new();
+        register_nginx_format(&ctx).unwrap();
+        let location = format!("{}/", dir.path().display());
+        let sql = format!("CREATE EXTERNAL TABLE logs STORED AS NGINX LOCATION '{location}'");
+        ctx.sql(&sql).await.unwrap();
+
+        assert_eq!(count(&ctx, "SELECT count(*) FROM logs").await, [8]);
+        let sql = "SELECT count(*) FROM logs WHERE status = 404 AND ua_family = 'APT'";
+        assert_eq!(count(&ctx, sql).await, [2]);
+        let sql = "SELECT sum(body_bytes) FROM logs GROUP BY ip ORDER BY 1";
+        let batches = run_sql(&ctx, sql).await.unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_store() {
+        let store = object_store::memory::InMemory::new();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(LINES.as_bytes()).unwrap();
+        let path = object_store::path::Path::from("logs/access.log.1.gz");
+        store.put(&path, gz.finish().unwrap().into()).await.unwrap();
+
+        let ctx = SessionContext::new();
+        register_nginx_format(&ctx).unwrap();
+        let url = ObjectStoreUrl::parse("memory://").unwrap();
+        ctx.register_object_store(url.as_ref(), Arc::new(store));
+        let sql = "CREATE EXTERNAL TABLE logs STORED AS NGINX LOCATION 'memory:///logs/'";
+        ctx.sql(sql).await.unwrap();
+        assert_eq!(count(&ctx, "SELECT count(*) FROM logs").await, [4]);
+    }
+
+    #[tokio::test]
+    async fn test_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        fs::write(&log, "200 10\nbroken\n404 0\n").unwrap();
+
+        let ctx = SessionContext::new();
+        register_nginx_format(&ctx).unwrap();
+        let create = |name: &str, options: &str| {
+            format!(
+                "CREATE EXTERNAL TABLE {name} STORED AS NGINX LOCATION '{}' OPTIONS ({options})",
+                log.display()
+            )
+        };
+        let skip = create(
+            "skip",
+            "'log_format' '$status $body_bytes_sent', 'on_error' 'skip'",
+        );
+        ctx.sql(&skip).await.unwrap();
+        assert_eq!(count(&ctx, "SELECT count(*) FROM skip").await, [2]);
+
+        let fail = create("fail", "'log_format' '$status $body_bytes_sent'");
+        ctx.sql(&fail).await.unwrap();
+        let err = run_sql(&ctx, "SELECT count(*) FROM fail")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("access.log:2"), "{err}");
+
+        assert!(ctx.sql(&create("bad", "'delimiter' ','")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_projection_keeps_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        let bad = LINES
+            .lines()
+            .next()
+            .unwrap()
+            .replace("17/May/2015", "17/Foo/2015");
+        fs::write(&log, format!("{LINES}\n{bad}\n")).unwrap();
+
+        let ctx = SessionContext::new();
+        register_nginx_format(&ctx).unwrap();
+        let location = log.display();
+        let sql = format!(
+            "CREATE EXTERNAL TABLE skip STORED AS NGINX LOCATION '{location}' \
+             OPTIONS ('on_error' 'skip')"
+        );
+        ctx.sql(&sql).await.unwrap();
+        let rows = |sql: &'static str| {
+            let ctx = ctx.clone();
+            async move {
+                let batches = run_sql(&ctx, sql).await.unwrap();
+                batches.iter().map(|b| b.num_rows()).sum::<usize>() as i64
+            }
+        };
+        assert_eq!(count(&ctx, "SELECT count(*) FROM skip").await, [4]);
+        assert_eq!(count(&ctx, "SELECT count(datetime) FROM skip").await, [4]);
+        assert_eq!(rows("SELECT * FROM skip").await, 4);
+        assert_eq!(rows("SELECT status FROM skip").await, 4);
+
+        let sql = format!("CREATE EXTERNAL TABLE fail STORED AS NGINX LOCATION '{location}'");
+        ctx.sql(&sql).await.unwrap();
+        for sql in [
+            "SELECT count(*) FROM fail",
+            "SELECT status FROM fail",
+            "SELECT * FROM fail",
+        ] {
+            let err = run_sql(&ctx, sql).await.unwrap_err();
+            assert!(
+                err.to_string().contains("access.log:5: datetime"),
+                "{sql}: {err}"
+            );
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::{collections::HashSet, fmt::Write, sync::Arc};
+
+use anyhow::{bail, Result};
+use arrow::{
+    array::{Array, ArrayRef, AsArray, RecordBatch, StringArray},
+    compute::cast,
+    csv::WriterBuilder,
+    datatypes::{DataType, Field, Float64Type, Int64Type, Schema},
+    util::display::{ArrayFormatter, FormatOptions},
+};
+use clap::ValueEnum;
+
+use super::pretty;
+use crate::{
+    arrow_record::ip_from_bytes,
+    json::{write_string, JsonValue, Num},
+};
+
+/// How query results are written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// An ASCII table.
+    #[default]
+    Table,
+    /// CSV with a header row.
+    Csv,
+    /// One JSON object per row.
+    Json,
+}
+
+impl OutputFormat {
+    pub fn render(self, batches: &[RecordBatch]) -> Result<String> {
+        let batches = batches.iter().map(readable).collect::<Result<Vec<_>>>()?;
+        match self {
+            OutputFormat::Table => pretty(&batches),
+            OutputFormat::Csv => {
+                let mut out = Vec::new();
+                let mut writer = WriterBuilder::new().with_header(true).build(&mut out);
+                for batch in &batches {
+                    writer.write(batch)?;
+                }
+                drop(writer);
+                Ok(String::from_utf8(out)?)
+            }
+            OutputFormat::Json => {
+                let mut out = String::new();
+                for batch in &batches {
+                    json_rows(batch, &mut out)?;
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// Shows the 16 byte IP columns the exporters write as addresses rather than hex.
+pub(crate) fn readable(batch: &RecordBatch) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    if !schema
+        .fields()
+        .iter()
+        .any(|f| f.data_type() == &DataType::FixedSizeBinary(16))
+    {
+        return Ok(batch.clone());
+    }
+    let mut fields = Vec::with_capacity(schema.fields().len());
+    let mut columns = Vec::with_capacity(batch.num_columns());
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        if field.data_type() == &DataType::FixedSizeBinary(16) {
+            let ips: StringArray = column
+                .as_fixed_size_binary()
+                .iter()
+                .map(|b| Some(ip_from_bytes(b?.try_into().ok()?).to_string()))
+                .collect();
+            fields.push(Field::new(field.name(), DataType::Utf8, true));
+            columns.push(Arc::new(ips) as ArrayRef);
+        } else {
+            fields.push(field.as_ref().clone());
+            columns.push(column.clone());
+        }
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+/// Writes each row as a JSON object line with the keys in column order; types without a JSON
+/// counterpart become text.
+fn json_rows(batch: &RecordBatch, out: &mut String) -> Result<()> {
+    let schema = batch.schema();
+    let mut names = HashSet::new();
+    for field in schema.fields() {
+        if !names.insert(field.name()) {
+            bail!(
+                "column {:?} appears more than once, alias it to write JSON",
+                field.name()
+            );
+        }
+    }
+    let options = FormatOptions::default();
+    let mut columns = Vec::with_capacity(batch.num_columns());
+    for column in batch.columns() {
+        let values: Vec<JsonValue> = match column.data_type() {
+            DataType::Null => vec![JsonValue::Null; column.len()],
+            DataType::Boolean => column
+                .as_boolean()
+                .iter()
+                .map(|v| v.map_or(JsonValue::Null, JsonValue::Bool))
+                .collect(),
+            ty if ty.is_integer() => cast(column, &DataType::Int64)?
+                .as_primitive::<Int64Type>()
+                .iter()
+                .map(|v| v.map_or(JsonValue::Null, |v| JsonValue::Number(Num::Int(v))))
+                .collect(),
+            ty if ty.is_floating() => cast(column, &DataType::Float64)?
+                .as_primitive::<Float64Type>()
+                .iter()
+                .map(|v| v.map_or(JsonValue::Null, |v| JsonValue::Number(Num::Float(v))))
+                .collect(),
+            _ => {
+                let formatter = ArrayFormatter::try_new(column.as_ref(), &options)?;
+                (0..column.len())
+                    .map(|i| match column.is_null(i) {
+                        true => JsonValue::Null,
+                        false => JsonValue::String(formatter.value(i).to_string()),
+                    })
+                    .collect()
+            }
+        };
+        columns.push(values);
+    }
+    for row in 0..batch.num_rows() {
+        out.push('{');
+        for (i, (field, values)) in schema.fields().iter().zip(&columns).enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_string(out, field.name())?;
+            write!(out, ":{}", values[row])?;
+        }
+        out.push_str("}\n");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::testing::{query_sample, LINES};
+
+    async fn batches(sql: &str) -> Vec<RecordBatch> {
+        query_sample(LINES, sql).await
+    }
+
+    #[tokio::test]
+    async fn test_render() {
+        let sql = "SELECT ip, status, method, is_bot, 1.5 AS f, NULL AS n FROM nginx \
+                   WHERE status = 304 LIMIT 1";
+        let batches = batches(sql).await;
+
+        let table = OutputFormat::Table.render(&batches).unwrap();
+        assert!(
+            table.contains("| 93.180.71.3 | 304    | GET    |"),
+            "{table}"
+        );
+
+        let csv = OutputFormat::Csv.render(&batches).unwrap();
+        assert_eq!(
+            csv,
+            "ip,status,method,is_bot,f,n\n93.180.71.3,304,GET,true,1.5,\n"
+        );
+
+        let json = OutputFormat::Json.render(&batches).unwrap();
+        assert_eq!(
+            json,
+            "{\"ip\":\"93.180.71.3\",\"status\":304,\"method\":\"GET\",\"is_bot\":true,\"f\":1.5,\"n\":null}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_duplicate_columns() {
+        // a join puts both tables' columns side by side under the same names
+        let sql =
+            "SELECT * FROM (SELECT status FROM nginx) a CROSS JOIN (SELECT status FROM nginx) b";
+        let err = OutputFormat::Json.render(&batches(sql).await).unwrap_err();
+        assert!(err.to_string().contains("\"status\""), "{err}");
+    }
+}
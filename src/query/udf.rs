@@ -0,0 +1,323 @@
+use std::{any::Any, net::IpAddr, sync::Arc};
+
+use arrow::{
+    array::{Array, ArrayRef, AsArray, BooleanArray, Int64Array, StringArray},
+    datatypes::{DataType, Int64Type},
+};
+use datafusion::{
+    common::{exec_err, plan_err, Result, ScalarValue},
+    logical_expr::{ColumnarValue, ScalarUDF, ScalarUDFImpl, Signature, Volatility},
+    prelude::SessionContext,
+};
+use winnow::Parser;
+
+use crate::{
+    arrow_record::ip_from_bytes,
+    net::{parse_ip, Cidr},
+    nginx::StatusClass,
+    ua::UaParser,
+    url::RequestTarget,
+};
+
+pub mod json;
+
+/// What an argument is coerced to before the function sees it.
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    /// The 16 byte binary the exporters write, or text.
+    Ip,
+    Str,
+    Int,
+}
+
+type Kernel = fn(&[ArrayRef]) -> Result<ArrayRef>;
+
+/// A scalar function evaluated a whole array at a time.
+#[derive(Debug)]
+struct LogUdf {
+    name: &'static str,
+    args: &'static [Arg],
+    return_type: DataType,
+    kernel: Kernel,
+    signature: Signature,
+}
+
+impl LogUdf {
+    fn new(
+        name: &'static str,
+        args: &'static [Arg],
+        return_type: DataType,
+        kernel: Kernel,
+    ) -> Self {
+        Self {
+            name,
+            args,
+            return_type,
+            kernel,
+            signature: Signature::user_defined(Volatility::Immutable),
+        }
+    }
+}
+
+impl ScalarUDFImpl for LogUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(self.return_type.clone())
+    }
+
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        if arg_types.len() != self.args.len() {
+            return plan_err!(
+                "{} takes {} arguments, got {}",
+                self.name,
+                self.args.len(),
+                arg_types.len()
+            );
+        }
+        let coerced = arg_types
+            .iter()
+            .zip(self.args)
+            .map(|(ty, arg)| match (arg, ty) {
+                (Arg::Ip, DataType::FixedSizeBinary(16)) => ty.clone(),
+                (Arg::Ip | Arg::Str, _) => DataType::Utf8,
+                (Arg::Int, _) => DataType::Int64,
+            })
+            .collect();
+        Ok(coerced)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let scalars = args
+            .iter()
+            .all(|arg| matches!(arg, ColumnarValue::Scalar(_)));
+        let arrays = ColumnarValue::values_to_arrays(args)?;
+        let result = (self.kernel)(&arrays)?;
+        if scalars {
+            Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(
+                &result, 0,
+            )?))
+        } else {
+            Ok(ColumnarValue::Array(result))
+        }
+    }
+}
+
+/// The log analytics and JSON scalar functions, for registering one by one.
+pub fn udfs() -> Vec<ScalarUDF> {
+    let log = [
+        LogUdf::new(
+            "cidr_contains",
+            &[Arg::Ip, Arg::Str],
+            DataType::Boolean,
+            cidr_contains,
+        ),
+        LogUdf::new("ip_to_int", &[Arg::Ip], DataType::Int64, ip_to_int),
+        LogUdf::new("url_path", &[Arg::Str], DataType::Utf8, url_path),
+        LogUdf::new(
+            "url_param",
+            &[Arg::Str, Arg::Str],
+            DataType::Utf8,
+            url_param,
+        ),
+        LogUdf::new("ua_family", &[Arg::Str], DataType::Utf8, ua_family),
+        LogUdf::new("status_class", &[Arg::Int], DataType::Utf8, status_class),
+    ]
+    .into_iter()
+    .map(ScalarUDF::new_from_impl);
+    log.chain(json::udfs()).collect()
+}
+
+/// Registers `cidr_contains(ip, '10.0.0.0/8')`, `ip_to_int(ip)`, `url_path(url)`,
+/// `url_param(url, 'q')`, `ua_family(ua)`, `status_class(status)`, the `json_*` functions
+/// and the `json_agg` and `json_agg_raw` aggregates.
+pub fn register_udfs(ctx: &SessionContext) {
+    for udf in udfs() {
+        ctx.register_udf(udf);
+    }
+    ctx.register_udaf(json::json_agg());
+    ctx.register_udaf(json::json_agg_raw());
+}
+
+/// Addresses that do not parse are null.
+fn ips(array: &ArrayRef) -> Vec<Option<IpAddr>> {
+    match array.data_type() {
+        DataType::FixedSizeBinary(16) => array
+            .as_fixed_size_binary()
+            .iter()
+            .map(|b| Some(ip_from_bytes(b?.try_into().ok()?)))
+            .collect(),
+        _ => array
+            .as_string::<i32>()
+            .iter()
+            .map(|s| parse_ip.parse(s?).ok())
+            .collect(),
+    }
+}
+
+fn cidr_contains(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let cidrs = args[1].as_string::<i32>();
+    let contains = ips(&args[0])
+        .into_iter()
+        .zip(cidrs)
+        .map(|(ip, cidr)| {
+            let (Some(ip), Some(cidr)) = (ip, cidr) else {
+                return Ok(None);
+            };
+            match cidr.parse::<Cidr>() {
+                Ok(cidr) => Ok(Some(cidr.contains(&ip))),
+                Err(err) => exec_err!("cidr_contains: {err}"),
+            }
+        })
+        .collect::<Result<BooleanArray>>()?;
+    Ok(Arc::new(contains))
+}
+
+/// IPv4 addresses as their 32 bit number; IPv6 ones do not fit and are null.
+fn ip_to_int(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let ints: Int64Array = ips(&args[0])
+        .into_iter()
+        .map(|ip| match ip? {
+            IpAddr::V4(v4) => Some(u32::from(v4) as i64),
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(|v4| u32::from(v4) as i64),
+        })
+        .collect();
+    Ok(Arc::new(ints))
+}
+
+fn url_path(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let paths: StringArray = args[0]
+        .as_string::<i32>()
+        .iter()
+        .map(|url| Some(RequestTarget::parse(url?).path))
+        .collect();
+    Ok(Arc::new(paths))
+}
+
+/// The first value of the parameter, percent-decoded.
+fn url_param(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let names = args[1].as_string::<i32>();
+    let values: StringArray = args[0]
+        .as_string::<i32>()
+        .iter()
+        .zip(names)
+        .map(|(url, name)| {
+            let target = RequestTarget::parse(url?);
+            target.query.get(name?).map(str::to_string)
+        })
+        .collect();
+    Ok(Arc::new(values))
+}
+
+fn ua_family(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let parser = UaParser::bundled();
+    let families: StringArray = args[0]
+        .as_string::<i32>()
+        .iter()
+        .map(|ua| Some(parser.parse(ua?).browser.family))
+        .collect();
+    Ok(Arc::new(families))
+}
+
+fn status_class(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let classes: StringArray = args[0]
+        .as_primitive::<Int64Type>()
+        .iter()
+        .map(|status| {
+            let class = u16::try_from(status?).map_or(StatusClass::Unknown, StatusClass::from);
+            Some(class.as_ref().to_string())
+        })
+        .collect();
+    Ok(Arc::new(classes))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::RecordBatch;
+
+    use super::*;
+    use crate::query::{run_sql, testing::query_sample};
+
+    const LINES: &str = r#"10.1.2.3 - - [17/May/2015:08:05:32 +0000] "GET /search?q=rust%20lang&page=2 HTTP/1.1" 200 490 "-" "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/115.0"
+93.180.71.3 - - [17/May/2015:08:05:23 +0000] "GET /downloads/product_1 HTTP/1.1" 404 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
+2001:db8::1 - - [18/May/2015:08:05:24 +0000] "GET /a%20b HTTP/1.1" 503 0 "-" "-""#;
+
+    async fn query(sql: &str) -> Vec<RecordBatch> {
+        query_sample(LINES, sql).await
+    }
+
+    fn strings(batches: &[RecordBatch], column: usize) -> Vec<Option<String>> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let column = b.column(column).as_string::<i32>();
+                column
+                    .iter()
+                    .map(|s| s.map(str::to_string))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_network_functions() {
+        let sql = "SELECT count(*) FROM nginx WHERE cidr_contains(ip, '10.0.0.0/8')";
+        let batches = query(sql).await;
+        assert_eq!(batches[0].column(0).as_primitive::<Int64Type>().value(0), 1);
+
+        let sql = "SELECT ip_to_int(ip), cidr_contains('::1', '::/0') FROM nginx";
+        let batches = query(sql).await;
+        let ints: Vec<_> = batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_primitive::<Int64Type>()
+                    .iter()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(ints, [Some(0x0a010203), Some(0x5db44703), None]);
+        assert!(batches[0].column(1).as_boolean().value(0));
+
+        let ctx = SessionContext::new();
+        register_udfs(&ctx);
+        let err = run_sql(&ctx, "SELECT cidr_contains('10.0.0.1', '10.0.0.0/99')")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid CIDR"), "{err}");
+        assert!(run_sql(&ctx, "SELECT ip_to_int('1.2.3.4', 1)")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_log_functions() {
+        let sql = "SELECT url_path(url), url_param(url, 'q'), ua_family(ua), status_class(status) \
+                   FROM nginx";
+        let batches = query(sql).await;
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            strings(&batches, 0),
+            [some("/search"), some("/downloads/product_1"), some("/a b")]
+        );
+        assert_eq!(strings(&batches, 1), [some("rust lang"), None, None]);
+        assert_eq!(
+            strings(&batches, 2),
+            [some("Firefox"), some("APT"), some("Other")]
+        );
+        assert_eq!(
+            strings(&batches, 3),
+            [some("2xx"), some("4xx"), some("5xx")]
+        );
+    }
+}
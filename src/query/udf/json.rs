@@ -0,0 +1,309 @@
+use std::{any::Any, mem, sync::Arc};
+
+use arrow::{
+    array::{ArrayRef, AsArray, Int64Array, StringArray},
+    datatypes::{DataType, Field, Float64Type, Int64Type},
+};
+use datafusion::{
+    common::{plan_err, Result, ScalarValue},
+    logical_expr::{
+        function::{AccumulatorArgs, StateFieldsArgs},
+        Accumulator, AggregateUDF, AggregateUDFImpl, ScalarUDF, Signature, Volatility,
+    },
+};
+
+use super::{Arg, LogUdf};
+use crate::json::{parse_json, JsonValue, Num};
+
+/// `json_get(doc, '/path')`, `json_get_int(doc, '/path')` and `json_typeof(doc)`.
+pub fn udfs() -> Vec<ScalarUDF> {
+    [
+        LogUdf::new("json_get", &[Arg::Str, Arg::Str], DataType::Utf8, json_get),
+        LogUdf::new(
+            "json_get_int",
+            &[Arg::Str, Arg::Str],
+            DataType::Int64,
+            json_get_int,
+        ),
+        LogUdf::new("json_typeof", &[Arg::Str], DataType::Utf8, json_typeof),
+    ]
+    .into_iter()
+    .map(ScalarUDF::new_from_impl)
+    .collect()
+}
+
+/// The values at a JSON pointer in each document; invalid documents and missing values are
+/// `None`.
+fn pointed<T>(args: &[ArrayRef], f: impl Fn(&JsonValue) -> Option<T>) -> Vec<Option<T>> {
+    let pointers = args[1].as_string::<i32>();
+    args[0]
+        .as_string::<i32>()
+        .iter()
+        .zip(pointers)
+        .map(|(doc, pointer)| f(parse_json(doc?).ok()?.pointer(pointer?)?))
+        .collect()
+}
+
+/// Strings come out as they are, other values as JSON text, and JSON `null` as NULL.
+fn json_get(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let values: StringArray = pointed(args, |value| match value {
+        JsonValue::Null => None,
+        JsonValue::String(s) => Some(s.clone()),
+        value => Some(value.to_string()),
+    })
+    .into_iter()
+    .collect();
+    Ok(Arc::new(values))
+}
+
+fn json_get_int(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let values: Int64Array = pointed(args, JsonValue::as_i64).into_iter().collect();
+    Ok(Arc::new(values))
+}
+
+fn json_typeof(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let types: StringArray = args[0]
+        .as_string::<i32>()
+        .iter()
+        .map(|doc| Some(parse_json(doc?).ok()?.type_name()))
+        .collect();
+    Ok(Arc::new(types))
+}
+
+/// `json_agg(value)`: the values of a group as a JSON array.
+///
+/// Text becomes a JSON string, numbers and booleans their JSON counterparts and NULL
+/// `null`. `json_agg_raw(value)` instead takes text to be JSON, such as the objects
+/// `json_get` returns, and only keeps it as a string when it does not parse.
+#[derive(Debug)]
+pub struct JsonAgg {
+    signature: Signature,
+    raw: bool,
+}
+
+pub fn json_agg() -> AggregateUDF {
+    AggregateUDF::new_from_impl(JsonAgg {
+        signature: Signature::user_defined(Volatility::Immutable),
+        raw: false,
+    })
+}
+
+pub fn json_agg_raw() -> AggregateUDF {
+    AggregateUDF::new_from_impl(JsonAgg {
+        signature: Signature::user_defined(Volatility::Immutable),
+        raw: true,
+    })
+}
+
+impl AggregateUDFImpl for JsonAgg {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        if self.raw {
+            "json_agg_raw"
+        } else {
+            "json_agg"
+        }
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Utf8)
+    }
+
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        let [ty] = arg_types else {
+            return plan_err!("{} takes 1 argument, got {}", self.name(), arg_types.len());
+        };
+        let coerced = match ty {
+            DataType::Boolean => DataType::Boolean,
+            ty if ty.is_integer() => DataType::Int64,
+            ty if ty.is_numeric() => DataType::Float64,
+            _ => DataType::Utf8,
+        };
+        Ok(vec![coerced])
+    }
+
+    fn state_fields(&self, args: StateFieldsArgs) -> Result<Vec<Field>> {
+        Ok(vec![Field::new(
+            format!("{}[values]", args.name),
+            DataType::Utf8,
+            true,
+        )])
+    }
+
+    fn accumulator(&self, _args: AccumulatorArgs) -> Result<Box<dyn Accumulator>> {
+        Ok(Box::new(JsonAggAccumulator {
+            raw: self.raw,
+            values: Vec::new(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct JsonAggAccumulator {
+    raw: bool,
+    values: Vec<JsonValue>,
+}
+
+impl JsonAggAccumulator {
+    fn to_json(&self) -> String {
+        JsonValue::Array(self.values.clone()).to_string()
+    }
+}
+
+impl Accumulator for JsonAggAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let array = &values[0];
+        let converted: Vec<JsonValue> = match array.data_type() {
+            DataType::Boolean => array
+                .as_boolean()
+                .iter()
+                .map(|v| v.map_or(JsonValue::Null, JsonValue::Bool))
+                .collect(),
+            DataType::Int64 => array
+                .as_primitive::<Int64Type>()
+                .iter()
+                .map(|v| v.map_or(JsonValue::Null, |v| JsonValue::Number(Num::Int(v))))
+                .collect(),
+            DataType::Float64 => array
+                .as_primitive::<Float64Type>()
+                .iter()
+                .map(|v| v.map_or(JsonValue::Null, |v| JsonValue::Number(Num::Float(v))))
+                .collect(),
+            _ => array
+                .as_string::<i32>()
+                .iter()
+                .map(|doc| match doc {
+                    None => JsonValue::Null,
+                    Some(doc) if self.raw => {
+                        parse_json(doc).unwrap_or_else(|_| JsonValue::String(doc.to_string()))
+                    }
+                    Some(doc) => JsonValue::String(doc.to_string()),
+                })
+                .collect(),
+        };
+        self.values.extend(converted);
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        Ok(ScalarValue::Utf8(Some(self.to_json())))
+    }
+
+    fn size(&self) -> usize {
+        mem::size_of_val(self) + self.values.capacity() * mem::size_of::<JsonValue>()
+    }
+
+    /// Partial results travel between partitions as JSON arrays.
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![ScalarValue::Utf8(Some(self.to_json()))])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        for state in states[0].as_string::<i32>().iter().flatten() {
+            if let Ok(JsonValue::Array(values)) = parse_json(state) {
+                self.values.extend(values);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::prelude::SessionContext;
+
+    use crate::query::{register_udfs, run_sql};
+
+    use super::*;
+
+    async fn strings(sql: &str) -> Vec<Option<String>> {
+        let ctx = SessionContext::new();
+        register_udfs(&ctx);
+        let setup = r#"CREATE TABLE requests (id INT, body VARCHAR) AS VALUES
+            (1, '{"user": {"id": 7, "name": "ann"}, "items": [{"sku": "a"}, {"sku": "b"}]}'),
+            (2, '{"user": {"id": "8"}, "items": []}'),
+            (3, 'not json'),
+            (4, NULL)"#;
+        run_sql(&ctx, setup).await.unwrap();
+        let batches = run_sql(&ctx, sql).await.unwrap();
+        batches
+            .iter()
+            .flat_map(|b| {
+                let column = arrow::compute::cast(b.column(0), &DataType::Utf8).unwrap();
+                let column = column.as_string::<i32>();
+                column
+                    .iter()
+                    .map(|s| s.map(str::to_string))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn some(values: &[&str]) -> Vec<Option<String>> {
+        values.iter().map(|s| Some(s.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_json_get() {
+        let got = strings("SELECT json_get(body, '/user/name') FROM requests ORDER BY id").await;
+        assert_eq!(got, [Some("ann".to_string()), None, None, None]);
+        let got = strings("SELECT json_get(body, '/items/1') FROM requests WHERE id = 1").await;
+        assert_eq!(got, some(&[r#"{"sku":"b"}"#]));
+        let got = strings("SELECT json_get_int(body, '/user/id') FROM requests ORDER BY id").await;
+        assert_eq!(got, [Some("7".to_string()), None, None, None]);
+        let got = strings("SELECT json_typeof(body) FROM requests ORDER BY id").await;
+        assert_eq!(
+            got,
+            [
+                Some("object".to_string()),
+                Some("object".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_agg() {
+        let got = strings("SELECT json_agg(json_get(body, '/user/id')) FROM requests").await;
+        let got = parse_json(got[0].as_deref().unwrap()).unwrap();
+        let JsonValue::Array(mut values) = got else {
+            panic!("json_agg did not return an array");
+        };
+        values.sort_by_key(|v| v.to_string());
+        assert_eq!(
+            values.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+            [r#""7""#, r#""8""#, "null", "null"]
+        );
+        let got = strings("SELECT json_agg_raw(json_get(body, '/user/id')) FROM requests").await;
+        let JsonValue::Array(mut values) = parse_json(got[0].as_deref().unwrap()).unwrap() else {
+            panic!("json_agg_raw did not return an array");
+        };
+        values.sort_by_key(|v| v.to_string());
+        assert_eq!(
+            values.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+            ["7", "8", "null", "null"]
+        );
+
+        let got =
+            strings("SELECT json_agg(id) FROM requests WHERE id < 3 GROUP BY id % 2 ORDER BY 1")
+                .await;
+        assert_eq!(got, some(&["[1]", "[2]"]));
+        let got = strings("SELECT json_agg(body) FROM requests WHERE id = 2").await;
+        assert_eq!(
+            got,
+            some(&[r#"["{\"user\": {\"id\": \"8\"}, \"items\": []}"]"#])
+        );
+        let got = strings("SELECT json_agg_raw(body) FROM requests WHERE id = 2").await;
+        assert_eq!(got, some(&[r#"[{"items":[],"user":{"id":"8"}}]"#]));
+        let got = strings("SELECT json_agg_raw(body) FROM requests WHERE id = 3").await;
+        assert_eq!(got, some(&[r#"["not json"]"#]));
+    }
+}
@@ -0,0 +1,571 @@
+use anyhow::Result;
+use arrow::{
+    array::{AsArray, RecordBatch},
+    compute::cast,
+    datatypes::{DataType, Int64Type, TimeUnit},
+};
+use chrono::{DateTime, Utc};
+use clap::{Args, ValueEnum};
+use datafusion::prelude::SessionContext;
+
+use crate::{
+    json::{write_string, JsonValue, Num},
+    query::{output::readable, run_sql},
+};
+
+pub mod html;
+pub mod table;
+
+pub use table::Table;
+
+/// How a [`TrafficReport`] is written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Markdown,
+    Json,
+    /// A single page with its styles and charts inline.
+    Html,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ReportOptions {
+    /// Rows in each top list.
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self { top: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    pub requests: u64,
+    pub bytes: u64,
+    /// Distinct client IP, user agent and day, as GoAccess counts them.
+    pub visitors: u64,
+    pub ips: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+}
+
+/// Traffic in one minute or hour.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    pub start: DateTime<Utc>,
+    pub requests: u64,
+    pub bytes: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+}
+
+impl Bucket {
+    /// The share of requests answered with a 4xx or 5xx status.
+    pub fn error_rate(&self) -> f64 {
+        ratio(self.client_errors + self.server_errors, self.requests)
+    }
+}
+
+/// A row of a top list or the status distribution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub value: String,
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+/// What GoAccess shows for an access log, computed with DataFusion over a registered table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrafficReport {
+    pub summary: Summary,
+    pub per_minute: Vec<Bucket>,
+    pub per_hour: Vec<Bucket>,
+    pub statuses: Vec<Entry>,
+    pub urls: Vec<Entry>,
+    pub ips: Vec<Entry>,
+    pub user_agents: Vec<Entry>,
+    /// How many rows the top lists were cut to.
+    pub top: usize,
+}
+
+const COLUMNS: [&str; 6] = ["ip", "datetime", "path", "status", "body_bytes", "ua"];
+
+const ERRORS: &str = "sum(CASE WHEN status >= 400 AND status < 500 THEN 1 ELSE 0 END), \
+                      sum(CASE WHEN status >= 500 THEN 1 ELSE 0 END)";
+
+impl TrafficReport {
+    /// Reads `table`, which needs the columns of the parsed [`NginxLog`](crate::nginx::NginxLog)
+    /// schema: `ip`, `datetime`, `path`, `status`, `body_bytes` and `ua`.
+    pub async fn compute(
+        ctx: &SessionContext,
+        table: &str,
+        options: &ReportOptions,
+    ) -> Result<Self> {
+        // every query reads the same few columns, so scan them once rather than re-parsing
+        // raw logs each time
+        let logs = ctx
+            .table(table)
+            .await?
+            .select_columns(&COLUMNS)?
+            .cache()
+            .await?;
+        let ctx = &SessionContext::new();
+        ctx.register_table("logs", logs.into_view())?;
+        let table = "logs";
+
+        let sql = format!(
+            "SELECT count(*), sum(body_bytes), count(DISTINCT ip), {ERRORS}, min(datetime), \
+             max(datetime) FROM {table}"
+        );
+        let totals = Rows::query(ctx, &sql).await?;
+        let sql = format!(
+            "SELECT count(*) FROM (SELECT DISTINCT ip, ua, date_trunc('day', datetime) FROM {table})"
+        );
+        let visitors = Rows::query(ctx, &sql).await?;
+        let summary = Summary {
+            requests: totals.ints(0)?.first().copied().unwrap_or_default(),
+            bytes: totals.ints(1)?.first().copied().unwrap_or_default(),
+            ips: totals.ints(2)?.first().copied().unwrap_or_default(),
+            client_errors: totals.ints(3)?.first().copied().unwrap_or_default(),
+            server_errors: totals.ints(4)?.first().copied().unwrap_or_default(),
+            first: totals.times(5)?.first().copied().flatten(),
+            last: totals.times(6)?.first().copied().flatten(),
+            visitors: visitors.ints(0)?.first().copied().unwrap_or_default(),
+        };
+
+        let top = options.top;
+        let by_status = format!(
+            "SELECT status, count(*), sum(body_bytes) FROM {table} GROUP BY status ORDER BY status"
+        );
+        Ok(Self {
+            summary,
+            per_minute: buckets(ctx, table, "minute").await?,
+            per_hour: buckets(ctx, table, "hour").await?,
+            statuses: Rows::query(ctx, &by_status).await?.entries()?,
+            urls: top_entries(ctx, table, "path", top).await?,
+            ips: top_entries(ctx, table, "ip", top).await?,
+            user_agents: top_entries(ctx, table, "ua", top).await?,
+            top,
+        })
+    }
+
+    /// The busiest minutes, most requests first.
+    pub fn peak_minutes(&self) -> Vec<&Bucket> {
+        let mut minutes: Vec<&Bucket> = self.per_minute.iter().collect();
+        minutes.sort_by(|a, b| b.requests.cmp(&a.requests).then(a.start.cmp(&b.start)));
+        minutes.truncate(self.top);
+        minutes
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Text => self.render_tables(Table::to_text),
+            ReportFormat::Markdown => self.render_tables(Table::to_markdown),
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Html => html::page(self),
+        }
+    }
+
+    fn render_tables(&self, render: fn(&Table) -> String) -> String {
+        self.tables()
+            .iter()
+            .map(render)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The report as the tables the text, Markdown and HTML outputs show; the full
+    /// per-minute series is left to JSON and only the busiest minutes are listed.
+    pub fn tables(&self) -> Vec<Table> {
+        let s = &self.summary;
+        let period = match (s.first, s.last) {
+            (Some(first), Some(last)) => format!("{} to {}", timestamp(first), timestamp(last)),
+            _ => "-".to_string(),
+        };
+        let summary = Table::new("Summary", &["Metric", "Value"])
+            .left_aligned()
+            .rows([
+                vec!["Period".to_string(), period],
+                vec!["Requests".to_string(), s.requests.to_string()],
+                vec!["Unique visitors".to_string(), s.visitors.to_string()],
+                vec!["Unique IPs".to_string(), s.ips.to_string()],
+                vec!["Bytes served".to_string(), human_bytes(s.bytes)],
+                vec![
+                    "4xx responses".to_string(),
+                    with_share(s.client_errors, s.requests),
+                ],
+                vec![
+                    "5xx responses".to_string(),
+                    with_share(s.server_errors, s.requests),
+                ],
+            ]);
+        let bucket_row = |b: &Bucket, format: &str| {
+            vec![
+                b.start.format(format).to_string(),
+                b.requests.to_string(),
+                human_bytes(b.bytes),
+                b.client_errors.to_string(),
+                b.server_errors.to_string(),
+                percent(b.error_rate()),
+            ]
+        };
+        let bucket_headers = |unit| [unit, "Requests", "Bytes", "4xx", "5xx", "Error rate"];
+        let per_hour = Table::new("Requests per hour", &bucket_headers("Hour")).rows(
+            self.per_hour
+                .iter()
+                .map(|b| bucket_row(b, "%Y-%m-%d %H:00")),
+        );
+        let peaks = Table::new("Busiest minutes", &bucket_headers("Minute")).rows(
+            self.peak_minutes()
+                .into_iter()
+                .map(|b| bucket_row(b, "%Y-%m-%d %H:%M")),
+        );
+        let entries = |title: &str, header: &str, entries: &[Entry]| {
+            Table::new(title, &[header, "Requests", "Share", "Bytes"]).rows(entries.iter().map(
+                |e| {
+                    vec![
+                        e.value.clone(),
+                        e.requests.to_string(),
+                        percent(ratio(e.requests, s.requests)),
+                        human_bytes(e.bytes),
+                    ]
+                },
+            ))
+        };
+        vec![
+            summary,
+            per_hour,
+            peaks,
+            entries("Status codes", "Status", &self.statuses),
+            entries("Top URLs", "Path", &self.urls),
+            entries("Top client IPs", "IP", &self.ips),
+            entries("Top user agents", "User agent", &self.user_agents),
+        ]
+    }
+
+    /// Everything computed, with the full per-minute and per-hour series, keys in the order
+    /// they are listed here.
+    pub fn to_json(&self) -> String {
+        let s = &self.summary;
+        let time = |t: Option<DateTime<Utc>>| {
+            t.map_or(JsonValue::Null, |t| JsonValue::String(t.to_rfc3339()))
+        };
+        let summary = object([
+            ("requests", int(s.requests)),
+            ("bytes", int(s.bytes)),
+            ("visitors", int(s.visitors)),
+            ("ips", int(s.ips)),
+            ("client_errors", int(s.client_errors)),
+            ("server_errors", int(s.server_errors)),
+            ("first", time(s.first).to_string()),
+            ("last", time(s.last).to_string()),
+        ]);
+        let buckets = |buckets: &[Bucket]| {
+            array(buckets.iter().map(|b| {
+                object([
+                    ("start", JsonValue::String(b.start.to_rfc3339()).to_string()),
+                    ("requests", int(b.requests)),
+                    ("bytes", int(b.bytes)),
+                    ("client_errors", int(b.client_errors)),
+                    ("server_errors", int(b.server_errors)),
+                ])
+            }))
+        };
+        let entries = |entries: &[Entry]| {
+            array(entries.iter().map(|e| {
+                object([
+                    ("value", JsonValue::String(e.value.clone()).to_string()),
+                    ("requests", int(e.requests)),
+                    ("bytes", int(e.bytes)),
+                ])
+            }))
+        };
+        object([
+            ("summary", summary),
+            ("per_minute", buckets(&self.per_minute)),
+            ("per_hour", buckets(&self.per_hour)),
+            ("statuses", entries(&self.statuses)),
+            ("urls", entries(&self.urls)),
+            ("ips", entries(&self.ips)),
+            ("user_agents", entries(&self.user_agents)),
+        ])
+    }
+}
+
+async fn buckets(ctx: &SessionContext, table: &str, unit: &str) -> Result<Vec<Bucket>> {
+    let sql = format!(
+        "SELECT date_trunc('{unit}', datetime) AS start, count(*), sum(body_bytes), {ERRORS} \
+         FROM {table} WHERE datetime IS NOT NULL GROUP BY start ORDER BY start"
+    );
+    let rows = Rows::query(ctx, &sql).await?;
+    let (requests, bytes) = (rows.ints(1)?, rows.ints(2)?);
+    let (client_errors, server_errors) = (rows.ints(3)?, rows.ints(4)?);
+    let buckets = rows
+        .times(0)?
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, start)| {
+            Some(Bucket {
+                start: start?,
+                requests: requests[i],
+                bytes: bytes[i],
+                client_errors: client_errors[i],
+                server_errors: server_errors[i],
+            })
+        })
+        .collect();
+    Ok(buckets)
+}
+
+async fn top_entries(
+    ctx: &SessionContext,
+    table: &str,
+    column: &str,
+    top: usize,
+) -> Result<Vec<Entry>> {
+    let sql = format!(
+        "SELECT {column}, count(*) AS requests, sum(body_bytes) FROM {table} \
+         WHERE {column} IS NOT NULL GROUP BY {column} ORDER BY requests DESC, {column} LIMIT {top}"
+    );
+    Rows::query(ctx, &sql).await?.entries()
+}
+
+/// Query results with IP columns as text, read a column at a time.
+struct Rows(Vec<RecordBatch>);
+
+impl Rows {
+    async fn query(ctx: &SessionContext, sql: &str) -> Result<Self> {
+        let batches = run_sql(ctx, sql).await?;
+        Ok(Self(batches.iter().map(readable).collect::<Result<_>>()?))
+    }
+
+    /// Nulls, such as the sum over no rows, are 0.
+    fn ints(&self, column: usize) -> Result<Vec<u64>> {
+        let mut values = Vec::new();
+        for batch in &self.0 {
+            let ints = cast(batch.column(column), &DataType::Int64)?;
+            let ints = ints.as_primitive::<Int64Type>();
+            values.extend(ints.iter().map(|v| v.unwrap_or_default().max(0) as u64));
+        }
+        Ok(values)
+    }
+
+    fn strings(&self, column: usize) -> Result<Vec<String>> {
+        let mut values = Vec::new();
+        for batch in &self.0 {
+            let strings = cast(batch.column(column), &DataType::Utf8)?;
+            let strings = strings.as_string::<i32>();
+            values.extend(strings.iter().map(|v| v.unwrap_or("-").to_string()));
+        }
+        Ok(values)
+    }
+
+    fn times(&self, column: usize) -> Result<Vec<Option<DateTime<Utc>>>> {
+        let mut values = Vec::new();
+        for batch in &self.0 {
+            // `date_trunc` gives nanoseconds while the tables hold microseconds
+            let micros = cast(
+                batch.column(column),
+                &DataType::Timestamp(TimeUnit::Microsecond, None),
+            )?;
+            let micros = cast(&micros, &DataType::Int64)?;
+            let micros = micros.as_primitive::<Int64Type>();
+            values.extend(micros.iter().map(|v| DateTime::from_timestamp_micros(v?)));
+        }
+        Ok(values)
+    }
+
+    /// Value, request count and bytes columns.
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let (requests, bytes) = (self.ints(1)?, self.ints(2)?);
+        Ok(self
+            .strings(0)?
+            .into_iter()
+            .zip(requests.into_iter().zip(bytes))
+            .map(|(value, (requests, bytes))| Entry {
+                value,
+                requests,
+                bytes,
+            })
+            .collect())
+    }
+}
+
+/// A JSON object from keys and values already written as JSON, unlike
+/// [`JsonValue::Object`] keeping the keys in order.
+fn object<const N: usize>(fields: [(&str, String); N]) -> String {
+    let mut out = String::from("{");
+    for (i, (key, value)) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_string(&mut out, key).expect("writing to a String cannot fail");
+        out.push(':');
+        out.push_str(&value);
+    }
+    out.push('}');
+    out
+}
+
+fn array(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+fn int(n: u64) -> String {
+    JsonValue::Number(Num::Int(n as i64)).to_string()
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+fn percent(ratio: f64) -> String {
+    format!("{:.1}%", ratio * 100.0)
+}
+
+fn with_share(part: u64, whole: u64) -> String {
+    format!("{part} ({})", percent(ratio(part, whole)))
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+/// Bytes in binary units, e.g. `1.5 MiB`.
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{value:.1} {unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        json::parse_json,
+        query::{testing::sample_context, NGINX_TABLE},
+    };
+
+    const LINES: &str = r#"93.180.71.3 - - [17/May/2015:08:05:32 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
+93.180.71.3 - - [17/May/2015:08:05:23 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
+80.91.33.133 - - [17/May/2015:08:06:24 +0000] "GET /downloads/product_2 HTTP/1.1" 404 336 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.17)"
+93.180.71.3 - - [18/May/2015:09:05:32 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
+217.168.17.5 - - [18/May/2015:09:05:34 +0000] "GET /downloads/product_1 HTTP/1.1" 500 2048 "-" "Debian APT-HTTP/1.3 (0.8.10.3)""#;
+
+    async fn report() -> TrafficReport {
+        let ctx = sample_context(LINES);
+        TrafficReport::compute(&ctx, NGINX_TABLE, &ReportOptions { top: 2 })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_compute() {
+        let report = report().await;
+        let s = &report.summary;
+        assert_eq!((s.requests, s.bytes, s.ips), (5, 2384, 3));
+        // 93.180.71.3 comes back the next day with the same user agent
+        assert_eq!(s.visitors, 4);
+        assert_eq!((s.client_errors, s.server_errors), (1, 1));
+        assert_eq!(timestamp(s.first.unwrap()), "2015-05-17 08:05:23 UTC");
+
+        let hours: Vec<_> = report.per_hour.iter().map(|b| b.requests).collect();
+        assert_eq!(hours, [3, 2]);
+        assert_eq!(
+            timestamp(report.per_hour[1].start),
+            "2015-05-18 09:00:00 UTC"
+        );
+        assert_eq!(report.per_hour[1].error_rate(), 0.5);
+        assert_eq!(report.per_minute.len(), 3);
+        assert_eq!(report.peak_minutes()[0].requests, 2);
+
+        let statuses: Vec<_> = report.statuses.iter().map(|e| e.value.as_str()).collect();
+        assert_eq!(statuses, ["304", "404", "500"]);
+        assert_eq!(report.urls[0].value, "/downloads/product_1");
+        assert_eq!(report.urls[0].requests, 4);
+        assert_eq!(report.ips.len(), 2);
+        assert_eq!(report.ips[0].value, "93.180.71.3");
+        assert_eq!(
+            report.user_agents[0].value,
+            "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render() {
+        let report = report().await;
+        let text = report.render(ReportFormat::Text);
+        assert!(text.contains("Unique visitors  4"), "{text}");
+        assert!(text.contains("2015-05-18 09:00"), "{text}");
+
+        let markdown = report.render(ReportFormat::Markdown);
+        assert!(markdown.contains("## Top client IPs"), "{markdown}");
+        assert!(
+            markdown.contains("| 93.180.71.3 | 3 | 60.0% | 0 B |"),
+            "{markdown}"
+        );
+
+        let text = report.render(ReportFormat::Json);
+        // keys in a fixed order, so reports of the same logs are byte for byte the same
+        assert!(
+            text.starts_with(r#"{"summary":{"requests":5,"bytes":"#),
+            "{text}"
+        );
+        let keys = [
+            "per_minute",
+            "per_hour",
+            "statuses",
+            "urls",
+            "ips",
+            "user_agents",
+        ]
+        .map(|key| text.find(&format!(r#","{key}":["#)).unwrap_or(0));
+        assert!(keys.windows(2).all(|w| 0 < w[0] && w[0] < w[1]), "{text}");
+        let json = parse_json(&text).unwrap();
+        assert_eq!(json.pointer("/summary/bytes").unwrap().as_i64(), Some(2384));
+        assert_eq!(
+            json.pointer("/per_minute/0/start").unwrap().as_str(),
+            Some("2015-05-17T08:05:00+00:00")
+        );
+        assert_eq!(
+            json.pointer("/statuses/2/value").unwrap().as_str(),
+            Some("500")
+        );
+
+        let html = report.render(ReportFormat::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<svg"));
+        assert!(html.contains("<h2>Top user agents</h2>"));
+        assert!(html.contains("<title>2015-05-18 09:00: 2 requests, 2.0 KiB, 0 4xx, 1 5xx</title>"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        assert_eq!(human_bytes(0), "0 B");
+        assert_eq!(human_bytes(1023), "1023 B");
+        assert_eq!(human_bytes(1536), "1.5 KiB");
+        assert_eq!(human_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
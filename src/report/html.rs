@@ -0,0 +1,134 @@
+use std::{borrow::Cow, fmt::Write};
+
+use super::{human_bytes, Bucket, TrafficReport};
+
+const STYLE: &str = "
+body { font: 14px/1.4 system-ui, sans-serif; margin: 2em auto; max-width: 1100px; color: #222; }
+h1 { font-size: 1.6em; }
+h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ddd; }
+table { border-collapse: collapse; width: 100%; }
+th, td { padding: 2px 8px; text-align: left; border-bottom: 1px solid #eee; word-break: break-all; }
+.num { text-align: right; white-space: nowrap; }
+svg { width: 100%; height: auto; background: #fafafa; }
+.ok { fill: #4c78a8; } .c4 { fill: #f58518; } .c5 { fill: #e45756; }
+.legend span { display: inline-block; width: 10px; height: 10px; margin: 0 4px 0 12px; }
+";
+
+const WIDTH: f64 = 1000.0;
+const HEIGHT: f64 = 200.0;
+
+/// Escapes text for element content and quoted attribute values.
+pub fn escape(s: &str) -> Cow<'_, str> {
+    if !s.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(s);
+    }
+    let mut escaped = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// A page that opens offline: styles are inline and the charts are SVG, with no scripts.
+pub fn page(report: &TrafficReport) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Traffic report</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>Traffic report</h1>\n"
+    );
+    let mut tables = report.tables().into_iter();
+    if let Some(summary) = tables.next() {
+        out.push_str(&summary.to_html());
+    }
+    out.push_str("<section>\n<h2>Hourly traffic</h2>\n");
+    out.push_str(
+        "<p class=\"legend\"><span style=\"background:#4c78a8\"></span>other\
+         <span style=\"background:#f58518\"></span>4xx\
+         <span style=\"background:#e45756\"></span>5xx</p>\n",
+    );
+    out.push_str(&chart(&report.per_hour, "%Y-%m-%d %H:00"));
+    out.push_str("</section>\n");
+    for table in tables {
+        out.push_str(&table.to_html());
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Stacked bars of the requests in each bucket, split into 5xx, 4xx and the rest.
+fn chart(buckets: &[Bucket], label: &str) -> String {
+    let mut svg = format!(
+        "<svg viewBox=\"0 0 {WIDTH} {}\" xmlns=\"http://www.w3.org/2000/svg\">\n",
+        HEIGHT + 20.0
+    );
+    let max = buckets.iter().map(|b| b.requests).max().unwrap_or(0);
+    if max > 0 {
+        let width = WIDTH / buckets.len() as f64;
+        let scale = HEIGHT / max as f64;
+        for (i, b) in buckets.iter().enumerate() {
+            let x = i as f64 * width;
+            let title = format!(
+                "{}: {} requests, {}, {} 4xx, {} 5xx",
+                b.start.format(label),
+                b.requests,
+                human_bytes(b.bytes),
+                b.client_errors,
+                b.server_errors
+            );
+            let _ = writeln!(svg, "<g><title>{}</title>", escape(&title));
+            let ok = b.requests.saturating_sub(b.client_errors + b.server_errors);
+            let mut top = HEIGHT;
+            for (class, count) in [("ok", ok), ("c4", b.client_errors), ("c5", b.server_errors)] {
+                if count == 0 {
+                    continue;
+                }
+                let height = count as f64 * scale;
+                top -= height;
+                let _ = writeln!(
+                    svg,
+                    "<rect class=\"{class}\" x=\"{x:.2}\" y=\"{top:.2}\" width=\"{width:.2}\" \
+                     height=\"{height:.2}\"/>"
+                );
+            }
+            svg.push_str("</g>\n");
+        }
+        let first = buckets[0].start.format(label);
+        let last = buckets[buckets.len() - 1].start.format(label);
+        let y = HEIGHT + 15.0;
+        let _ = writeln!(
+            svg,
+            "<text x=\"0\" y=\"{y}\" font-size=\"12\">{first}</text>"
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{WIDTH}\" y=\"{y}\" font-size=\"12\" text-anchor=\"end\">{last}</text>"
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"4\" y=\"14\" font-size=\"12\">{max} requests</text>"
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("/index.html"), "/index.html");
+        assert_eq!(
+            escape(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt::Write;
+
+use super::html::escape;
+
+/// A titled table of preformatted cells, written as text, Markdown or HTML.
+///
+/// The first column holds labels; the others are numbers and right aligned unless the table is
+/// [`left_aligned`](Table::left_aligned).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub title: String,
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    pub left_aligned: bool,
+}
+
+impl Table {
+    pub fn new(title: &str, headers: &[&str]) -> Self {
+        Self {
+            title: title.to_string(),
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+            left_aligned: false,
+        }
+    }
+
+    pub fn rows(mut self, rows: impl IntoIterator<Item = Vec<String>>) -> Self {
+        self.rows.extend(rows);
+        self
+    }
+
+    pub fn left_aligned(mut self) -> Self {
+        self.left_aligned = true;
+        self
+    }
+
+    fn right_aligned(&self, column: usize) -> bool {
+        column > 0 && !self.left_aligned
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let line = |cells: &[String]| {
+            let mut line = String::new();
+            for (i, (cell, width)) in cells.iter().zip(&widths).enumerate() {
+                if i > 0 {
+                    line.push_str("  ");
+                }
+                if self.right_aligned(i) {
+                    let _ = write!(line, "{cell:>width$}");
+                } else {
+                    let _ = write!(line, "{cell:<width$}");
+                }
+            }
+            line.trim_end().to_string()
+        };
+        let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+
+        let mut out = format!(
+            "{}\n{}\n",
+            self.title,
+            "=".repeat(self.title.chars().count())
+        );
+        out.push_str(&line(&self.headers));
+        out.push('\n');
+        out.push_str(&line(&rule));
+        out.push('\n');
+        for row in &self.rows {
+            out.push_str(&line(row));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_markdown(&self) -> String {
+        // `<` would start inline HTML
+        let cell = |s: &String| s.replace('|', "\\|").replace('<', "&lt;");
+        let rule = (0..self.headers.len()).map(|i| match self.right_aligned(i) {
+            true => "---:".to_string(),
+            false => "---".to_string(),
+        });
+        let mut out = format!("## {}\n\n", self.title);
+        out.push_str(&markdown_row(self.headers.iter().map(cell)));
+        out.push_str(&markdown_row(rule));
+        for row in &self.rows {
+            out.push_str(&markdown_row(row.iter().map(cell)));
+        }
+        out
+    }
+
+    pub fn to_html(&self) -> String {
+        let class = |i: usize| {
+            if self.right_aligned(i) {
+                " class=\"num\""
+            } else {
+                ""
+            }
+        };
+        let mut out = format!(
+            "<section>\n<h2>{}</h2>\n<table>\n<thead><tr>",
+            escape(&self.title)
+        );
+        for (i, header) in self.headers.iter().enumerate() {
+            let _ = write!(out, "<th{}>{}</th>", class(i), escape(header));
+        }
+        out.push_str("</tr></thead>\n<tbody>\n");
+        for row in &self.rows {
+            out.push_str("<tr>");
+            for (i, cell) in row.iter().enumerate() {
+                let _ = write!(out, "<td{}>{}</td>", class(i), escape(cell));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</tbody>\n</table>\n</section>\n");
+        out
+    }
+}
+
+fn markdown_row(cells: impl Iterator<Item = String>) -> String {
+    format!("| {} |\n", cells.collect::<Vec<_>>().join(" | "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        Table::new("Top URLs", &["Path", "Requests"]).rows([
+            vec!["/a|b".to_string(), "12".to_string()],
+            vec!["/<index>".to_string(), "3".to_string()],
+        ])
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(
+            table().to_text(),
+            "Top URLs\n========\nPath      Requests\n--------  --------\n/a|b            12\n/<index>         3\n"
+        );
+        assert_eq!(
+            table().to_markdown(),
+            "## Top URLs\n\n| Path | Requests |\n| --- | ---: |\n| /a\\|b | 12 |\n| /&lt;index> | 3 |\n"
+        );
+        let html = table().to_html();
+        assert!(
+            html.contains("<td>/&lt;index&gt;</td><td class=\"num\">3</td>"),
+            "{html}"
+        );
+        let text = table().left_aligned().to_text();
+        assert!(text.contains("/<index>  3\n"), "{text}");
+    }
+}
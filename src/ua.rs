@@ -0,0 +1,307 @@
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+/// The ruleset in `assets/ua_regexes.yaml`.
+pub const BUNDLED_RULES: &str = include_str!("../assets/ua_regexes.yaml");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Desktop,
+    Mobile,
+    Tablet,
+    Bot,
+    Other,
+}
+
+impl AsRef<str> for DeviceType {
+    fn as_ref(&self) -> &str {
+        match self {
+            DeviceType::Desktop => "desktop",
+            DeviceType::Mobile => "mobile",
+            DeviceType::Tablet => "tablet",
+            DeviceType::Bot => "bot",
+            DeviceType::Other => "other",
+        }
+    }
+}
+crate::arrow_dictionary!(DeviceType);
+
+/// A browser or OS family with its version, e.g. `Chrome` `38` `0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Family {
+    pub family: String,
+    pub major: Option<String>,
+    pub minor: Option<String>,
+}
+
+impl Default for Family {
+    fn default() -> Self {
+        Self {
+            family: "Other".to_string(),
+            major: None,
+            minor: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAgent {
+    pub browser: Family,
+    pub os: Family,
+    /// uap's device family, `Spider` for automated clients.
+    pub device: String,
+    pub device_type: DeviceType,
+    pub is_bot: bool,
+}
+
+#[derive(Deserialize)]
+struct Rules {
+    user_agent_parsers: Vec<BrowserRule>,
+    os_parsers: Vec<OsRule>,
+    device_parsers: Vec<DeviceRule>,
+}
+
+#[derive(Deserialize)]
+struct BrowserRule {
+    regex: String,
+    family_replacement: Option<String>,
+    v1_replacement: Option<String>,
+    v2_replacement: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OsRule {
+    regex: String,
+    os_replacement: Option<String>,
+    os_v1_replacement: Option<String>,
+    os_v2_replacement: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeviceRule {
+    regex: String,
+    /// `i` for a case-insensitive match.
+    regex_flag: Option<String>,
+    device_replacement: Option<String>,
+    device_type: Option<String>,
+}
+
+struct Matcher {
+    regex: Regex,
+    replacements: [Option<String>; 3],
+}
+
+impl Matcher {
+    fn new(regex: &str, case_insensitive: bool, replacements: [Option<String>; 3]) -> Result<Self> {
+        let regex = if case_insensitive {
+            Regex::new(&format!("(?i){regex}"))?
+        } else {
+            Regex::new(regex)?
+        };
+        Ok(Self {
+            regex,
+            replacements,
+        })
+    }
+
+    /// Fills in each of the three parts from its replacement, or from capture group 1..=3.
+    fn apply(&self, ua: &str) -> Option<[Option<String>; 3]> {
+        let caps = self.regex.captures(ua)?;
+        let part = |i: usize| match &self.replacements[i] {
+            Some(r) => Some(expand(r, &caps)),
+            None => caps.get(i + 1).map(|m| m.as_str().to_string()),
+        };
+        Some([part(0), part(1), part(2)].map(|p| p.filter(|p| !p.is_empty())))
+    }
+}
+
+/// Replaces `$1`..`$9` with capture groups, unmatched groups expanding to nothing.
+fn expand(replacement: &str, caps: &Captures) -> String {
+    let mut out = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().and_then(|d| d.to_digit(10))) {
+            ('$', Some(group)) => {
+                chars.next();
+                out.push_str(caps.get(group as usize).map_or("", |m| m.as_str()));
+            }
+            _ => out.push(c),
+        }
+    }
+    out.trim().to_string()
+}
+
+/// A user-agent parser driven by a uap-core style `regexes.yaml`.
+pub struct UaParser {
+    browsers: Vec<Matcher>,
+    oses: Vec<Matcher>,
+    devices: Vec<(Matcher, DeviceType)>,
+}
+
+impl UaParser {
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let rules: Rules = serde_yaml::from_str(yaml)?;
+        let browsers = rules
+            .user_agent_parsers
+            .into_iter()
+            .map(|r| {
+                let replacements = [r.family_replacement, r.v1_replacement, r.v2_replacement];
+                Matcher::new(&r.regex, false, replacements)
+            })
+            .collect::<Result<_>>()?;
+        let oses = rules
+            .os_parsers
+            .into_iter()
+            .map(|r| {
+                let replacements = [r.os_replacement, r.os_v1_replacement, r.os_v2_replacement];
+                Matcher::new(&r.regex, false, replacements)
+            })
+            .collect::<Result<_>>()?;
+        let devices = rules
+            .device_parsers
+            .into_iter()
+            .map(|r| {
+                let device_type = match r.device_type.as_deref() {
+                    Some("desktop") => DeviceType::Desktop,
+                    Some("mobile") => DeviceType::Mobile,
+                    Some("tablet") => DeviceType::Tablet,
+                    Some("bot") => DeviceType::Bot,
+                    Some(other) => anyhow::bail!("unknown device_type {other:?}"),
+                    None => DeviceType::Other,
+                };
+                let case_insensitive = r.regex_flag.as_deref() == Some("i");
+                let matcher = Matcher::new(
+                    &r.regex,
+                    case_insensitive,
+                    [r.device_replacement, None, None],
+                )?;
+                Ok((matcher, device_type))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            browsers,
+            oses,
+            devices,
+        })
+    }
+
+    /// The parser for [`BUNDLED_RULES`], compiled on first use.
+    pub fn bundled() -> &'static Self {
+        static PARSER: OnceLock<UaParser> = OnceLock::new();
+        PARSER.get_or_init(|| Self::from_yaml(BUNDLED_RULES).expect("bundled rules are valid"))
+    }
+
+    pub fn parse(&self, ua: &str) -> UserAgent {
+        let family = |matchers: &[Matcher]| {
+            matchers
+                .iter()
+                .find_map(|m| m.apply(ua))
+                .map(|[family, major, minor]| Family {
+                    family: family.unwrap_or_else(|| "Other".to_string()),
+                    major,
+                    minor,
+                })
+                .unwrap_or_default()
+        };
+        let (device, device_type) = self
+            .devices
+            .iter()
+            .find_map(|(m, ty)| Some((m.apply(ua)?, *ty)))
+            .map(|([device, ..], ty)| (device.unwrap_or_else(|| "Other".to_string()), ty))
+            .unwrap_or(("Other".to_string(), DeviceType::Other));
+        UserAgent {
+            browser: family(&self.browsers),
+            os: family(&self.oses),
+            is_bot: device_type == DeviceType::Bot || device == "Spider",
+            device,
+            device_type,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(ua: &str) -> UserAgent {
+        UaParser::bundled().parse(ua)
+    }
+
+    #[test]
+    fn test_package_managers() {
+        let ua = parse("Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)");
+        assert_eq!(ua.browser.family, "APT");
+        assert_eq!(ua.browser.major.as_deref(), Some("1"));
+        assert_eq!(ua.browser.minor.as_deref(), Some("3"));
+        assert_eq!(ua.os.family, "Ubuntu");
+        assert!(ua.is_bot);
+        assert_eq!(ua.device_type, DeviceType::Bot);
+
+        let ua = parse(
+            "Chef Client/11.6.2 (ruby-1.9.3-p448; ohai-6.18.0; x86_64-linux; +http://opscode.com)",
+        );
+        assert_eq!(ua.browser.family, "Chef Client");
+        assert_eq!(ua.browser.major.as_deref(), Some("11"));
+        assert_eq!(ua.os.family, "Linux");
+        assert!(ua.is_bot);
+    }
+
+    #[test]
+    fn test_browsers() {
+        let ua = parse("Mozilla/5.0 (Windows NT 5.1) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/38.0.2125.104 Safari/537.36");
+        assert_eq!(ua.browser.family, "Chrome");
+        assert_eq!(ua.browser.major.as_deref(), Some("38"));
+        assert_eq!(ua.os.family, "Windows");
+        assert_eq!(ua.os.major.as_deref(), Some("XP"));
+        assert_eq!(ua.device_type, DeviceType::Desktop);
+        assert!(!ua.is_bot);
+
+        let ua = parse("Mozilla/5.0 (iPhone; CPU iPhone OS 12_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/12.0 Mobile/15E148 Safari/604.1");
+        assert_eq!(ua.browser.family, "Mobile Safari");
+        assert_eq!(ua.os.family, "iOS");
+        assert_eq!(ua.os.minor.as_deref(), Some("1"));
+        assert_eq!(ua.device, "iPhone");
+        assert_eq!(ua.device_type, DeviceType::Mobile);
+
+        let ua = parse("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)");
+        assert_eq!(ua.browser.family, "Googlebot");
+        assert!(ua.is_bot);
+    }
+
+    #[test]
+    fn test_unknown() {
+        let ua = parse("-");
+        assert_eq!(ua.browser, Family::default());
+        assert_eq!(ua.device, "Other");
+        assert_eq!(ua.device_type, DeviceType::Other);
+        assert!(!ua.is_bot);
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        let parser = UaParser::from_yaml(
+            r#"
+user_agent_parsers:
+  - regex: '(Acme)Fetch/(\d+)'
+    family_replacement: '$1 Fetcher'
+os_parsers: []
+device_parsers:
+  - regex: 'acme'
+    regex_flag: 'i'
+    device_replacement: 'Spider'
+"#,
+        )
+        .unwrap();
+        let ua = parser.parse("AcmeFetch/3");
+        assert_eq!(ua.browser.family, "Acme Fetcher");
+        assert_eq!(ua.browser.major.as_deref(), Some("3"));
+        assert!(ua.is_bot);
+        assert!(UaParser::from_yaml(
+            "user_agent_parsers: [{regex: '('}]\nos_parsers: []\ndevice_parsers: []"
+        )
+        .is_err());
+    }
+}
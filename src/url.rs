@@ -0,0 +1,180 @@
+use std::borrow::Cow;
+
+use percent_encoding::percent_decode_str;
+use winnow::{
+    ascii::alpha1,
+    combinator::{opt, preceded, rest, separated},
+    token::take_till,
+    PResult, Parser,
+};
+
+/// Decoded query parameters in request order; a key may repeat.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams(pub Vec<(String, String)>);
+
+impl QueryParams {
+    /// The first value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A request target, e.g. `/downloads/product_1?id=3`, split into its parts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestTarget {
+    /// Set for absolute-form targets sent to proxies, e.g. `http://example.com/`.
+    pub host: Option<String>,
+    /// Percent-decoded path.
+    pub path: String,
+    pub query: QueryParams,
+    /// The path with identifier segments replaced by `:id`, see [`normalize_route`].
+    pub route: String,
+}
+
+impl RequestTarget {
+    /// Parses any target; there is no invalid input, at worst everything ends up in `path`.
+    pub fn parse(target: &str) -> Self {
+        parse_request_target
+            .parse(target)
+            .unwrap_or_else(|_| RequestTarget {
+                host: None,
+                path: target.to_string(),
+                query: QueryParams::default(),
+                route: normalize_route(target),
+            })
+    }
+}
+
+pub fn parse_request_target(s: &mut &str) -> PResult<RequestTarget> {
+    let host = opt(parse_authority).parse_next(s)?;
+    let path = take_till(0.., ['?', '#']).parse_next(s)?;
+    let query = opt(preceded('?', parse_query)).parse_next(s)?;
+    // fragments are not sent by browsers, but scanners do
+    let _ = opt(preceded('#', rest)).parse_next(s)?;
+
+    let path = match decode(path) {
+        p if p.is_empty() && host.is_some() => "/".to_string(),
+        p => p.into_owned(),
+    };
+    let route = normalize_route(&path);
+    Ok(RequestTarget {
+        host,
+        path,
+        query: query.unwrap_or_default(),
+        route,
+    })
+}
+
+//parse scheme://authority
+fn parse_authority(s: &mut &str) -> PResult<String> {
+    let authority = preceded((alpha1, "://"), take_till(0.., ['/', '?', '#'])).parse_next(s)?;
+    Ok(authority.to_string())
+}
+
+pub fn parse_query(s: &mut &str) -> PResult<QueryParams> {
+    let params: Vec<Option<(String, String)>> =
+        separated(0.., parse_query_param, '&').parse_next(s)?;
+    Ok(QueryParams(params.into_iter().flatten().collect()))
+}
+
+//parse key=value, key or an empty parameter between `&&`
+fn parse_query_param(s: &mut &str) -> PResult<Option<(String, String)>> {
+    let key = take_till(0.., ['=', '&', '#']).parse_next(s)?;
+    let value = opt(preceded('=', take_till(0.., ['&', '#']))).parse_next(s)?;
+    if key.is_empty() && value.is_none() {
+        return Ok(None);
+    }
+    let value = value.map(decode_form).unwrap_or_default();
+    Ok(Some((decode_form(key), value)))
+}
+
+fn decode(s: &str) -> Cow<'_, str> {
+    percent_decode_str(s).decode_utf8_lossy()
+}
+
+/// Query strings encode spaces as `+`.
+fn decode_form(s: &str) -> String {
+    decode(&s.replace('+', " ")).into_owned()
+}
+
+/// Replaces path segments that identify a resource with `:id`, so that `/users/42/posts` and
+/// `/users/7/posts` group together: numbers, UUIDs and long hex strings.
+pub fn normalize_route(path: &str) -> String {
+    path.split('/')
+        .map(|segment| if is_id(segment) { ":id" } else { segment })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_id(segment: &str) -> bool {
+    let hex = |s: &str| s.chars().all(|c| c.is_ascii_hexdigit());
+    let numeric = !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit());
+    let uuid =
+        segment.split('-').map(str::len).eq([8, 4, 4, 4, 12]) && hex(&segment.replace('-', ""));
+    let digest = segment.len() >= 16 && hex(segment) && segment.chars().any(|c| c.is_ascii_digit());
+    numeric || uuid || digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request_target() {
+        let target = RequestTarget::parse("/search%20it/?q=caf%C3%A9+au+lait&page=2&&flag&=x#top");
+        assert_eq!(target.host, None);
+        assert_eq!(target.path, "/search it/");
+        assert_eq!(
+            target.query.0,
+            vec![
+                ("q".to_string(), "café au lait".to_string()),
+                ("page".to_string(), "2".to_string()),
+                ("flag".to_string(), "".to_string()),
+                ("".to_string(), "x".to_string()),
+            ]
+        );
+        assert_eq!(target.query.get("page"), Some("2"));
+    }
+
+    #[test]
+    fn test_parse_absolute_and_odd_targets() {
+        let target = RequestTarget::parse("http://example.com:8080?a=1");
+        assert_eq!(target.host.as_deref(), Some("example.com:8080"));
+        assert_eq!(target.path, "/");
+        assert_eq!(target.query.get("a"), Some("1"));
+
+        assert_eq!(RequestTarget::parse("*").path, "*");
+        assert_eq!(
+            RequestTarget::parse("example.com:443").path,
+            "example.com:443"
+        );
+        assert!(RequestTarget::parse("/a?").query.is_empty());
+        assert_eq!(RequestTarget::parse("/%FF").path, "/\u{FFFD}");
+    }
+
+    #[test]
+    fn test_normalize_route() {
+        assert_eq!(normalize_route("/users/42/posts/7"), "/users/:id/posts/:id");
+        assert_eq!(
+            normalize_route("/orders/123e4567-e89b-12d3-a456-426614174000"),
+            "/orders/:id"
+        );
+        assert_eq!(normalize_route("/blob/0123456789abcdef0123"), "/blob/:id");
+        assert_eq!(
+            normalize_route("/downloads/product_1"),
+            "/downloads/product_1"
+        );
+        assert_eq!(normalize_route("/v2/deadbeef"), "/v2/deadbeef");
+    }
+}